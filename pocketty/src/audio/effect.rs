@@ -1,19 +1,36 @@
+use serde::{Deserialize, Serialize};
 use super::frame::StereoFrame;
 
 // At some point I'd like to split this effects bit into a folder structure; 
 // the latter half of this event is going to be spent just making cool effects
 // so it should be easy to add them.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EffectSpec {
     Bitcrusher { levels: u32 },
     Distortion { drive: f32 },
+    Compressor { threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32 },
+    LowPass { cutoff_hz: f32, resonance: f32 },
+    HighPass { cutoff_hz: f32, resonance: f32 },
+    Reverb { room_size: f32, damping: f32, mix: f32 },
 }
 
 impl EffectSpec {
-    pub fn to_effect(&self) -> Box<dyn Effect> {
+    pub fn to_effect(&self, sample_rate: f32) -> Box<dyn Effect> {
         match self {
             EffectSpec::Bitcrusher { levels } => Box::new(Bitcrusher::new(*levels)),
             EffectSpec::Distortion { drive } => Box::new(Distortion::new(*drive)),
+            EffectSpec::Compressor { threshold_db, ratio, attack_ms, release_ms } => Box::new(
+                Compressor::new(*threshold_db, *ratio, *attack_ms, *release_ms, sample_rate),
+            ),
+            EffectSpec::LowPass { cutoff_hz, resonance } => {
+                Box::new(StateVariableFilter::new(FilterMode::LowPass, *cutoff_hz, *resonance, sample_rate))
+            }
+            EffectSpec::HighPass { cutoff_hz, resonance } => {
+                Box::new(StateVariableFilter::new(FilterMode::HighPass, *cutoff_hz, *resonance, sample_rate))
+            }
+            EffectSpec::Reverb { room_size, damping, mix } => {
+                Box::new(Reverb::new(*room_size, *damping, *mix, sample_rate))
+            }
         }
     }
 
@@ -21,14 +38,43 @@ impl EffectSpec {
         match self {
             EffectSpec::Bitcrusher { levels } => format!("Bitcrush({})", levels),
             EffectSpec::Distortion { drive } => format!("Distortion({})", drive),
+            EffectSpec::Compressor { threshold_db, ratio, .. } => {
+                format!("Compressor({}dB {}:1)", threshold_db, ratio)
+            }
+            EffectSpec::LowPass { cutoff_hz, resonance } => format!("LowPass({:.0}Hz {:.2})", cutoff_hz, resonance),
+            EffectSpec::HighPass { cutoff_hz, resonance } => format!("HighPass({:.0}Hz {:.2})", cutoff_hz, resonance),
+            EffectSpec::Reverb { room_size, mix, .. } => format!("Reverb({:.2} {:.0}%)", room_size, mix * 100.0),
         }
     }
 } 
 
 pub trait Effect: Send {
     fn process(&mut self, buf: &mut [StereoFrame]);
+
+    /// How many frames of output the effect keeps making after its input goes silent.
+    /// The engine keeps a finished voice's chain running this long so tails aren't cut.
+    fn tail_frames(&self) -> usize {
+        0
+    }
+
+    /// Retune a live low-pass. Returns false for effects that aren't one, so the
+    /// engine can tell whether the chain needs a filter added.
+    fn set_filter(&mut self, _cutoff_hz: f32, _resonance: f32) -> bool {
+        false
+    }
 }
 
+// Parameter ranges every effect is clamped into on construction. Below 2 levels
+// the crusher's step size divides by zero and the whole mix turns to NaN.
+const CRUSH_LEVELS: (u32, u32) = (2, 65536);
+const DRIVE_RANGE: (f32, f32) = (0.0, 1.0);
+const THRESHOLD_DB_RANGE: (f32, f32) = (-60.0, 0.0);
+const RATIO_RANGE: (f32, f32) = (1.0, 20.0);
+const ENV_MS_RANGE: (f32, f32) = (0.1, 2000.0);
+const CUTOFF_HZ_RANGE: (f32, f32) = (20.0, 20000.0);
+const MAX_RESONANCE_Q: f32 = 6.0; // Q at resonance 1.0: a clear peak that stays short of self-oscillation
+const FILTER_GLIDE: f32 = 0.25; // share of the way to a new setting covered each block
+
 //bitcrusher
 pub struct Bitcrusher {
     levels: f32,
@@ -37,7 +83,7 @@ pub struct Bitcrusher {
 impl Bitcrusher {
     pub fn new(levels: u32) -> Self {
         Self {
-            levels: (levels.max(2).min(65536)) as f32,
+            levels: levels.clamp(CRUSH_LEVELS.0, CRUSH_LEVELS.1) as f32,
         }
     }
 }
@@ -45,6 +91,9 @@ impl Bitcrusher {
 impl Effect for Bitcrusher {
     fn process(&mut self, buf: &mut [StereoFrame]) {
         let scale = (self.levels - 1.0) * 0.5;
+        if scale <= 0.0 {
+            return; // can't happen through new(), but never divide by zero on the audio thread
+        }
         let inv = 1.0 / scale;
         for f in buf.iter_mut() {
             f.left = (f.left.clamp(-1.0, 1.0) * scale).round() * inv;
@@ -60,8 +109,10 @@ pub struct Distortion {
 
 impl Distortion {
     pub fn new(drive: f32) -> Self {
-        Self { 
-            drive: drive.clamp(0.0, 1.0) as f32,
+        // NaN survives clamp(), so catch it explicitly
+        let drive = if drive.is_finite() { drive } else { DRIVE_RANGE.0 };
+        Self {
+            drive: drive.clamp(DRIVE_RANGE.0, DRIVE_RANGE.1),
         }
     }
 }
@@ -75,3 +126,356 @@ impl Effect for Distortion {
         }
     }
 }
+
+//compressor
+// Feed-forward peak compressor. The envelope carries over between blocks, and makeup
+// gain brings back half of what a full-scale signal would lose.
+pub struct Compressor {
+    threshold_db: f32,
+    slope: f32, // 1 - 1/ratio: how much of the overshoot gets taken away
+    attack: f32, // one-pole coefficients per sample
+    release: f32,
+    makeup_db: f32,
+    env: f32,
+}
+
+impl Compressor {
+    pub fn new(threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, sample_rate: f32) -> Self {
+        let finite = |v: f32, fallback: f32| if v.is_finite() { v } else { fallback };
+        let threshold_db = finite(threshold_db, 0.0).clamp(THRESHOLD_DB_RANGE.0, THRESHOLD_DB_RANGE.1);
+        let ratio = finite(ratio, 1.0).clamp(RATIO_RANGE.0, RATIO_RANGE.1);
+        let coeff = |ms: f32| {
+            let ms = finite(ms, ENV_MS_RANGE.0).clamp(ENV_MS_RANGE.0, ENV_MS_RANGE.1);
+            (-1.0 / (ms * 0.001 * sample_rate.max(1.0))).exp()
+        };
+        let slope = 1.0 - 1.0 / ratio;
+        Self {
+            threshold_db,
+            slope,
+            attack: coeff(attack_ms),
+            release: coeff(release_ms),
+            makeup_db: -threshold_db * slope * 0.5,
+            env: 0.0,
+        }
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, buf: &mut [StereoFrame]) {
+        for f in buf.iter_mut() {
+            let peak = f.left.abs().max(f.right.abs());
+            let coeff = if peak > self.env { self.attack } else { self.release };
+            self.env = coeff * self.env + (1.0 - coeff) * peak;
+
+            let env_db = 20.0 * self.env.max(1e-6).log10();
+            let reduction_db = (env_db - self.threshold_db).max(0.0) * self.slope;
+            let gain = 10.0_f32.powf((self.makeup_db - reduction_db) / 20.0);
+            f.left *= gain;
+            f.right *= gain;
+        }
+    }
+}
+
+//state variable filter
+// Trapezoidal (zero-delay feedback) SVF, after Andrew Simper's write-up: stays stable
+// and in tune all the way up to Nyquist, unlike the classic Chamberlin form. One core
+// computes every response; the mode just picks which output we keep.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+}
+
+pub struct StateVariableFilter {
+    mode: FilterMode,
+    sample_rate: f32,
+    cutoff_hz: f32,
+    resonance: f32,
+    target: Option<(f32, f32)>, // (cutoff, resonance) we're gliding to
+    a1: f32,
+    a2: f32,
+    a3: f32,
+    k: f32, // damping, 1/Q
+    ic1: [f32; 2], // integrator state per channel
+    ic2: [f32; 2],
+}
+
+impl StateVariableFilter {
+    /// `resonance` 0.0-1.0 runs from a flat Q of 0.5 up to MAX_RESONANCE_Q.
+    pub fn new(mode: FilterMode, cutoff_hz: f32, resonance: f32, sample_rate: f32) -> Self {
+        let sample_rate = if sample_rate.is_finite() { sample_rate.max(1.0) } else { 44100.0 };
+        let mut filter = Self {
+            mode,
+            sample_rate,
+            cutoff_hz: 0.0,
+            resonance: 0.0,
+            target: None,
+            a1: 0.0,
+            a2: 0.0,
+            a3: 0.0,
+            k: 0.0,
+            ic1: [0.0; 2],
+            ic2: [0.0; 2],
+        };
+        (filter.cutoff_hz, filter.resonance) = filter.sanitize(cutoff_hz, resonance);
+        filter.update_coefficients();
+        filter
+    }
+
+    fn sanitize(&self, cutoff_hz: f32, resonance: f32) -> (f32, f32) {
+        let cutoff_hz = if cutoff_hz.is_finite() { cutoff_hz } else { CUTOFF_HZ_RANGE.1 };
+        // keep clear of Nyquist, where tan() blows up
+        let cutoff_hz = cutoff_hz.clamp(CUTOFF_HZ_RANGE.0, CUTOFF_HZ_RANGE.1).min(self.sample_rate * 0.49);
+        let resonance = if resonance.is_finite() { resonance.clamp(0.0, 1.0) } else { 0.0 };
+        (cutoff_hz, resonance)
+    }
+
+    fn update_coefficients(&mut self) {
+        let q = 0.5 * (2.0 * MAX_RESONANCE_Q).powf(self.resonance);
+        let g = (std::f32::consts::PI * self.cutoff_hz / self.sample_rate).tan();
+        self.k = 1.0 / q;
+        self.a1 = 1.0 / (1.0 + g * (g + self.k));
+        self.a2 = g * self.a1;
+        self.a3 = g * self.a2;
+    }
+
+    // Step toward the target once per block. The cutoff moves in octaves so a sweep
+    // sounds even across the range; jumping straight there would click.
+    fn glide(&mut self) {
+        let Some((cutoff_hz, resonance)) = self.target else { return; };
+        let octaves = (cutoff_hz / self.cutoff_hz).log2();
+        if octaves.abs() < 0.01 && (resonance - self.resonance).abs() < 0.005 {
+            self.cutoff_hz = cutoff_hz;
+            self.resonance = resonance;
+            self.target = None;
+        } else {
+            self.cutoff_hz *= 2.0_f32.powf(octaves * FILTER_GLIDE);
+            self.resonance += (resonance - self.resonance) * FILTER_GLIDE;
+        }
+        self.update_coefficients();
+    }
+
+    // one sample through one channel
+    #[inline]
+    fn tick(&mut self, ch: usize, v0: f32) -> f32 {
+        let v3 = v0 - self.ic2[ch];
+        let v1 = self.a1 * self.ic1[ch] + self.a2 * v3; // band
+        let v2 = self.ic2[ch] + self.a2 * self.ic1[ch] + self.a3 * v3; // low
+        self.ic1[ch] = 2.0 * v1 - self.ic1[ch];
+        self.ic2[ch] = 2.0 * v2 - self.ic2[ch];
+        match self.mode {
+            FilterMode::LowPass => v2,
+            FilterMode::HighPass => v0 - self.k * v1 - v2,
+        }
+    }
+}
+
+impl Effect for StateVariableFilter {
+    fn process(&mut self, buf: &mut [StereoFrame]) {
+        self.glide();
+        for f in buf.iter_mut() {
+            f.left = self.tick(0, f.left);
+            f.right = self.tick(1, f.right);
+        }
+    }
+
+    fn set_filter(&mut self, cutoff_hz: f32, resonance: f32) -> bool {
+        if self.mode != FilterMode::LowPass {
+            return false; // fx high-pass keeps its fixed corner
+        }
+        self.target = Some(self.sanitize(cutoff_hz, resonance));
+        true
+    }
+}
+
+//reverb
+// Freeverb: eight damped feedback combs in parallel into four allpasses in series, per
+// channel. The right channel's delays run a little longer, which is where the width
+// comes from. Delay lengths are the classic 44.1 kHz tunings, scaled to the rate.
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const REVERB_INPUT_GAIN: f32 = 0.015; // the combs sum hot, so feed them quietly
+
+struct Comb {
+    buf: Vec<f32>,
+    pos: usize,
+    store: f32, // damping low-pass state
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self { buf: vec![0.0; len.max(1)], pos: 0, store: 0.0 }
+    }
+
+    #[inline]
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let out = self.buf[self.pos];
+        self.store = out * (1.0 - damp) + self.store * damp;
+        self.buf[self.pos] = input + self.store * feedback;
+        self.pos = (self.pos + 1) % self.buf.len();
+        out
+    }
+}
+
+struct Allpass {
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self { buf: vec![0.0; len.max(1)], pos: 0 }
+    }
+
+    #[inline]
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buf[self.pos];
+        self.buf[self.pos] = input + delayed * 0.5;
+        self.pos = (self.pos + 1) % self.buf.len();
+        delayed - input
+    }
+}
+
+pub struct Reverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    feedback: f32,
+    damp: f32,
+    wet: f32,
+    dry: f32,
+    tail: usize,
+}
+
+impl Reverb {
+    /// `room_size`, `damping` and `mix` are all 0.0-1.0.
+    pub fn new(room_size: f32, damping: f32, mix: f32, sample_rate: f32) -> Self {
+        let unit = |v: f32| if v.is_finite() { v.clamp(0.0, 1.0) } else { 0.0 };
+        let scale = if sample_rate.is_finite() { sample_rate.max(1.0) / 44100.0 } else { 1.0 };
+        let len = |n: usize| (n as f32 * scale) as usize;
+
+        let feedback = unit(room_size) * 0.28 + 0.7;
+        let mix = unit(mix);
+        let longest = len(COMB_TUNING[7] + STEREO_SPREAD);
+        // the combs ring down 60 dB after this many trips round the longest one
+        let trips = (0.001_f32.ln() / feedback.ln()).ceil() as usize;
+        Self {
+            combs: [0, STEREO_SPREAD].map(|spread| COMB_TUNING.iter().map(|&n| Comb::new(len(n + spread))).collect()),
+            allpasses: [0, STEREO_SPREAD].map(|spread| ALLPASS_TUNING.iter().map(|&n| Allpass::new(len(n + spread))).collect()),
+            feedback,
+            damp: unit(damping) * 0.4,
+            wet: mix * 3.0, // freeverb's wet scaling
+            dry: 1.0 - mix,
+            tail: trips * longest,
+        }
+    }
+
+    #[inline]
+    fn channel(&mut self, ch: usize, input: f32) -> f32 {
+        let (feedback, damp) = (self.feedback, self.damp);
+        let mut out: f32 = self.combs[ch].iter_mut().map(|c| c.process(input * REVERB_INPUT_GAIN, feedback, damp)).sum();
+        for ap in &mut self.allpasses[ch] {
+            out = ap.process(out);
+        }
+        out
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, buf: &mut [StereoFrame]) {
+        for f in buf.iter_mut() {
+            // both sides hear a mono sum, like freeverb; the different delays decorrelate them
+            let input = (f.left + f.right) * 0.5;
+            let (wl, wr) = (self.channel(0, input), self.channel(1, input));
+            f.left = f.left * self.dry + wl * self.wet;
+            f.right = f.right * self.dry + wr * self.wet;
+        }
+    }
+
+    fn tail_frames(&self) -> usize {
+        self.tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    fn sine(hz: f32, frames: usize) -> Vec<StereoFrame> {
+        (0..frames)
+            .map(|i| {
+                let s = (std::f32::consts::TAU * hz * i as f32 / RATE).sin();
+                StereoFrame { left: s, right: s }
+            })
+            .collect()
+    }
+
+    // skips the first half so the filter has settled
+    fn rms(buf: &[StereoFrame]) -> f32 {
+        let tail = &buf[buf.len() / 2..];
+        (tail.iter().map(|f| f.left * f.left).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn low_pass_cuts_a_tone_above_the_cutoff() {
+        let mut buf = sine(1000.0, 9600);
+        let dry = rms(&buf);
+        EffectSpec::LowPass { cutoff_hz: 200.0, resonance: 0.0 }.to_effect(RATE).process(&mut buf);
+        // 2.3 octaves up a 12 dB/oct slope: well under a tenth of the level
+        assert!(rms(&buf) < dry * 0.1, "{} vs {}", rms(&buf), dry);
+    }
+
+    #[test]
+    fn crusher_stays_finite_at_degenerate_levels() {
+        let crushers = [0, 1, u32::MAX].map(Bitcrusher::new);
+        // and the ones new() can't build any more
+        let raw = [Bitcrusher { levels: 1.0 }, Bitcrusher { levels: 0.0 }];
+        for mut crusher in crushers.into_iter().chain(raw) {
+            let mut buf = sine(440.0, 256);
+            crusher.process(&mut buf);
+            assert!(buf.iter().all(|f| f.left.is_finite() && f.right.is_finite()), "levels {}", crusher.levels);
+        }
+    }
+
+    #[test]
+    fn compressor_gain_follows_the_envelope() {
+        // -20 dB threshold at 4:1: 15 dB off a 0 dB signal, 7.5 dB of that made back up
+        let mut comp = Compressor::new(-20.0, 4.0, 5.0, 100.0, RATE);
+        let level = |v: f32| vec![StereoFrame { left: v, right: v }; RATE as usize / 2];
+        let gain_db = |out: &StereoFrame, v: f32| 20.0 * (out.left / v).log10();
+
+        let mut loud = level(1.0);
+        comp.process(&mut loud);
+        assert!(gain_db(&loud[0], 1.0) > -1.0); // the attack hasn't caught it yet
+        assert!((gain_db(loud.last().unwrap(), 1.0) + 7.5).abs() < 0.2);
+
+        // a quiet stretch right after: held down at first, then released to just the makeup
+        let mut quiet = level(0.01);
+        comp.process(&mut quiet);
+        let gains: Vec<f32> = [0, 2400, 4800, 23999].iter().map(|&i| gain_db(&quiet[i], 0.01)).collect();
+        assert!(gains.windows(2).all(|w| w[0] < w[1]), "{:?}", gains);
+        assert!(gains[0] < 0.0);
+        assert!((gains[3] - 7.5).abs() < 0.2, "{:?}", gains);
+    }
+
+    #[test]
+    fn high_pass_cuts_dc_and_passes_a_high_tone() {
+        let high_pass = || EffectSpec::HighPass { cutoff_hz: 200.0, resonance: 0.0 }.to_effect(RATE);
+
+        let mut dc = vec![StereoFrame { left: 0.5, right: 0.5 }; 9600];
+        high_pass().process(&mut dc);
+        assert!(rms(&dc) < 0.005, "dc {}", rms(&dc));
+
+        let mut low = sine(30.0, 9600);
+        let dry = rms(&low);
+        high_pass().process(&mut low);
+        assert!(rms(&low) < dry * 0.1, "30 Hz {} vs {}", rms(&low), dry);
+
+        let mut tone = sine(5000.0, 9600);
+        let dry = rms(&tone);
+        high_pass().process(&mut tone);
+        assert!((rms(&tone) / dry - 1.0).abs() < 0.05, "5 kHz {} vs {}", rms(&tone), dry);
+    }
+}
@@ -1,19 +1,63 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 
 use crossbeam_channel::{Receiver, Sender};
 
 use crate::audio_api::AudioCommand;
-use super::effect::{Effect, EffectSpec};
+use crate::shared::{NUM_SLOTS, NUM_SOUNDS};
+use super::effect::{Effect, FilterMode, StateVariableFilter};
 use super::frame::StereoFrame;
 use super::sample_buffer::SampleBuffer;
 use super::voice::Voice;
 use super::SampleId;
 
 const TEMP_BUF_CAP: usize = 8192; // Sort of arbitrarily chosen, but chosen nonetheless
-const RECORD_PEAK_THRESHOLD: f32 = 0.02;
+pub const RECORD_PEAK_THRESHOLD: f32 = 0.02; // until the project sends its own
+const RECORD_THRESHOLD_RANGE: (f32, f32) = (0.001, 0.5);
+const NORMALIZE_PEAK: f32 = 0.891; // -1 dBFS
+const TRIM_SILENCE_THRESHOLD: f32 = 0.005; // ~-46 dBFS, well under the record threshold
 const PRE_ROLL_FRAMES: usize = 6615;
+const MONITOR_MAX_BACKLOG: usize = 1024; // frames; past this the oldest are dropped to keep latency down
+const DC_CUTOFF_HZ: f32 = 20.0;
+pub const MAX_VOICES: usize = 32; // until told otherwise; past this new hits steal the oldest voice
+const STEAL_FADE_FRAMES: usize = 256; // a stolen voice (fx tail and all) ramps out over this
+// per-sample meters; room for every slot plus the samples still fading out after a reload
+pub const METER_CHANNELS: usize = NUM_SLOTS * 2;
+
+// One-pole high-pass on the master bus: strips DC offset and subsonic rumble
+struct DcBlocker {
+    enabled: bool,
+    r: f32, // pole; closer to 1.0 = lower cutoff
+    x1: StereoFrame,
+    y1: StereoFrame,
+}
+
+impl DcBlocker {
+    fn new(sample_rate: f32, enabled: bool) -> Self {
+        Self {
+            enabled,
+            r: 1.0 - (2.0 * std::f32::consts::PI * DC_CUTOFF_HZ / sample_rate),
+            x1: StereoFrame::default(),
+            y1: StereoFrame::default(),
+        }
+    }
+
+    fn process(&mut self, buf: &mut [StereoFrame]) {
+        if !self.enabled {
+            return;
+        }
+        for f in buf.iter_mut() {
+            let y = StereoFrame {
+                left: f.left - self.x1.left + self.r * self.y1.left,
+                right: f.right - self.x1.right + self.r * self.y1.right,
+            };
+            self.x1 = *f;
+            self.y1 = y;
+            *f = y;
+        }
+    }
+}
 
 enum RecordingState {
     Idle,
@@ -73,40 +117,119 @@ impl PreRollRing {
 }
 
 
+// Sidechain gain on one sample's voices: snaps down on a hit, ramps back to 1.0
+struct DuckEnvelope {
+    target: SampleId,
+    gain: f32,
+    recover_per_frame: f32,
+}
+
 // An internal attachment between a voice and the params of our trigger call
 struct ActiveVoice {
     voice: Voice,
     sample_id: SampleId,
     effect_chain: Vec<Box<dyn Effect>>,
+    cue: bool,
+    tail_left: usize, // frames the chain keeps ringing once the voice itself is done (reverb)
+    stolen: Option<usize>, // frames left in the fade after another hit took this voice's place
+    choke_group: Option<u8>,
+}
+
+/// Meters the audio thread publishes for the UI. Atomics only, so
+/// reporting never blocks the callback.
+pub struct EngineMeters {
+    pub voices: AtomicUsize,      // voices still sounding after the last block
+    pub callback_load: AtomicU32, // percent of the block's real-time budget spent rendering
+    pub input_peak: AtomicU32,    // f32 bits; loudest input sample since the UI last took it
+    pub output_peak: AtomicU32,   // f32 bits; loudest sample of the final mix since the UI last took it
+    pub sample_peaks: Vec<SamplePeak>, // METER_CHANNELS of them, handed out as samples register
+}
+
+impl Default for EngineMeters {
+    fn default() -> Self {
+        Self {
+            voices: AtomicUsize::new(0),
+            callback_load: AtomicU32::new(0),
+            input_peak: AtomicU32::new(0),
+            output_peak: AtomicU32::new(0),
+            sample_peaks: (0..METER_CHANNELS).map(|_| SamplePeak::default()).collect(),
+        }
+    }
+}
+
+/// Output level of one registered sample, summed over all its voices.
+#[derive(Default)]
+pub struct SamplePeak {
+    pub id: AtomicU64,   // sample id + 1; 0 = channel unused
+    pub peak: AtomicU32, // f32 bits; loudest output sample since the UI last took it
 }
 
 pub struct CompletedRecording {
     pub sample_id: SampleId,
     pub buffer: SampleBuffer,
+    pub in_engine: bool, // false for a grab: it's handed over whole and registered from the main thread
 }
 
 pub struct Engine {
     samples: HashMap<SampleId, SampleBuffer>, // the sample buffers we've registered
+    unregistering: Vec<SampleId>, // freed once their voices finish fading
     active: Vec<ActiveVoice>,
+    max_voices: usize,
     temp_buf: Vec<StereoFrame>,
+    cue_buf: Vec<StereoFrame>,
+    cue_tx: Option<Sender<Vec<StereoFrame>>>, // headphone cue stream; cue voices join the mix without one
+    cue_free: Option<Receiver<Vec<StereoFrame>>>, // emptied blocks back from the cue stream, so sending never allocates
+    dc_blocker: DcBlocker,
+    ducks: Vec<DuckEnvelope>, // active sidechain envelopes, one per ducked sample
+    sample_rate: f32,
 
     // Recording
     recording: RecordingState,
+    record_threshold: f32, // input peak that starts an armed recording
+    normalize_recordings: bool,
+    trim_recordings: bool,
+    monitor: bool,
+    monitor_gain: f32,
+    monitor_buf: VecDeque<StereoFrame>, // input waiting to be mixed into the output
+    grab: Option<(SampleId, Vec<StereoFrame>)>, // master output capture; arrives reserved to the frames wanted
+    master_capture: Option<Vec<StereoFrame>>, // jam recording; arrives reserved to the cap so it never reallocates
+    master_tx: Option<Sender<SampleBuffer>>,
     input_rx: Option<Receiver<Vec<StereoFrame>>>,
     completed_tx: Option<Sender<CompletedRecording>>,
     capturing_flag: Arc<AtomicBool>, // shared with AudioHandle for UI feedback
+    meters: Option<Arc<EngineMeters>>, // shared with AudioHandle for the LCD readouts
+    meter_channels: HashMap<SampleId, usize>, // which sample_peaks entry each sample reports to
 }
 
 impl Engine {
     pub fn new(capturing_flag: Arc<AtomicBool>) -> Self {
         Self {
             samples: HashMap::new(),
-            active: Vec::new(),
+            unregistering: Vec::with_capacity(NUM_SOUNDS),
+            active: Vec::with_capacity(MAX_VOICES * 2),
+            max_voices: MAX_VOICES,
             temp_buf: vec![StereoFrame::default(); TEMP_BUF_CAP],
+            cue_buf: vec![StereoFrame::default(); TEMP_BUF_CAP],
+            cue_tx: None,
+            cue_free: None,
+            dc_blocker: DcBlocker::new(44100.0, true),
+            ducks: Vec::with_capacity(NUM_SOUNDS),
+            sample_rate: 44100.0,
             recording: RecordingState::Idle,
+            record_threshold: RECORD_PEAK_THRESHOLD,
+            normalize_recordings: false,
+            trim_recordings: false,
+            monitor: false,
+            monitor_gain: 1.0,
+            monitor_buf: VecDeque::with_capacity(MONITOR_MAX_BACKLOG + TEMP_BUF_CAP),
+            grab: None,
+            master_capture: None,
+            master_tx: None,
             input_rx: None,
             completed_tx: None,
             capturing_flag,
+            meters: None,
+            meter_channels: HashMap::with_capacity(METER_CHANNELS),
         }
     }
 
@@ -118,19 +241,77 @@ impl Engine {
         self.completed_tx = Some(tx);
     }
 
+    pub fn set_master_tx(&mut self, tx: Sender<SampleBuffer>) {
+        self.master_tx = Some(tx);
+    }
+
+    pub fn set_cue_tx(&mut self, tx: Sender<Vec<StereoFrame>>, free: Receiver<Vec<StereoFrame>>) {
+        self.cue_tx = Some(tx);
+        self.cue_free = Some(free);
+    }
+
+    /// Tune rate-dependent processing to the output device.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.dc_blocker = DcBlocker::new(sample_rate as f32, self.dc_blocker.enabled);
+        self.sample_rate = sample_rate as f32;
+    }
+
+    pub fn set_meters(&mut self, meters: Arc<EngineMeters>) {
+        self.meters = Some(meters);
+    }
+
+    // give a newly registered sample a free meter channel (if any are left)
+    fn assign_meter(&mut self, id: SampleId) {
+        let Some(meters) = &self.meters else { return; };
+        if self.meter_channels.contains_key(&id) {
+            return;
+        }
+        let free = (0..METER_CHANNELS).find(|c| !self.meter_channels.values().any(|v| v == c));
+        if let Some(channel) = free {
+            meters.sample_peaks[channel].peak.store(0, Ordering::Relaxed);
+            meters.sample_peaks[channel].id.store(id.0 + 1, Ordering::Relaxed);
+            self.meter_channels.insert(id, channel);
+        }
+    }
+
+    fn forget_sample(&mut self, id: SampleId) {
+        self.samples.remove(&id);
+        if let Some(channel) = self.meter_channels.remove(&id)
+            && let Some(meters) = &self.meters
+        {
+            meters.sample_peaks[channel].id.store(0, Ordering::Relaxed);
+        }
+    }
+
     pub fn handle_cmd(&mut self, cmd: AudioCommand) {
         match cmd {
             AudioCommand::RegisterSample { id, buffer } => {
                 self.samples.insert(id, buffer);
+                self.assign_meter(id);
+            }
+            AudioCommand::UnregisterSample { id } => {
+                // fade out anything still playing it; the buffer goes once they're done
+                let mut playing = false;
+                for active in self.active.iter_mut().filter(|a| a.sample_id == id) {
+                    active.voice.request_fade_out();
+                    playing = true;
+                }
+                if playing {
+                    self.unregistering.push(id);
+                } else {
+                    self.forget_sample(id);
+                }
             }
             AudioCommand::Trigger(params) => {
-                if !self.samples.contains_key(&params.sample_id) {
+                if !self.samples.contains_key(&params.sample_id)
+                    || self.unregistering.contains(&params.sample_id)
+                {
                     return;
                 }
                 let effect_chain: Vec<Box<dyn Effect>> = params
                     .effect_chain
                     .iter()
-                    .map(EffectSpec::to_effect)
+                    .map(|spec| spec.to_effect(self.sample_rate))
                     .collect();
                 let voice = Voice::new(
                     params.trim_start,
@@ -139,11 +320,36 @@ impl Engine {
                     params.gain,
                     params.reverse,
                     params.stutter_period_samples,
+                    params.hold,
+                    params.interp,
+                    params.envelope,
+                    params.loop_start.zip(params.loop_end),
+                    params.pan,
                 );
+                let tail_left = effect_chain.iter().map(|e| e.tail_frames()).max().unwrap_or(0);
+                // voice stealing: at the cap, the oldest voice still playing (a ringing fx
+                // tail counts) fades out to make room
+                let playing = self.active.iter().filter(|a| a.stolen.is_none()).count();
+                let excess = (playing + 1).saturating_sub(self.max_voices);
+                for active in self.active.iter_mut().filter(|a| a.stolen.is_none()).take(excess) {
+                    active.stolen = Some(STEAL_FADE_FRAMES);
+                }
+                // choke: a hit cuts whatever else in its group is still sounding (closed hat
+                // stops the open one). Faded, not cut, and fx tails ring on. Voices that haven't
+                // rendered yet came in with this hit (unison's twin) and stay.
+                if let Some(group) = params.choke_group {
+                    for active in self.active.iter_mut().filter(|a| a.choke_group == Some(group) && a.voice.has_started()) {
+                        active.voice.request_fade_out();
+                    }
+                }
                 self.active.push(ActiveVoice {
                     voice,
                     sample_id: params.sample_id,
                     effect_chain,
+                    cue: params.cue,
+                    tail_left,
+                    stolen: None,
+                    choke_group: params.choke_group,
                 });
             }
             AudioCommand::SetPlaybackPosition { sample_id, position } => { // scratch effect
@@ -153,9 +359,100 @@ impl Engine {
                     active.voice.set_pos(position);
                 }
             }
+            AudioCommand::SetVoiceFilter { sample_id, cutoff_hz, resonance } => {
+                if let Some(active) = self.active.iter_mut().rev()
+                    .find(|a| a.sample_id == sample_id && a.voice.active)
+                {
+                    let retuned = active.effect_chain.iter_mut()
+                        .any(|e| e.set_filter(cutoff_hz, resonance));
+                    // triggered wide open, so there's no filter yet: add one that starts open
+                    // and glides down like any other change
+                    if !retuned && cutoff_hz < 20000.0 {
+                        let mut filter = StateVariableFilter::new(FilterMode::LowPass, 20000.0, resonance, self.sample_rate);
+                        filter.set_filter(cutoff_hz, resonance);
+                        active.effect_chain.insert(0, Box::new(filter));
+                    }
+                }
+            }
+            AudioCommand::SetLoopRegion { sample_id, start, end } => {
+                if let Some(active) = self.active.iter_mut().rev()
+                    .find(|a| a.sample_id == sample_id && a.voice.active)
+                {
+                    active.voice.set_loop_region(start, end);
+                }
+            }
+            AudioCommand::ClearLoopRegion { sample_id } => {
+                for active in self.active.iter_mut().filter(|a| a.sample_id == sample_id) {
+                    active.voice.clear_loop_region();
+                }
+            }
+            AudioCommand::ReleaseVoice { sample_id } => {
+                for active in self.active.iter_mut().filter(|a| a.sample_id == sample_id) {
+                    active.voice.release_sustain();
+                }
+            }
             AudioCommand::StopAllVoices => {
+                // Fade rather than cut so stopping mid-sample doesn't click
                 for active in &mut self.active {
-                    active.voice.active = false;
+                    active.voice.request_fade_out();
+                }
+            }
+            AudioCommand::StopVoices { sample_id } => {
+                for active in &mut self.active {
+                    if active.sample_id == sample_id {
+                        active.voice.release();
+                    }
+                }
+            }
+            AudioCommand::Duck { target, amount, release_ms } => {
+                let release_frames = (release_ms.max(1.0) / 1000.0 * self.sample_rate).max(1.0);
+                let floor = 1.0 - amount;
+                match self.ducks.iter_mut().find(|d| d.target == target) {
+                    // retrigger mid-recovery: never jump back up, just restart the ramp
+                    Some(duck) => {
+                        duck.gain = duck.gain.min(floor);
+                        duck.recover_per_frame = amount / release_frames;
+                    }
+                    None => self.ducks.push(DuckEnvelope {
+                        target,
+                        gain: floor,
+                        recover_per_frame: amount / release_frames,
+                    }),
+                }
+            }
+            AudioCommand::SetDcFilter { enabled } => {
+                self.dc_blocker.enabled = enabled;
+            }
+            AudioCommand::SetRecordThreshold(threshold) => {
+                if threshold.is_finite() {
+                    self.record_threshold = threshold.clamp(RECORD_THRESHOLD_RANGE.0, RECORD_THRESHOLD_RANGE.1);
+                }
+            }
+            AudioCommand::SetNormalizeRecordings(enabled) => {
+                self.normalize_recordings = enabled;
+            }
+            AudioCommand::SetMaxVoices(max) => {
+                self.max_voices = max.max(1);
+            }
+            AudioCommand::SetTrimRecordings(enabled) => {
+                self.trim_recordings = enabled;
+            }
+            AudioCommand::StartMasterCapture { buffer } => {
+                // the middle only starts one at a time, so a second buffer never gets dropped here
+                if self.master_capture.is_none() {
+                    self.master_capture = Some(buffer);
+                }
+            }
+            AudioCommand::StopMasterCapture => self.finish_master_capture(),
+            AudioCommand::SetInputMonitor(enabled) => {
+                self.monitor = enabled;
+            }
+            AudioCommand::SetMonitorGain(gain) => {
+                self.monitor_gain = gain.clamp(0.0, 1.0);
+            }
+            AudioCommand::SyncStutter => {
+                for active in &mut self.active {
+                    active.voice.resync_stutter();
                 }
             }
             AudioCommand::StartRecording { sample_id } => {
@@ -165,24 +462,36 @@ impl Engine {
                     pre_roll: PreRollRing::new(PRE_ROLL_FRAMES),
                 };
             }
+            AudioCommand::GrabOutput { sample_id, buffer } => {
+                self.grab = Some((sample_id, buffer));
+            }
             AudioCommand::StopRecording => {
                 self.capturing_flag.store(false, Ordering::Relaxed);
                 // Finalise whatever we have and register the sample
                 match std::mem::replace(&mut self.recording, RecordingState::Idle) {
                     RecordingState::Capturing { sample_id, buffer } => {
-                        let buf = if buffer.is_empty() {
+                        let mut buf = if buffer.is_empty() {
                             SampleBuffer::from_frames(vec![StereoFrame::default()])
                         } else {
                             SampleBuffer::from_frames(buffer)
                         };
+                        // trim first so normalizing doesn't lift the tail over the threshold
+                        if self.trim_recordings {
+                            buf.trim_trailing_silence(TRIM_SILENCE_THRESHOLD);
+                        }
+                        if self.normalize_recordings {
+                            buf.normalize(NORMALIZE_PEAK);
+                        }
                         // Send a copy to the main thread for saving to disk
                         if let Some(tx) = &self.completed_tx {
                             let _ = tx.try_send(CompletedRecording {
                                 sample_id,
                                 buffer: buf.clone(),
+                                in_engine: true,
                             });
                         }
                         self.samples.insert(sample_id, buf);
+                        self.assign_meter(sample_id);
                     }
                     RecordingState::Armed { sample_id, .. } => {
                         // Never reached the threshold — register silence
@@ -190,6 +499,7 @@ impl Engine {
                             sample_id,
                             SampleBuffer::from_frames(vec![StereoFrame::default()]),
                         );
+                        self.assign_meter(sample_id);
                     }
                     RecordingState::Idle => {} // nothing to do
                 }
@@ -197,6 +507,14 @@ impl Engine {
         }
     }
 
+    fn finish_master_capture(&mut self) {
+        if let Some(frames) = self.master_capture.take()
+            && let Some(tx) = &self.master_tx
+        {
+            let _ = tx.try_send(SampleBuffer::from_frames(frames));
+        }
+    }
+
     pub fn drain_input(&mut self) {
         let rx = match &self.input_rx {
             Some(rx) => rx,
@@ -212,6 +530,26 @@ impl Engine {
             return;
         }
 
+        // input meter: keep the loudest sample until the UI takes it
+        if let Some(meters) = &self.meters {
+            let peak = chunks.iter()
+                .flatten()
+                .fold(0.0_f32, |m, f| m.max(f.left.abs()).max(f.right.abs()));
+            let _ = meters.input_peak.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                (peak > f32::from_bits(bits)).then_some(peak.to_bits())
+            });
+        }
+
+        // monitoring: queue what came in for render_block, only while there's something to record
+        if self.monitor && !matches!(self.recording, RecordingState::Idle) {
+            self.monitor_buf.extend(chunks.iter().flatten());
+            let excess = self.monitor_buf.len().saturating_sub(MONITOR_MAX_BACKLOG);
+            self.monitor_buf.drain(..excess);
+        } else {
+            self.monitor_buf.clear();
+        }
+
+        let threshold = self.record_threshold;
         match &mut self.recording {
             RecordingState::Idle => {}
             RecordingState::Armed { pre_roll, .. } => {
@@ -222,7 +560,7 @@ impl Engine {
                 'outer: for chunk in &chunks {
                     for (i, frame) in chunk.iter().enumerate() {
                         let level = frame.left.abs().max(frame.right.abs());
-                        if level > RECORD_PEAK_THRESHOLD {
+                        if level > threshold {
                             triggered = true;
                             trigger_offset = total_offset + i;
                             break 'outer;
@@ -280,9 +618,14 @@ impl Engine {
         for f in out.iter_mut() { // clear to zeros
             *f = StereoFrame::default();
         }
+        let cueing = self.cue_tx.is_some();
+        if cueing {
+            self.cue_buf.resize(n_frames, StereoFrame::default());
+            self.cue_buf.fill(StereoFrame::default());
+        }
 
         for active in &mut self.active { // for each active voice
-            if !active.voice.active {
+            if !active.voice.active && active.tail_left == 0 {
                 continue;
             }
             let Some(buffer) = self.samples.get(&active.sample_id) else { // get the sample buffer for this voice
@@ -291,16 +634,402 @@ impl Engine {
             for f in temp.iter_mut() { // clear the temp buffer to zeros
                 *f = StereoFrame::default();
             }
-            active.voice.render_into(buffer, temp); // render the voice into the temp buffer
+            if active.voice.active {
+                active.voice.render_into(buffer, temp); // render the voice into the temp buffer
+            } else {
+                // voice finished: feed the chain silence until its tail has rung out
+                active.tail_left = active.tail_left.saturating_sub(n_frames);
+            }
             for effect in &mut active.effect_chain { // plug in the temp through the effect chain
                 effect.process(temp);
             }
+            if let Some(duck) = self.ducks.iter().find(|d| d.target == active.sample_id) {
+                for (i, f) in temp.iter_mut().enumerate() {
+                    let g = (duck.gain + duck.recover_per_frame * i as f32).min(1.0);
+                    f.left *= g;
+                    f.right *= g;
+                }
+            }
+            if let Some(left) = active.stolen {
+                for (i, f) in temp.iter_mut().enumerate() {
+                    let g = left.saturating_sub(i) as f32 / STEAL_FADE_FRAMES as f32;
+                    f.left *= g;
+                    f.right *= g;
+                }
+                let left = left.saturating_sub(n_frames);
+                active.stolen = Some(left);
+                if left == 0 {
+                    active.voice.active = false;
+                    active.tail_left = 0;
+                }
+            }
+            if let Some(meters) = &self.meters
+                && let Some(&channel) = self.meter_channels.get(&active.sample_id)
+            {
+                let peak = temp.iter().fold(0.0f32, |p, f| p.max(f.left.abs()).max(f.right.abs()));
+                if peak.is_finite() {
+                    // non-negative floats order the same as their bits
+                    meters.sample_peaks[channel].peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+                }
+            }
+            // cue voices go to the headphones only, if there's a cue output to hear them on
+            let bus = if active.cue && cueing { &mut self.cue_buf[..] } else { &mut *out };
             for (i, f) in temp.iter().enumerate().take(n_frames) { // add the temp to the output
-                out[i].left += f.left;
-                out[i].right += f.right;
+                // a voice or effect gone non-finite gets dropped rather than poisoning the whole mix
+                if !(f.left.is_finite() && f.right.is_finite()) {
+                    continue;
+                }
+                bus[i].left += f.left;
+                bus[i].right += f.right;
+            }
+        }
+
+        self.dc_blocker.process(out);
+        if let Some((_, buffer)) = &mut self.grab {
+            let take = (buffer.capacity() - buffer.len()).min(n_frames);
+            buffer.extend_from_slice(&out[..take]);
+            if buffer.len() == buffer.capacity()
+                && let Some((sample_id, buffer)) = self.grab.take()
+                && let Some(tx) = &self.completed_tx
+            {
+                // no copy here: the main thread registers it back once it has saved it
+                let buffer = SampleBuffer::from_frames(buffer);
+                let _ = tx.try_send(CompletedRecording { sample_id, buffer, in_engine: false });
+            }
+        }
+        if let Some(jam) = &mut self.master_capture {
+            let take = (jam.capacity() - jam.len()).min(n_frames);
+            jam.extend_from_slice(&out[..take]);
+            if jam.len() == jam.capacity() {
+                self.finish_master_capture();
+            }
+        }
+        // input monitoring joins after the grab so it never ends up in a sound. Input
+        // chunks don't line up with output blocks; whatever hasn't arrived yet is silence.
+        if !self.monitor_buf.is_empty() {
+            let gain = self.monitor_gain;
+            for (f, m) in out.iter_mut().zip(self.monitor_buf.drain(..n_frames.min(self.monitor_buf.len()))) {
+                f.left += m.left * gain;
+                f.right += m.right * gain;
+            }
+        }
+        if let Some(meters) = &self.meters {
+            // metered last, so it's what actually reaches the speakers (monitoring included)
+            let peak = out.iter().fold(0.0f32, |p, f| p.max(f.left.abs()).max(f.right.abs()));
+            if peak.is_finite() {
+                meters.output_peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+            }
+        }
+        // no empty block to fill means the cue output has fallen behind: skip this one
+        // rather than allocate (it'd drop the oldest audio anyway)
+        if let Some(tx) = &self.cue_tx
+            && let Some(free) = &self.cue_free
+            && let Ok(mut block) = free.try_recv()
+        {
+            block.clear();
+            block.extend_from_slice(&self.cue_buf[..n_frames]);
+            let _ = tx.try_send(block);
+        }
+
+        for duck in &mut self.ducks {
+            duck.gain = (duck.gain + duck.recover_per_frame * n_frames as f32).min(1.0);
+        }
+        self.ducks.retain(|d| d.gain < 1.0);
+
+        self.active.retain(|a| a.voice.active || a.tail_left > 0); // remove voices that have finished playing (and ringing)
+        let mut i = 0;
+        while i < self.unregistering.len() {
+            let id = self.unregistering[i];
+            if self.active.iter().any(|a| a.sample_id == id) {
+                i += 1; // still fading
+            } else {
+                self.unregistering.swap_remove(i);
+                self.forget_sample(id);
             }
         }
+        if let Some(meters) = &self.meters {
+            meters.voices.store(self.active.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::next_sample_id;
+    use crate::audio::voice::{Envelope, Interpolation};
+    use crate::audio_api::TriggerParams;
 
-        self.active.retain(|a| a.voice.active); // remove voices that have finished playing
+    fn engine() -> Engine {
+        let mut engine = Engine::new(Arc::new(AtomicBool::new(false)));
+        engine.set_sample_rate(48000);
+        engine
+    }
+
+    // an engine whose input is fed by hand, `loud` frames over the record threshold
+    fn recording_engine(loud: usize) -> (Engine, SampleId) {
+        let mut engine = engine();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        engine.set_input_rx(rx);
+        let id = next_sample_id();
+        engine.handle_cmd(AudioCommand::StartRecording { sample_id: id });
+        tx.send(vec![StereoFrame { left: 0.5, right: 0.5 }; loud]).unwrap();
+        (engine, id)
+    }
+
+    // a second of DC, registered under a fresh id, and a plain hit on it
+    fn registered(engine: &mut Engine) -> SampleId {
+        let id = next_sample_id();
+        let buffer = SampleBuffer::from_frames(vec![StereoFrame { left: 0.5, right: 0.5 }; 48000]);
+        engine.handle_cmd(AudioCommand::RegisterSample { id, buffer });
+        id
+    }
+
+    fn hit(sample_id: SampleId) -> AudioCommand {
+        AudioCommand::Trigger(TriggerParams {
+            sample_id,
+            trim_start: 0,
+            length: 48000,
+            gain: 1.0,
+            pitch: 1.0,
+            effect_chain: vec![],
+            reverse: false,
+            stutter_period_samples: None,
+            hold: false,
+            interp: Interpolation::default(),
+            envelope: Envelope::default(),
+            loop_start: None,
+            loop_end: None,
+            pan: 0.0,
+            choke_group: None,
+            cue: false,
+        })
+    }
+
+    fn metered(meters: &EngineMeters, id: SampleId) -> bool {
+        meters.sample_peaks.iter().any(|m| m.id.load(Ordering::Relaxed) == id.0 + 1)
+    }
+
+    #[test]
+    fn recordings_get_a_meter() {
+        let meters = Arc::new(EngineMeters::default());
+        let (mut engine, captured) = recording_engine(64);
+        engine.set_meters(Arc::clone(&meters));
+        engine.drain_input();
+        engine.handle_cmd(AudioCommand::StopRecording);
+        assert!(metered(&meters, captured));
+
+        // armed but never loud enough: still a (silent) sample with a meter
+        let (mut engine, silent) = recording_engine(0);
+        engine.set_meters(Arc::clone(&meters));
+        engine.handle_cmd(AudioCommand::StopRecording);
+        assert!(metered(&meters, silent));
+    }
+
+    #[test]
+    fn master_capture_fills_the_buffer_it_was_handed() {
+        let mut engine = engine();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        engine.set_master_tx(tx);
+        engine.handle_cmd(AudioCommand::StartMasterCapture { buffer: Vec::with_capacity(300) });
+        let mut out = vec![StereoFrame::default(); 128];
+        for _ in 0..3 {
+            engine.render_block(&mut out);
+        }
+        // full at 300 frames: handed back without growing, and capture is off again
+        assert_eq!(rx.try_recv().unwrap().data.len(), 300);
+        assert!(engine.master_capture.is_none());
+    }
+
+    #[test]
+    fn a_grab_is_handed_over_whole_for_the_main_thread_to_register() {
+        let mut engine = engine();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        engine.set_completed_tx(tx);
+        let id = next_sample_id();
+        engine.handle_cmd(AudioCommand::GrabOutput { sample_id: id, buffer: Vec::with_capacity(300) });
+        let mut out = vec![StereoFrame::default(); 128];
+        for _ in 0..3 {
+            engine.render_block(&mut out);
+        }
+        let grab = rx.try_recv().unwrap();
+        assert_eq!((grab.sample_id, grab.buffer.data.len(), grab.in_engine), (id, 300, false));
+        assert!(engine.grab.is_none() && !engine.samples.contains_key(&id));
+    }
+
+    #[test]
+    fn cue_blocks_are_recycled_not_allocated() {
+        let mut engine = engine();
+        let (tx, rx) = crossbeam_channel::bounded(2);
+        let (free_tx, free_rx) = crossbeam_channel::bounded(2);
+        free_tx.send(Vec::with_capacity(256)).unwrap();
+        engine.set_cue_tx(tx, free_rx);
+
+        let mut out = vec![StereoFrame::default(); 128];
+        engine.render_block(&mut out);
+        let block = rx.try_recv().unwrap();
+        assert_eq!((block.len(), block.capacity()), (128, 256));
+
+        // the one block hasn't come back yet, so the next render sends nothing
+        engine.render_block(&mut out);
+        assert!(rx.try_recv().is_err());
+        free_tx.send(block).unwrap();
+        engine.render_block(&mut out);
+        assert_eq!(rx.try_recv().unwrap().capacity(), 256);
+    }
+
+    #[test]
+    fn voice_cap_steals_the_oldest_voice() {
+        let mut engine = engine();
+        engine.handle_cmd(AudioCommand::SetMaxVoices(4));
+        let ids: Vec<SampleId> = (0..10).map(|_| registered(&mut engine)).collect();
+        let mut out = vec![StereoFrame::default(); 64];
+        for &id in &ids {
+            engine.handle_cmd(hit(id));
+            let playing = engine.active.iter().filter(|a| a.stolen.is_none()).count();
+            assert!(playing <= 4, "{} voices playing", playing);
+            engine.render_block(&mut out);
+        }
+
+        // once the steal fades run out only the four newest hits are left
+        for _ in 0..STEAL_FADE_FRAMES / out.len() + 1 {
+            engine.render_block(&mut out);
+        }
+        let left: Vec<SampleId> = engine.active.iter().map(|a| a.sample_id).collect();
+        assert_eq!(left, ids[6..]);
+    }
+
+    #[test]
+    fn capturing_flag_follows_the_recording_state() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut engine = Engine::new(Arc::clone(&flag));
+        let (tx, rx) = crossbeam_channel::unbounded();
+        engine.set_input_rx(rx);
+        engine.handle_cmd(AudioCommand::StartRecording { sample_id: next_sample_id() });
+
+        // armed, hearing nothing over the threshold
+        tx.send(vec![StereoFrame::default(); 64]).unwrap();
+        engine.drain_input();
+        assert!(!flag.load(Ordering::Relaxed));
+
+        tx.send(vec![StereoFrame { left: 0.5, right: 0.5 }; 64]).unwrap();
+        engine.drain_input();
+        assert!(flag.load(Ordering::Relaxed));
+
+        engine.handle_cmd(AudioCommand::StopRecording);
+        assert!(!flag.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn unregistered_samples_fade_out_and_stop_triggering() {
+        let mut engine = engine();
+        let id = registered(&mut engine);
+        engine.handle_cmd(hit(id));
+        let mut out = vec![StereoFrame::default(); 64];
+        engine.render_block(&mut out);
+
+        // still playing: it fades rather than cuts, and the buffer goes once it's quiet
+        engine.handle_cmd(AudioCommand::UnregisterSample { id });
+        assert!(engine.samples.contains_key(&id));
+        engine.handle_cmd(hit(id));
+        assert_eq!(engine.active.len(), 1);
+        for _ in 0..8 {
+            engine.render_block(&mut out);
+        }
+        assert!(engine.active.is_empty());
+        assert!(!engine.samples.contains_key(&id));
+
+        engine.handle_cmd(hit(id));
+        assert!(engine.active.is_empty());
+    }
+
+    #[test]
+    fn a_non_finite_voice_is_left_out_of_the_mix() {
+        let mut engine = engine();
+        let clean = registered(&mut engine);
+        let broken = next_sample_id();
+        let nan = SampleBuffer::from_frames(vec![StereoFrame { left: f32::NAN, right: f32::INFINITY }; 48000]);
+        engine.handle_cmd(AudioCommand::RegisterSample { id: broken, buffer: nan });
+        engine.handle_cmd(AudioCommand::SetDcFilter { enabled: false });
+
+        let mut alone = vec![StereoFrame::default(); 64];
+        engine.handle_cmd(hit(clean));
+        engine.render_block(&mut alone);
+        engine.handle_cmd(hit(broken));
+        let mut mixed = vec![StereoFrame::default(); 64];
+        engine.render_block(&mut mixed);
+
+        assert!(mixed.iter().all(|f| f.left.is_finite() && f.right.is_finite()));
+        assert_eq!(mixed[63].left, alone[63].left);
+    }
+
+    #[test]
+    fn sync_stutter_brings_stutters_back_in_phase() {
+        let stutter = |id| match hit(id) {
+            AudioCommand::Trigger(params) => {
+                AudioCommand::Trigger(TriggerParams { stutter_period_samples: Some(100), ..params })
+            }
+            _ => unreachable!(),
+        };
+        let phases = |engine: &Engine| engine.active.iter().map(|a| a.voice.pos).collect::<Vec<_>>();
+        for sync in [false, true] {
+            let mut engine = engine();
+            let id = registered(&mut engine);
+            // two stutters of the same slice, started 37 frames apart
+            engine.handle_cmd(stutter(id));
+            engine.render_block(&mut [StereoFrame::default(); 37]);
+            engine.handle_cmd(stutter(id));
+            if sync {
+                engine.handle_cmd(AudioCommand::SyncStutter);
+            }
+            engine.render_block(&mut [StereoFrame::default(); 10]);
+
+            let p = phases(&engine);
+            assert_eq!(p[0] == p[1], sync, "positions {:?}", p);
+        }
+    }
+
+    #[test]
+    fn finished_recordings_lose_their_silent_tail_when_asked() {
+        for trim in [false, true] {
+            let mut engine = engine();
+            let (completed_tx, completed) = crossbeam_channel::unbounded();
+            engine.set_completed_tx(completed_tx);
+            let (tx, rx) = crossbeam_channel::unbounded();
+            engine.set_input_rx(rx);
+            engine.handle_cmd(AudioCommand::SetTrimRecordings(trim));
+            engine.handle_cmd(AudioCommand::StartRecording { sample_id: next_sample_id() });
+            tx.send(vec![StereoFrame { left: 0.5, right: 0.5 }; 64]).unwrap();
+            tx.send(vec![StereoFrame::default(); 48000]).unwrap();
+            engine.drain_input();
+            engine.handle_cmd(AudioCommand::StopRecording);
+
+            let len = completed.try_recv().unwrap().buffer.data.len();
+            assert_eq!(len, if trim { 64 + 2205 } else { 64 + 48000 }); // the hit plus the ~50ms pad
+        }
+    }
+
+    #[test]
+    fn a_hit_chokes_the_ringing_voice_in_its_group() {
+        let grouped = |id, group| match hit(id) {
+            AudioCommand::Trigger(params) => {
+                AudioCommand::Trigger(TriggerParams { choke_group: group, ..params })
+            }
+            _ => unreachable!(),
+        };
+        let mut engine = engine();
+        let (open_hat, closed_hat, kick) = (registered(&mut engine), registered(&mut engine), registered(&mut engine));
+        engine.handle_cmd(grouped(open_hat, Some(1)));
+        engine.handle_cmd(grouped(kick, None));
+        let mut out = vec![StereoFrame::default(); 64];
+        engine.render_block(&mut out);
+
+        // the closed hat fades the open one out; the kick, in no group, rings on
+        engine.handle_cmd(grouped(closed_hat, Some(1)));
+        for _ in 0..8 {
+            engine.render_block(&mut out);
+        }
+        let left: Vec<SampleId> = engine.active.iter().map(|a| a.sample_id).collect();
+        assert_eq!(left, [kick, closed_hat]);
     }
 }
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 use anyhow::Context;
 use crossbeam_channel::{Receiver, Sender};
@@ -11,6 +12,7 @@ use crate::audio_api::AudioCommand;
 mod effect;
 mod engine;
 mod frame;
+mod resample;
 mod sample_buffer;
 mod sample_id;
 mod voice;
@@ -19,35 +21,127 @@ pub use effect::{Effect, EffectSpec};
 pub use frame::StereoFrame;
 pub use sample_buffer::SampleBuffer;
 pub use sample_id::{next_sample_id, SampleId};
+pub use voice::{Envelope, Interpolation};
 
-use engine::{CompletedRecording, Engine};
+use engine::{CompletedRecording, Engine, EngineMeters};
+use resample::StreamResampler;
+
+// Cue blocks out to the headphone stream, emptied ones back from it
+type CueLink = (Sender<Vec<StereoFrame>>, Receiver<Vec<StereoFrame>>);
+
+// Everything an engine is wired up with. Kept on the handle so switching the
+// output device can build a new engine that picks up where the old one left off.
+#[derive(Clone)]
+struct EngineLinks {
+    cmd_rx: Receiver<AudioCommand>,
+    input_rx: Receiver<Vec<StereoFrame>>,
+    completed_tx: Sender<CompletedRecording>,
+    master_tx: Sender<SampleBuffer>,   // finished jam recordings
+    capturing_flag: Arc<AtomicBool>, // shared with the engine for UI feedback
+    meters: Arc<EngineMeters>,         // voice count / callback time / input level, published by the engine
+    cue: Option<CueLink>, // set when a cue output is open
+}
 
 pub struct AudioHandle {
     tx: Sender<AudioCommand>,
     completed_rx: Receiver<CompletedRecording>,
-    capturing_flag: Arc<AtomicBool>,
-    _output_stream: cpal::Stream,
+    master_rx: Receiver<SampleBuffer>,
+    links: EngineLinks,
+
+    // Output device switching. The engine lives inside the stream callback, so
+    // switching rebuilds both.
+    output_stream: cpal::Stream,
+    output_device_index: usize,
+
+    // Headphone cue output (POCKETTY_CUE_DEVICE), fed by whichever engine is current
+    _cue_stream: Option<cpal::Stream>,
 
     // Input device switching
     input_stream: Option<cpal::Stream>,
+    input_error: Arc<AtomicBool>, // set by the input stream's error callback
     input_tx: Sender<Vec<StereoFrame>>,
     sample_rate: cpal::SampleRate,
     input_device_index: usize,
 
     // Sample registry clone (for offline bounce)
     sample_registry: HashMap<SampleId, SampleBuffer>,
+
+    // Engine settings that have to survive an output switch (which builds a new engine)
+    dc_filter: bool,
+    record_threshold: f32,
+    normalize_recordings: bool,
+    trim_recordings: bool,
+    input_monitor: bool,
+    monitor_gain: f32,
+    max_voices: usize,
+    input_is_output: bool, // same device name both ways: monitoring would feed back, so it's held off
+    monitor_same_device: bool, // the user says it won't (headphones, or ALSA "default" both ways on separate hardware)
 }
 
 impl AudioHandle {
     /// Send a command to the engine. Also keeps a clone of registered samples
     /// so we can do offline bounce on the main thread.
     pub fn send(&mut self, cmd: AudioCommand) {
-        if let AudioCommand::RegisterSample { id, ref buffer } = cmd {
-            self.sample_registry.insert(id, buffer.clone());
+        match cmd {
+            AudioCommand::RegisterSample { id, ref buffer } => {
+                self.sample_registry.insert(id, buffer.clone());
+            }
+            AudioCommand::UnregisterSample { id } => {
+                self.sample_registry.remove(&id);
+            }
+            AudioCommand::SetDcFilter { enabled } => {
+                self.dc_filter = enabled;
+            }
+            AudioCommand::SetRecordThreshold(threshold) => {
+                self.record_threshold = threshold;
+            }
+            AudioCommand::SetNormalizeRecordings(enabled) => {
+                self.normalize_recordings = enabled;
+            }
+            AudioCommand::SetTrimRecordings(enabled) => {
+                self.trim_recordings = enabled;
+            }
+            AudioCommand::SetInputMonitor(enabled) => {
+                self.input_monitor = enabled;
+                let _ = self.tx.try_send(AudioCommand::SetInputMonitor(self.monitor_allowed()));
+                return;
+            }
+            AudioCommand::SetMonitorGain(gain) => {
+                self.monitor_gain = gain;
+            }
+            AudioCommand::SetMaxVoices(max) => {
+                self.max_voices = max;
+            }
+            _ => {}
         }
         let _ = self.tx.try_send(cmd);
     }
 
+    /// True when the input and output are the same device, where monitoring stays
+    /// off whatever the project asks for (it would just feed back), unless overridden.
+    pub fn monitor_guarded(&self) -> bool {
+        self.input_is_output && !self.monitor_same_device
+    }
+
+    /// Let monitoring through even when input and output look like one device. Device
+    /// names are all there is to go on, and ALSA names both directions "default".
+    pub fn set_monitor_same_device(&mut self, allowed: bool) {
+        self.monitor_same_device = allowed;
+        let _ = self.tx.try_send(AudioCommand::SetInputMonitor(self.monitor_allowed()));
+    }
+
+    fn monitor_allowed(&self) -> bool {
+        self.input_monitor && !self.monitor_guarded()
+    }
+
+    // re-check the feedback guard after either device changes
+    fn refresh_monitor_guard(&mut self) {
+        let input = Self::list_input_devices().get(self.input_device_index).cloned();
+        let output = Self::list_output_devices().get(self.output_device_index).cloned();
+        self.input_is_output = input.is_some() && input == output;
+        let _ = self.tx.try_send(AudioCommand::SetInputMonitor(self.monitor_allowed()));
+    }
+
     /// Access the sample registry (for offline bounce).
     pub fn samples(&self) -> &HashMap<SampleId, SampleBuffer> {
         &self.sample_registry
@@ -58,26 +152,90 @@ impl AudioHandle {
         self.sample_rate
     }
 
-    pub fn poll_completed_recording(&self) -> Option<CompletedRecording> {
-        self.completed_rx.try_recv().ok()
+    pub fn poll_completed_recording(&mut self) -> Option<CompletedRecording> {
+        let rec = self.completed_rx.try_recv().ok()?;
+        if rec.in_engine {
+            // the engine registered it itself; keep a copy so a rebuilt engine (and bounce) have it too
+            self.sample_registry.insert(rec.sample_id, rec.buffer.clone());
+        } else {
+            // a grab leaves the audio thread without a copy, so it gets registered from here
+            self.send(AudioCommand::RegisterSample { id: rec.sample_id, buffer: rec.buffer.clone() });
+        }
+        Some(rec)
+    }
+
+    /// A finished master (jam) recording, if one came back from the engine.
+    pub fn poll_master_capture(&self) -> Option<SampleBuffer> {
+        self.master_rx.try_recv().ok()
     }
 
     /// True when the engine has crossed the peak threshold and is actively capturing audio.
     pub fn is_capturing(&self) -> bool {
-        self.capturing_flag.load(Ordering::Relaxed)
+        self.links.capturing_flag.load(Ordering::Relaxed)
+    }
+
+    /// Number of voices the engine rendered in its last block.
+    pub fn active_voices(&self) -> usize {
+        self.links.meters.voices.load(Ordering::Relaxed)
+    }
+
+    /// Share of the last block's real-time budget spent rendering, in percent.
+    pub fn callback_load(&self) -> u32 {
+        self.links.meters.callback_load.load(Ordering::Relaxed)
+    }
+
+    /// Loudest input sample (0.0-1.0) the engine saw since the last call.
+    pub fn take_input_peak(&self) -> f32 {
+        f32::from_bits(self.links.meters.input_peak.swap(0, Ordering::Relaxed))
+    }
+
+    /// Loudest sample of the master output since the last call. Over 1.0 means it clipped.
+    pub fn take_output_peak(&self) -> f32 {
+        f32::from_bits(self.links.meters.output_peak.swap(0, Ordering::Relaxed))
+    }
+
+    /// Loudest output (0.0-1.0+) of each registered sample since the last call.
+    pub fn take_sample_peaks(&self) -> Vec<(SampleId, f32)> {
+        self.links.meters.sample_peaks.iter()
+            .filter_map(|m| {
+                let id = m.id.load(Ordering::Relaxed).checked_sub(1)?;
+                Some((SampleId(id), f32::from_bits(m.peak.swap(0, Ordering::Relaxed))))
+            })
+            .collect()
     }
 
     /// List names of all available input devices.
     pub fn list_input_devices() -> Vec<String> {
         let host = cpal::default_host();
         host.input_devices()
-            .map(|devs| {
-                devs.filter_map(|d| d.name().ok())
-                    .collect()
-            })
+            .map(|devs| devs.filter_map(|d| device_name(&d)).collect())
             .unwrap_or_default()
     }
 
+    /// List names of all available output devices.
+    pub fn list_output_devices() -> Vec<String> {
+        let host = cpal::default_host();
+        host.output_devices()
+            .map(|devs| devs.filter_map(|d| device_name(&d)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Index of the active input device within `list_input_devices()`.
+    pub fn input_device_index(&self) -> usize {
+        self.input_device_index
+    }
+
+    /// Index of the active output device within `list_output_devices()`.
+    pub fn output_device_index(&self) -> usize {
+        self.output_device_index
+    }
+
+    /// False when the input stream couldn't be opened or has reported an error
+    /// since, i.e. recordings will come out silent.
+    pub fn input_ok(&self) -> bool {
+        self.input_stream.is_some() && !self.input_error.load(Ordering::Relaxed)
+    }
+
     /// Name of the currently active input device.
     pub fn current_input_name(&self) -> String {
         let devices = Self::list_input_devices();
@@ -92,35 +250,121 @@ impl AudioHandle {
     /// Cycle to the next input device and rebuild the input stream.
     /// Returns the name of the newly selected device.
     pub fn cycle_input_device(&mut self) -> String {
+        let count = Self::list_input_devices().len();
+        if count == 0 {
+            return "none".into();
+        }
+
+        // Advance to the next device that opens (wrapping), skipping ones that don't
+        for offset in 1..=count {
+            let next = (self.input_device_index + offset) % count;
+            if let Ok(name) = self.select_input_device(next) {
+                return name;
+            }
+        }
+        // stayed on the previous device; input_ok() says whether that one still works
+        self.current_input_name()
+    }
+
+    /// Switch the input stream to the device called `name`, falling back to the first
+    /// one whose name contains it. False when there's no such device or it wouldn't
+    /// open, in which case the current device stays.
+    pub fn set_input_device_by_name(&mut self, name: &str) -> bool {
+        let devices = Self::list_input_devices();
+        let Some(index) = devices.iter().position(|d| d == name)
+            .or_else(|| devices.iter().position(|d| d.contains(name)))
+        else {
+            return false;
+        };
+        self.select_input_device(index).is_ok()
+    }
+
+    /// Switch the input stream to the device at `index`. If the new stream
+    /// can't be built, the previous device is restored and an error returned.
+    pub fn select_input_device(&mut self, index: usize) -> anyhow::Result<String> {
         let host = cpal::default_host();
         let devices: Vec<cpal::Device> = host.input_devices()
             .map(|d| d.collect())
             .unwrap_or_default();
+        let device = devices.get(index).context("no such input device")?;
+        let name = device_name(device).unwrap_or_else(|| "???".into());
 
-        if devices.is_empty() {
-            return "none".into();
-        }
-
-        // Advance to next device (wrapping)
-        self.input_device_index = (self.input_device_index + 1) % devices.len();
-        let device = &devices[self.input_device_index];
-        let name = device.name().unwrap_or_else(|_| "???".into());
-
-        // Drop old stream (stops it)
+        // Drop old stream (stops it) before opening the new one
         self.input_stream = None;
-
-        // Build new stream on the selected device
+        self.input_error.store(false, Ordering::Relaxed);
         self.input_stream = build_input_stream_on_device(
             device,
             self.sample_rate,
             self.input_tx.clone(),
+            Arc::clone(&self.input_error),
         );
 
         if self.input_stream.is_none() {
-            // silently failed — UI shows the device name regardless
+            if let Some(prev) = devices.get(self.input_device_index) {
+                self.input_stream = build_input_stream_on_device(
+                    prev,
+                    self.sample_rate,
+                    self.input_tx.clone(),
+                    Arc::clone(&self.input_error),
+                );
+            }
+            anyhow::bail!("couldn't open input '{}'", name);
         }
 
-        name
+        self.input_device_index = index;
+        self.refresh_monitor_guard();
+        Ok(name)
+    }
+
+    /// Switch playback to the output device at `index`. A fresh engine is built
+    /// with every registered sample; voices that were sounding are dropped.
+    /// On failure the previous device is reopened and an error returned.
+    pub fn select_output_device(&mut self, index: usize) -> anyhow::Result<String> {
+        let host = cpal::default_host();
+        let devices: Vec<cpal::Device> = host.output_devices()
+            .map(|d| d.collect())
+            .unwrap_or_default();
+        let device = devices.get(index).context("no such output device")?;
+        let name = device_name(device).unwrap_or_else(|| "???".into());
+
+        match self.open_output(device) {
+            Ok(()) => {
+                self.output_device_index = index;
+                self.refresh_monitor_guard();
+                Ok(name)
+            }
+            Err(e) => {
+                if let Some(prev) = devices.get(self.output_device_index) {
+                    let _ = self.open_output(prev);
+                }
+                Err(e.context(format!("couldn't open output '{}'", name)))
+            }
+        }
+    }
+
+    fn open_output(&mut self, device: &cpal::Device) -> anyhow::Result<()> {
+        let (stream, sample_rate) = open_output_stream(
+            device,
+            Some(self.sample_rate),
+            self.links.clone(),
+            &self.sample_registry,
+        )?;
+        // Replacing the field drops (stops) the old stream
+        self.output_stream = stream;
+        let rate_changed = sample_rate != self.sample_rate;
+        self.sample_rate = sample_rate;
+        if rate_changed && self.input_stream.is_some() {
+            // the input resamples to the engine rate, so it has to follow
+            let _ = self.select_input_device(self.input_device_index);
+        }
+        let _ = self.tx.try_send(AudioCommand::SetDcFilter { enabled: self.dc_filter });
+        let _ = self.tx.try_send(AudioCommand::SetRecordThreshold(self.record_threshold));
+        let _ = self.tx.try_send(AudioCommand::SetNormalizeRecordings(self.normalize_recordings));
+        let _ = self.tx.try_send(AudioCommand::SetTrimRecordings(self.trim_recordings));
+        let _ = self.tx.try_send(AudioCommand::SetInputMonitor(self.monitor_allowed()));
+        let _ = self.tx.try_send(AudioCommand::SetMonitorGain(self.monitor_gain));
+        let _ = self.tx.try_send(AudioCommand::SetMaxVoices(self.max_voices));
+        Ok(())
     }
 }
 
@@ -129,72 +373,159 @@ pub fn start_audio() -> anyhow::Result<AudioHandle> {
 
     let host = cpal::default_host();
     let device = host.default_output_device().context("no default output device")?;
-    let config = device.default_output_config().context("no default output config")?;
-
-    let sample_rate = config.sample_rate();
-    let channels = config.channels() as usize;
 
     let (input_tx, input_rx) = crossbeam_channel::bounded::<Vec<StereoFrame>>(2048);
     let (completed_tx, completed_rx) = crossbeam_channel::bounded::<CompletedRecording>(16);
-    let capturing_flag = Arc::new(AtomicBool::new(false));
+    let (master_tx, master_rx) = crossbeam_channel::bounded::<SampleBuffer>(4);
+    let mut links = EngineLinks {
+        cmd_rx: rx,
+        input_rx,
+        completed_tx,
+        master_tx,
+        capturing_flag: Arc::new(AtomicBool::new(false)),
+        meters: Arc::new(EngineMeters::default()),
+        cue: None,
+    };
+
+    // The cue output needs the main rate to resample to, so it opens second and
+    // the engine is handed its channels through the links. Blocks go round between
+    // the two: the engine fills an empty one, the cue stream plays it and sends it back.
+    let (cue_tx, cue_rx) = crossbeam_channel::bounded::<Vec<StereoFrame>>(CUE_BLOCKS);
+    let (free_tx, free_rx) = crossbeam_channel::bounded::<Vec<StereoFrame>>(CUE_BLOCKS);
+    for _ in 0..CUE_BLOCKS {
+        let _ = free_tx.try_send(Vec::with_capacity(CUE_BLOCK_FRAMES));
+    }
+    let cue_name = std::env::var("POCKETTY_CUE_DEVICE").ok();
+    let main_rate = device.default_output_config().map(|c| c.sample_rate()).ok();
+    let cue_stream = cue_name.zip(main_rate)
+        .and_then(|(name, rate)| open_cue_stream(&host, &name, rate, cue_rx, free_tx));
+    if cue_stream.is_some() {
+        links.cue = Some((cue_tx, free_rx));
+    }
+
+    let (output_stream, sample_rate) =
+        open_output_stream(&device, None, links.clone(), &HashMap::new())?;
+
+    // Find the indices of the default devices in the device lists
+    let default_output_name = device_name(&device).unwrap_or_default();
+    let output_device_index = AudioHandle::list_output_devices().iter()
+        .position(|n| n == &default_output_name)
+        .unwrap_or(0);
+    let default_input_name = host.default_input_device()
+        .and_then(|d| device_name(&d))
+        .unwrap_or_default();
+    let input_device_index = AudioHandle::list_input_devices().iter()
+        .position(|n| n == &default_input_name)
+        .unwrap_or(0);
+
+    let input_error = Arc::new(AtomicBool::new(false));
+    let input_stream =
+        try_build_input_stream(&host, sample_rate, input_tx.clone(), Arc::clone(&input_error));
+
+    let mut handle = AudioHandle {
+        tx,
+        completed_rx,
+        master_rx,
+        links,
+        output_stream,
+        output_device_index,
+        _cue_stream: cue_stream,
+        input_stream,
+        input_error,
+        input_tx,
+        sample_rate,
+        input_device_index,
+        sample_registry: HashMap::new(),
+        dc_filter: true,
+        record_threshold: engine::RECORD_PEAK_THRESHOLD,
+        normalize_recordings: false,
+        trim_recordings: false,
+        input_monitor: false,
+        monitor_gain: 1.0,
+        max_voices: engine::MAX_VOICES,
+        input_is_output: false,
+        monitor_same_device: false,
+    };
+    handle.refresh_monitor_guard();
+    Ok(handle)
+}
+
+// cpal 0.17 deprecates name() in favour of description(), but name() is what
+// the rest of the app shows and matches devices by.
+#[allow(deprecated)]
+fn device_name(device: &cpal::Device) -> Option<String> {
+    device.name().ok()
+}
+
+// ── Output stream ─────────────────────────────────────────────────
+
+/// Open and start an output stream on `device`, preferring `preferred_rate`
+/// (so loaded samples keep their pitch) when the device supports it.
+fn open_output_stream(
+    device: &cpal::Device,
+    preferred_rate: Option<cpal::SampleRate>,
+    links: EngineLinks,
+    samples: &HashMap<SampleId, SampleBuffer>,
+) -> anyhow::Result<(cpal::Stream, cpal::SampleRate)> {
+    let default = device.default_output_config().context("no default output config")?;
+    let config = preferred_rate
+        .filter(|&rate| rate != default.sample_rate())
+        .and_then(|rate| {
+            device.supported_output_configs().ok()?
+                .find(|c| {
+                    c.sample_format() == cpal::SampleFormat::F32
+                        && c.min_sample_rate() <= rate
+                        && rate <= c.max_sample_rate()
+                })
+                .map(|c| c.with_sample_rate(rate))
+        })
+        .unwrap_or(default);
+
+    let sample_rate = config.sample_rate();
+    let channels = config.channels() as usize;
 
     match config.sample_format() {
         cpal::SampleFormat::F32 => {
             let output_stream = build_output_stream_f32(
-                &device, &config.into(), rx, input_rx, completed_tx,
-                channels, Arc::clone(&capturing_flag),
+                device, &config.into(), links, channels, samples,
             )?;
             output_stream.play().context("failed to play output stream")?;
-
-            // Find the index of the default input device
-            let default_input_name = host.default_input_device()
-                .and_then(|d| d.name().ok())
-                .unwrap_or_default();
-            let all_inputs: Vec<String> = host.input_devices()
-                .map(|devs| devs.filter_map(|d| d.name().ok()).collect())
-                .unwrap_or_default();
-            let input_device_index = all_inputs.iter()
-                .position(|n| n == &default_input_name)
-                .unwrap_or(0);
-
-            let input_stream = try_build_input_stream(&host, sample_rate, input_tx.clone());
-
-            Ok(AudioHandle {
-                tx,
-                completed_rx,
-                capturing_flag,
-                _output_stream: output_stream,
-                input_stream,
-                input_tx,
-                sample_rate,
-                input_device_index,
-                sample_registry: HashMap::new(),
-            })
+            Ok((output_stream, sample_rate))
         }
         _ => anyhow::bail!("unsupported sample format (only f32 supported for now)"),
     }
 }
 
-// ── Output stream ─────────────────────────────────────────────────
-
 fn build_output_stream_f32(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
-    rx: Receiver<AudioCommand>,
-    input_rx: Receiver<Vec<StereoFrame>>,
-    completed_tx: crossbeam_channel::Sender<CompletedRecording>,
+    links: EngineLinks,
     channels: usize,
-    capturing_flag: Arc<AtomicBool>,
+    samples: &HashMap<SampleId, SampleBuffer>,
 ) -> anyhow::Result<cpal::Stream> {
+    let EngineLinks { cmd_rx: rx, input_rx, completed_tx, master_tx, capturing_flag, meters, cue } = links;
     let mut engine = Engine::new(capturing_flag);
     engine.set_input_rx(input_rx);
     engine.set_completed_tx(completed_tx);
+    engine.set_master_tx(master_tx);
+    if let Some((tx, free)) = cue {
+        engine.set_cue_tx(tx, free);
+    }
+    engine.set_meters(Arc::clone(&meters));
+    engine.set_sample_rate(config.sample_rate);
+    let sample_rate = config.sample_rate as f64;
+
+    // Carry over everything that was registered on the previous engine
+    for (&id, buffer) in samples {
+        engine.handle_cmd(AudioCommand::RegisterSample { id, buffer: buffer.clone() });
+    }
 
     let err_fn = |err: cpal::StreamError| { let _ = err; };
 
     let stream = device.build_output_stream(
         config,
         move |data: &mut [f32], _info| {
+            let started = Instant::now();
             while let Ok(cmd) = rx.try_recv() {
                 engine.handle_cmd(cmd);
             }
@@ -206,6 +537,13 @@ fn build_output_stream_f32(
                 std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut StereoFrame, n_frames)
             };
             engine.render_block(frames);
+
+            // How much of this block's playback time we spent producing it
+            let budget = n_frames as f64 / sample_rate;
+            if budget > 0.0 {
+                let pct = started.elapsed().as_secs_f64() / budget * 100.0;
+                meters.callback_load.store(pct as u32, Ordering::Relaxed);
+            }
         },
         err_fn,
         None,
@@ -214,12 +552,79 @@ fn build_output_stream_f32(
     Ok(stream)
 }
 
+// ── Cue output ───────────────────────────────────────────────────
+
+const CUE_MAX_BACKLOG: usize = 4096; // frames; past this the oldest are dropped to keep latency down
+const CUE_BLOCKS: usize = 64; // blocks going round between the engine and the cue stream
+const CUE_BLOCK_FRAMES: usize = 8192; // room in each, bigger than any callback asks for
+
+/// Open the headphone cue output on the first output device whose name contains
+/// `name`. The engine sends it blocks rendered at `main_rate`; when it runs dry
+/// (the two devices' clocks drift) it plays silence. Played blocks go back on
+/// `free` for the engine to fill again. None if there's no such device or it
+/// won't open, in which case cue sounds just stay in the main mix.
+fn open_cue_stream(
+    host: &cpal::Host,
+    name: &str,
+    main_rate: cpal::SampleRate,
+    rx: Receiver<Vec<StereoFrame>>,
+    free: Sender<Vec<StereoFrame>>,
+) -> Option<cpal::Stream> {
+    let default_name = host.default_output_device().and_then(|d| device_name(&d));
+    let device = host.output_devices().ok()?
+        .find(|d| device_name(d).is_some_and(|n| n.contains(name) && Some(&n) != default_name.as_ref()))?;
+    let config = device.default_output_config().ok()?;
+    if config.sample_format() != cpal::SampleFormat::F32 {
+        return None;
+    }
+    let stream_config: cpal::StreamConfig = config.into();
+    let channels = stream_config.channels as usize;
+    let ratio = stream_config.sample_rate as f64 / main_rate as f64;
+    let needs_resample = (ratio - 1.0).abs() > 0.001;
+
+    let mut pending: VecDeque<StereoFrame> = VecDeque::with_capacity(CUE_MAX_BACKLOG * 2);
+    let mut resampler = StreamResampler::new(ratio);
+    let stream = device
+        .build_output_stream(
+            &stream_config,
+            move |data: &mut [f32], _info| {
+                while let Ok(block) = rx.try_recv() {
+                    if needs_resample {
+                        pending.extend(resampler.process(&block));
+                    } else {
+                        pending.extend(&block);
+                    }
+                    let _ = free.try_send(block);
+                }
+                let excess = pending.len().saturating_sub(CUE_MAX_BACKLOG);
+                pending.drain(..excess);
+
+                for out in data.chunks_exact_mut(channels) {
+                    let f = pending.pop_front().unwrap_or_default();
+                    for (c, s) in out.iter_mut().enumerate() {
+                        *s = match c {
+                            0 => f.left,
+                            1 => f.right,
+                            _ => 0.0,
+                        };
+                    }
+                }
+            },
+            |_err: cpal::StreamError| {},
+            None,
+        )
+        .ok()?;
+    stream.play().ok()?;
+    Some(stream)
+}
+
 // ── Input stream (default device) ────────────────────────────────
 
 fn try_build_input_stream(
     host: &cpal::Host,
     target_sample_rate: cpal::SampleRate,
     tx: Sender<Vec<StereoFrame>>,
+    error_flag: Arc<AtomicBool>,
 ) -> Option<cpal::Stream> {
     let device = match host.default_input_device() {
         Some(d) => d,
@@ -228,7 +633,7 @@ fn try_build_input_stream(
         }
     };
 
-    build_input_stream_on_device(&device, target_sample_rate, tx)
+    build_input_stream_on_device(&device, target_sample_rate, tx, error_flag)
 }
 
 // ── Input stream (specific device) ──────────────────────────────
@@ -237,6 +642,7 @@ fn build_input_stream_on_device(
     device: &cpal::Device,
     target_sample_rate: cpal::SampleRate,
     tx: Sender<Vec<StereoFrame>>,
+    error_flag: Arc<AtomicBool>,
 ) -> Option<cpal::Stream> {
     let supported = device.default_input_config().ok()?;
     let stream_config: cpal::StreamConfig = supported.into();
@@ -249,8 +655,13 @@ fn build_input_stream_on_device(
     let needs_resample = (resample_ratio - 1.0).abs() > 0.001;
 
     let in_channels = stream_config.channels as usize;
+    let mut resampler = StreamResampler::new(resample_ratio);
 
-    let err_fn = |err: cpal::StreamError| { let _ = err; };
+    // a device that errors out mid-session (unplugged, etc.) just stops sending;
+    // flag it so the UI can say so instead of recording silence
+    let err_fn = move |_err: cpal::StreamError| {
+        error_flag.store(true, Ordering::Relaxed);
+    };
 
     let stream = device
         .build_input_stream(
@@ -258,20 +669,23 @@ fn build_input_stream_on_device(
             move |data: &[f32], _info: &cpal::InputCallbackInfo| {
                 let frames: Vec<StereoFrame> = if in_channels == 1 {
                     data.iter()
-                        .map(|&s| StereoFrame { left: s, right: s })
+                        .map(|&s| {
+                            let s = sanitize_input_sample(s);
+                            StereoFrame { left: s, right: s }
+                        })
                         .collect()
                 } else {
                     data.chunks_exact(in_channels)
                         .map(|c| StereoFrame {
-                            left: c[0],
-                            right: if c.len() > 1 { c[1] } else { c[0] },
+                            left: sanitize_input_sample(c[0]),
+                            right: sanitize_input_sample(if c.len() > 1 { c[1] } else { c[0] }),
                         })
                         .collect()
                 };
 
                 // Resample to target rate if the device runs at a different rate
                 let output = if needs_resample {
-                    resample_linear_frames(&frames, resample_ratio)
+                    resampler.process(&frames)
                 } else {
                     frames
                 };
@@ -290,57 +704,50 @@ fn build_input_stream_on_device(
     Some(stream)
 }
 
-/// Simple linear interpolation resampler for input frames.
-fn resample_linear_frames(input: &[StereoFrame], ratio: f64) -> Vec<StereoFrame> {
-    if input.is_empty() {
-        return Vec::new();
-    }
-    let out_len = (input.len() as f64 * ratio) as usize;
-    let mut output = Vec::with_capacity(out_len);
-    for i in 0..out_len {
-        let src = i as f64 / ratio;
-        let idx = src as usize;
-        let frac = (src - idx as f64) as f32;
-        let s0 = input[idx.min(input.len() - 1)];
-        let s1 = input[(idx + 1).min(input.len() - 1)];
-        output.push(StereoFrame {
-            left: s0.left * (1.0 - frac) + s1.left * frac,
-            right: s0.right * (1.0 - frac) + s1.right * frac,
-        });
-    }
-    output
+/// Drivers can hand us garbage around device start/stop: NaN/inf, denormals,
+/// or values way past full scale. Flush those to zero and clamp the rest.
+#[inline]
+fn sanitize_input_sample(s: f32) -> f32 {
+    if s.is_normal() { s.clamp(-1.0, 1.0) } else { 0.0 }
 }
 
 // ── Offline bounce ──────────────────────────────────────────────
 
 /// Render a pattern offline into a SampleBuffer.
-/// `step_commands[i]` = the AudioCommands to fire at step i (0..15).
-/// Output is exactly `n_steps * frames_per_step` frames — hard cutoff at the pattern boundary.
+/// `events` = (frame offset, command) pairs sorted by offset, e.g. from `Middle::schedule_pattern`.
+/// Output is exactly `total_frames` long — hard cutoff at the pattern boundary.
+/// Filters, reverb and the DC blocker are tuned to `sample_rate`, the rate the
+/// samples were loaded at; `dc_filter` mirrors the project's master DC toggle.
 pub fn bounce_offline(
     samples: &HashMap<SampleId, SampleBuffer>,
-    step_commands: &[Vec<AudioCommand>],
-    frames_per_step: usize,
+    events: &[(usize, AudioCommand)],
+    total_frames: usize,
+    sample_rate: u32,
+    dc_filter: bool,
 ) -> SampleBuffer {
     let capturing_flag = Arc::new(AtomicBool::new(false));
     let mut engine = Engine::new(capturing_flag);
+    engine.set_sample_rate(sample_rate);
+    engine.handle_cmd(AudioCommand::SetDcFilter { enabled: dc_filter });
 
     // Register all samples
     for (&id, buffer) in samples {
         engine.handle_cmd(AudioCommand::RegisterSample { id, buffer: buffer.clone() });
     }
 
-    let n_steps = step_commands.len();
-    let total = n_steps * frames_per_step;
-    let mut output = vec![StereoFrame::default(); total];
+    let mut output = vec![StereoFrame::default(); total_frames];
 
-    for (step_idx, cmds) in step_commands.iter().enumerate() {
-        for cmd in cmds {
-            engine.handle_cmd(cmd.clone());
+    // render up to each event's offset, then apply it
+    let mut rendered = 0;
+    for (at, cmd) in events {
+        let at = (*at).min(total_frames);
+        if at > rendered {
+            engine.render_block(&mut output[rendered..at]);
+            rendered = at;
         }
-        let start = step_idx * frames_per_step;
-        let end = start + frames_per_step;
-        engine.render_block(&mut output[start..end]);
+        engine.handle_cmd(cmd.clone());
     }
+    engine.render_block(&mut output[rendered..]);
 
     SampleBuffer::from_frames(output)
 }
@@ -0,0 +1,191 @@
+// Sample rate conversion. Files are converted once at load, so they get a windowed
+// sinc that's band-limited to the lower of the two rates: pitching a 48k or 96k file
+// down to the engine rate no longer folds its top octave back in as junk. The live
+// streams (input, cue) go through a Catmull-Rom resampler instead, which is cheap
+// enough to run per callback and a good deal cleaner than straight lines.
+
+use std::f64::consts::PI;
+
+use super::frame::StereoFrame;
+use super::voice::cubic;
+
+const SINC_ZERO_CROSSINGS: usize = 12; // each side of the kernel centre
+const SINC_TABLE_RES: usize = 256;     // kernel points per zero crossing
+const SINC_ROLLOFF: f64 = 0.95;        // cutoff as a share of the lower nyquist, room for the transition band
+
+/// Convert `frames` from `src_rate` to `dst_rate`. The output is `len * dst / src`
+/// frames, rounded up.
+pub fn resample(frames: &[StereoFrame], src_rate: u32, dst_rate: u32) -> Vec<StereoFrame> {
+    if src_rate == dst_rate || frames.is_empty() {
+        return frames.to_vec();
+    }
+    let ratio = dst_rate as f64 / src_rate as f64;
+    // downsampling lowers the cutoff to the new nyquist, measured in source frames
+    let cutoff = ratio.min(1.0) * SINC_ROLLOFF;
+    let half_width = SINC_ZERO_CROSSINGS as f64 / cutoff;
+    let table = sinc_table();
+
+    let out_len = (frames.len() as f64 * ratio).ceil() as usize;
+    let mut out = Vec::with_capacity(out_len);
+    for i in 0..out_len {
+        let center = i as f64 / ratio; // fractional position in the source
+        let first = (center - half_width).ceil().max(0.0) as usize;
+        let last = ((center + half_width).floor() as usize).min(frames.len() - 1);
+
+        let (mut left, mut right, mut total) = (0.0_f32, 0.0_f32, 0.0_f32);
+        for (j, f) in frames.iter().enumerate().take(last + 1).skip(first) {
+            let at = ((center - j as f64).abs() * cutoff * SINC_TABLE_RES as f64) as usize;
+            let Some(&w) = table.get(at) else { continue; };
+            left += f.left * w;
+            right += f.right * w;
+            total += w;
+        }
+        // dividing by the weight sum keeps unity gain, including near the ends where
+        // the kernel hangs off the buffer
+        out.push(if total.abs() > f32::EPSILON {
+            StereoFrame { left: left / total, right: right / total }
+        } else {
+            StereoFrame::zero()
+        });
+    }
+    out
+}
+
+// one side of a Blackman-windowed sinc, from the centre out to the last zero crossing
+fn sinc_table() -> Vec<f32> {
+    let len = SINC_ZERO_CROSSINGS * SINC_TABLE_RES + 1;
+    (0..len)
+        .map(|k| {
+            let x = k as f64 / SINC_TABLE_RES as f64;
+            let sinc = if k == 0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let d = x / SINC_ZERO_CROSSINGS as f64;
+            let window = 0.42 + 0.5 * (PI * d).cos() + 0.08 * (2.0 * PI * d).cos();
+            (sinc * window) as f32
+        })
+        .collect()
+}
+
+/// Cubic resampler for a stream that arrives in chunks. The read position and the
+/// previous chunk's last three frames carry over, so chunk boundaries interpolate
+/// like any other stretch of frames and the output length doesn't drift from
+/// `input * ratio` however many chunks go through. Costs two frames of latency.
+pub struct StreamResampler {
+    step: f64,                 // source frames per output frame
+    pos: f64,                  // next read position; 0.0 is history[0] once there is one
+    history: [StereoFrame; 3], // tail of the previous chunk
+    held: usize,               // how much of `history` is filled
+}
+
+impl StreamResampler {
+    pub fn new(ratio: f64) -> Self {
+        Self { step: 1.0 / ratio, pos: 0.0, history: [StereoFrame::zero(); 3], held: 0 }
+    }
+
+    pub fn process(&mut self, input: &[StereoFrame]) -> Vec<StereoFrame> {
+        if input.is_empty() {
+            return Vec::new();
+        }
+        // the source for this call is the history followed by the new chunk
+        let held = self.held;
+        let len = held + input.len();
+        let history = self.history;
+        let frame = |i: usize| if i < held { history[i] } else { input[i - held] };
+
+        let mut output = Vec::with_capacity((len as f64 / self.step) as usize + 1);
+        while (self.pos as usize) + 2 < len {
+            let idx = self.pos as usize;
+            let t = (self.pos - idx as f64) as f32;
+            let (p0, p1, p2, p3) = (frame(idx.saturating_sub(1)), frame(idx), frame(idx + 1), frame(idx + 2));
+            output.push(StereoFrame {
+                left: cubic(p0.left, p1.left, p2.left, p3.left, t),
+                right: cubic(p0.right, p1.right, p2.right, p3.right, t),
+            });
+            self.pos += self.step;
+        }
+        // keep the last three frames and re-base the read position onto them
+        let keep = len.min(3);
+        for k in 0..keep {
+            self.history[k] = frame(len - keep + k);
+        }
+        self.held = keep;
+        self.pos -= (len - keep) as f64;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(hz: f32, rate: u32, frames: usize) -> Vec<StereoFrame> {
+        (0..frames)
+            .map(|i| {
+                let s = (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin();
+                StereoFrame { left: s, right: s }
+            })
+            .collect()
+    }
+
+    // away from the ends, where the kernel hangs off the buffer
+    fn rms(frames: &[StereoFrame]) -> f32 {
+        let mid = &frames[frames.len() / 8..frames.len() * 7 / 8];
+        (mid.iter().map(|f| f.left * f.left).sum::<f32>() / mid.len() as f32).sqrt()
+    }
+
+    // what loads used to go through: straight lines between neighbours
+    fn linear(frames: &[StereoFrame], src_rate: u32, dst_rate: u32) -> Vec<StereoFrame> {
+        let step = src_rate as f64 / dst_rate as f64;
+        let out_len = (frames.len() as f64 / step) as usize;
+        (0..out_len)
+            .map(|i| {
+                let pos = i as f64 * step;
+                let (k, t) = (pos as usize, (pos - pos.floor()) as f32);
+                let (a, b) = (frames[k], frames[(k + 1).min(frames.len() - 1)]);
+                StereoFrame { left: a.left + (b.left - a.left) * t, right: a.right + (b.right - a.right) * t }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn downsampling_drops_tones_above_the_new_nyquist() {
+        // 15 kHz has nowhere to go at 22.05k: linear folds it back down to 7.05 kHz
+        let high = sine(15000.0, 48000, 4800);
+        let sinc = rms(&resample(&high, 48000, 22050));
+        let lines = rms(&linear(&high, 48000, 22050));
+        assert!(sinc < 0.01, "sinc leaves {}", sinc);
+        assert!(lines > 0.3, "linear leaves {}", lines);
+
+        // while a tone well inside the band comes through at full level
+        let low = sine(1000.0, 48000, 4800);
+        let kept = rms(&resample(&low, 48000, 22050)) / rms(&low);
+        assert!((kept - 1.0).abs() < 0.02, "1 kHz at {}", kept);
+    }
+
+    #[test]
+    fn chunked_stream_has_no_seams() {
+        let ratio = 48000.0 / 44100.0;
+        let step = 2.0 * std::f32::consts::PI * 440.0 / 44100.0;
+        let sine: Vec<StereoFrame> = (0..4410)
+            .map(|i| {
+                let s = (i as f32 * step).sin();
+                StereoFrame { left: s, right: s }
+            })
+            .collect();
+
+        let whole = StreamResampler::new(ratio).process(&sine);
+        let mut chunked_rs = StreamResampler::new(ratio);
+        let mut chunked = Vec::new();
+        for chunk in sine.chunks(331) {
+            chunked.extend(chunked_rs.process(chunk));
+        }
+
+        // the same frames as resampling it in one go, seams included
+        assert_eq!(chunked.len(), whole.len());
+        for (i, (a, b)) in chunked.iter().zip(&whole).enumerate() {
+            assert!((a.left - b.left).abs() < 1e-5, "frame {}: {} vs {}", i, a.left, b.left);
+        }
+        // and never a jump bigger than the sine's own steepest slope at the output rate
+        let max_step = step / ratio as f32 * 1.01;
+        assert!(chunked.windows(2).all(|w| (w[1].left - w[0].left).abs() <= max_step));
+    }
+}
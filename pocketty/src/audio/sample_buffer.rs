@@ -1,5 +1,8 @@
 use std::path::Path;
 use super::frame::StereoFrame;
+use super::resample::resample;
+
+const TRIM_PAD_FRAMES: usize = 2205; // ~50ms at 44.1k, a little under at 48k
 
 #[derive(Clone, Debug)]
 pub struct SampleBuffer {
@@ -25,15 +28,93 @@ impl SampleBuffer {
                 .samples::<f32>()
                 .collect::<Result<Vec<_>, _>>()?, 
             hound::SampleFormat::Int => { // int, convert to float
-                let max = (1i32 << (spec.bits_per_sample - 1)) as f32;
+                // full scale is 2^(bits-1) at every depth. Done in f32 since `1i32 << 31`
+                // wraps negative and flipped 32-bit files upside down. hound already
+                // recenters unsigned 8-bit (silence at 128) to signed, so no special case.
+                if !(8..=32).contains(&spec.bits_per_sample) {
+                    anyhow::bail!("Unsupported bit depth: {}", spec.bits_per_sample);
+                }
+                let max = 2f32.powi(spec.bits_per_sample as i32 - 1);
                 reader
                     .samples::<i32>()
-                    .map(|s| s.map(|x| x as f32 / max)) // cap the int at the max value
+                    .map(|s| s.map(|x| x as f32 / max))
                     .collect::<Result<Vec<_>, _>>()?
             },
             _ => anyhow::bail!("Unsupported sample format: {:?}", spec.sample_format),
         };
 
+        Self::from_interleaved(samples, file_channels, file_rate, target_rate, target_channels)
+    }
+
+    // Load a FLAC file from disk into the sample buffer
+    pub fn load_flac(path: &Path, target_rate: u32, target_channels: u16) -> anyhow::Result<Self> {
+        let mut reader = claxon::FlacReader::open(path)?;
+        let info = reader.streaminfo();
+        // FLAC is always integer; same full-scale rule as int WAVs
+        let max = 2f32.powi(info.bits_per_sample as i32 - 1);
+        let samples: Vec<f32> = reader
+            .samples()
+            .map(|s| s.map(|x| x as f32 / max))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_interleaved(samples, info.channels as u16, info.sample_rate, target_rate, target_channels)
+    }
+
+    // Load an MP3 file from disk into the sample buffer. Gapless mode has symphonia
+    // drop the encoder delay and padding (from the LAME/Xing header when there is
+    // one), so one-shots start on the hit instead of after ~50ms of priming silence.
+    pub fn load_mp3(path: &Path, target_rate: u32, target_channels: u16) -> anyhow::Result<Self> {
+        use symphonia::core::audio::SampleBuffer as DecodedBuffer;
+        use symphonia::core::errors::Error;
+        use symphonia::core::formats::FormatOptions;
+        use symphonia::core::io::MediaSourceStream;
+        use symphonia::core::probe::Hint;
+
+        let file = std::fs::File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let format_opts = FormatOptions { enable_gapless: true, ..Default::default() };
+        let probed = symphonia::default::get_probe()
+            .format(Hint::new().with_extension("mp3"), stream, &format_opts, &Default::default())?;
+        let mut format = probed.format;
+        let track = format.default_track().ok_or_else(|| anyhow::anyhow!("no audio track"))?;
+        let track_id = track.id;
+        let file_rate = track.codec_params.sample_rate.ok_or_else(|| anyhow::anyhow!("unknown sample rate"))?;
+        let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &Default::default())?;
+
+        // interleaved stereo; frames can switch between mono and stereo mid-file
+        let mut samples: Vec<f32> = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(_)) => continue, // a corrupt frame; skip it like players do
+                Err(e) => return Err(e.into()),
+            };
+            let channels = decoded.spec().channels.count().max(1);
+            let mut buf = DecodedBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            for c in buf.samples().chunks_exact(channels) {
+                samples.push(c[0]);
+                samples.push(if channels > 1 { c[1] } else { c[0] });
+            }
+        }
+        Self::from_interleaved(samples, 2, file_rate, target_rate, target_channels)
+    }
+
+    // Interleaved file samples -> stereo frames at the engine rate
+    fn from_interleaved(
+        samples: Vec<f32>,
+        file_channels: u16,
+        file_rate: u32,
+        target_rate: u32,
+        target_channels: u16,
+    ) -> anyhow::Result<Self> {
         let mut frames: Vec<StereoFrame> = if file_channels == 1 {
             samples
                 .into_iter()
@@ -53,7 +134,7 @@ impl SampleBuffer {
         };
 
         if file_rate != target_rate {
-            frames = resample_linear(&frames, file_rate, target_rate);
+            frames = resample(&frames, file_rate, target_rate);
         }
 
         if target_channels != 2 {
@@ -63,6 +144,37 @@ impl SampleBuffer {
         Ok(Self { data: frames })
     }
 
+    /// Loudest sample in either channel.
+    pub fn peak(&self) -> f32 {
+        self.data.iter().fold(0.0_f32, |m, f| m.max(f.left.abs()).max(f.right.abs()))
+    }
+
+    /// Scale the whole buffer so its loudest sample sits at `target_peak`. Silence
+    /// (or near enough) is left alone rather than blown up.
+    pub fn normalize(&mut self, target_peak: f32) {
+        let peak = self.peak();
+        if !peak.is_finite() || peak < 1e-6 {
+            return;
+        }
+        let gain = target_peak / peak;
+        for f in &mut self.data {
+            f.left *= gain;
+            f.right *= gain;
+        }
+    }
+
+    /// Drop the near-silent tail (every frame after the last one at or above
+    /// `threshold`), keeping a short pad so decays don't get chopped.
+    pub fn trim_trailing_silence(&mut self, threshold: f32) {
+        let last_loud = self.data.iter()
+            .rposition(|f| f.left.abs() >= threshold || f.right.abs() >= threshold);
+        let keep = match last_loud {
+            Some(i) => (i + 1 + TRIM_PAD_FRAMES).min(self.data.len()),
+            None => 1, // all silence; keep one frame so the buffer is never empty
+        };
+        self.data.truncate(keep.max(1));
+    }
+
     pub fn save_wav(&self, path: &Path, sample_rate: u32) -> anyhow::Result<()> {
         let spec = hound::WavSpec {
             channels: 2,
@@ -80,30 +192,99 @@ impl SampleBuffer {
     }
 }
 
-fn resample_linear(frames: &[StereoFrame], source_rate: u32, target_rate: u32) -> Vec<StereoFrame> {
-    // This is a simple linear resampler, we might want to use a better one past the treehacks context
-    if source_rate == target_rate {
-        return frames.to_vec();
-    }
-    let ratio = target_rate as f64 / source_rate as f64;
-    let out_len = (frames.len() as f64 * ratio).ceil() as usize;
-    let mut out = Vec::with_capacity(out_len);
-
-    for i in 0..out_len {
-        // fractional position in the source buffer
-        let src_pos = i as f64 / ratio; // ex. 3.7
-        let idx = src_pos.floor() as usize; // ex. 3
-        let frac = (src_pos - idx as f64) as f32; // ex. 0.7
-        if idx >= frames.len().saturating_sub(1) { // edge case
-            out.push(*frames.last().unwrap_or(&StereoFrame::zero()));
-        } else {
-            let a = frames[idx]; // ex. frame 3
-            let b = frames[idx + 1]; // ex. frame 4
-            out.push(StereoFrame { // blend via frac and linear interpolation
-                left: a.left * (1.0 - frac) + b.left * frac,
-                right: a.right * (1.0 - frac) + b.right * frac,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_wav(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("pocketty-{}-{}.wav", name, std::process::id()))
+    }
+
+    type Writer = hound::WavWriter<std::io::BufWriter<std::fs::File>>;
+
+    // write `samples` as a mono file and read it back the way the loader does
+    fn round_trip(bits: u16, format: hound::SampleFormat, samples: &[f32], write: impl Fn(&mut Writer, f32)) -> Vec<f32> {
+        let path = scratch_wav(&format!("{:?}{}", format, bits));
+        let spec = hound::WavSpec { channels: 1, sample_rate: 48000, bits_per_sample: bits, sample_format: format };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for &s in samples {
+            write(&mut writer, s);
+        }
+        writer.finalize().unwrap();
+        let loaded = SampleBuffer::load_wav(&path, 48000, 2).unwrap();
+        let _ = std::fs::remove_file(&path);
+        loaded.data.iter().map(|f| f.left).collect()
+    }
+
+    #[test]
+    fn int_wavs_reach_full_scale_at_every_depth() {
+        for bits in [8u16, 16, 24, 32] {
+            let max = (1i64 << (bits - 1)) as f64;
+            // full scale both ways, and silence (8-bit is stored unsigned, centred on 128)
+            let got = round_trip(bits, hound::SampleFormat::Int, &[1.0, -1.0, 0.0], |w, s| {
+                let x = if s > 0.0 { max - 1.0 } else { s as f64 * max };
+                w.write_sample(x as i32).unwrap();
             });
+            assert!((got[0] - 1.0).abs() < 0.01, "{}-bit: {}", bits, got[0]);
+            assert_eq!(got[1], -1.0, "{}-bit", bits);
+            assert_eq!(got[2], 0.0, "{}-bit", bits);
         }
     }
-    out
+
+    #[test]
+    fn float_wavs_pass_straight_through() {
+        let samples = [1.0, -1.0, 0.25, 0.0];
+        let got = round_trip(32, hound::SampleFormat::Float, &samples, |w, s| w.write_sample(s).unwrap());
+        assert_eq!(got, samples);
+    }
+
+    #[test]
+    fn flac_fixture_decodes_like_the_same_audio_as_wav() {
+        // tests/fixtures: 512 frames of a 16-bit stereo 440 Hz tone (right = -left) at
+        // 44.1k, once as WAV and once as a two-frame FLAC
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+        for rate in [44100, 48000] {
+            let flac = SampleBuffer::load_flac(&fixtures.join("tone.flac"), rate, 2).unwrap();
+            let wav = SampleBuffer::load_wav(&fixtures.join("tone.wav"), rate, 2).unwrap();
+            assert_eq!(flac.data.len(), wav.data.len());
+            assert!(flac.peak() > 0.4);
+            for (a, b) in flac.data.iter().zip(&wav.data) {
+                assert_eq!((a.left, a.right), (b.left, b.right));
+            }
+        }
+    }
+
+    #[test]
+    fn normalize_scales_to_the_target_and_leaves_silence_alone() {
+        let target = 10f32.powf(-1.0 / 20.0); // -1 dBFS
+        let mut quiet = SampleBuffer::from_frames(vec![
+            StereoFrame { left: 0.1, right: -0.2 },
+            StereoFrame { left: -0.05, right: 0.0 },
+        ]);
+        quiet.normalize(target);
+        assert!((quiet.peak() - target).abs() < 1e-6);
+        assert!((quiet.data[0].left / quiet.data[0].right + 0.5).abs() < 1e-6); // shape kept
+
+        let mut silent = SampleBuffer::from_frames(vec![StereoFrame::default(); 64]);
+        silent.normalize(target);
+        assert!(silent.data.iter().all(|f| f.left == 0.0 && f.right == 0.0));
+    }
+
+    #[test]
+    fn trailing_silence_is_trimmed_to_a_short_pad() {
+        let loud = StereoFrame { left: 0.5, right: 0.5 };
+        let hiss = StereoFrame { left: 0.001, right: -0.001 };
+        let mut frames = vec![loud; 100];
+        frames.extend(vec![hiss; 10 * TRIM_PAD_FRAMES]);
+        let mut buf = SampleBuffer::from_frames(frames);
+        buf.trim_trailing_silence(0.005);
+        assert_eq!(buf.data.len(), 100 + TRIM_PAD_FRAMES);
+
+        // a tail shorter than the pad stays as it is, and all-silence keeps one frame
+        buf.trim_trailing_silence(0.005);
+        assert_eq!(buf.data.len(), 100 + TRIM_PAD_FRAMES);
+        let mut silent = SampleBuffer::from_frames(vec![hiss; 500]);
+        silent.trim_trailing_silence(0.005);
+        assert_eq!(silent.data.len(), 1);
+    }
 }
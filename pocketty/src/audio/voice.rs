@@ -1,11 +1,91 @@
+use serde::{Deserialize, Serialize};
+
 use super::frame::StereoFrame;
 use super::sample_buffer::SampleBuffer;
 
+// Short fade-out near the end to avoid hard clicks (~6ms at 44.1kHz)
+const FADE_SAMPLES: f32 = 256.0;
+// Crossfade across the seam of a live loop region (~1.5ms), shortened for tiny regions
+const LOOP_XFADE_SAMPLES: f32 = 64.0;
+
 #[inline]
 fn lerp(a: f32, b: f32, t: f32) -> f32 {
     a * (1.0 - t) + b * t
 }
 
+// Catmull-Rom through p1..p2, with p0/p3 as the outer neighbours
+#[inline]
+pub(super) fn cubic(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+    let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+    let c = -0.5 * p0 + 0.5 * p2;
+    ((a * t + b) * t + c) * t + p1
+}
+
+/// How a voice reads between samples when repitched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    None,   // nearest sample: crunchy, aliased repitch
+    Linear, // duller when pitched far from the original
+    #[default]
+    Cubic,  // Catmull-Rom over four neighbours, clean on big pitch shifts
+}
+
+impl Interpolation {
+    pub fn next(self) -> Self {
+        match self {
+            Interpolation::None => Interpolation::Linear,
+            Interpolation::Linear => Interpolation::Cubic,
+            Interpolation::Cubic => Interpolation::None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Interpolation::None => "RAW",
+            Interpolation::Linear => "LIN",
+            Interpolation::Cubic => "CUB",
+        }
+    }
+}
+
+/// Amplitude envelope, times in output frames. The default is a plain gate: straight
+/// in at full level, out over the anti-click fade.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32, // level (0.0-1.0) held after the decay
+    pub release: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self { attack: 0.0, decay: 0.0, sustain: 1.0, release: 0.0 }
+    }
+}
+
+impl Envelope {
+    // attack/decay/sustain level `t` frames into the voice
+    fn level(&self, t: f32) -> f32 {
+        if t < self.attack {
+            return t / self.attack;
+        }
+        let t = t - self.attack;
+        if t < self.decay {
+            return 1.0 - (1.0 - self.sustain) * t / self.decay;
+        }
+        self.sustain
+    }
+}
+
+/// Equal-power pan law for `pan` in -1.0 (left) .. 1.0 (right). Scaled so the centre
+/// is unity, which keeps unpanned sounds exactly as loud as they were.
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos() * std::f32::consts::SQRT_2, angle.sin() * std::f32::consts::SQRT_2)
+}
+
 #[derive(Clone, Debug)]
 pub struct Voice {
     pub pos: f32,
@@ -16,10 +96,19 @@ pub struct Voice {
     trim_start: usize,
     length: usize,
     stutter_period: Option<u32>,
+    hold: bool, // drone: loop the whole region until stopped
+    interp: Interpolation,
+    loop_region: Option<(f32, f32)>, // live loop roll: [start, end) within the region, wraps with a crossfade
+    sustaining: bool, // loop_region is the sound's sustain loop rather than a roll, so a note-off lets go of it
+    envelope: Envelope,
+    fade_out: Option<(usize, f32)>, // (frames left, total) of a requested stop fade or release
     frames_rendered: usize, // total output frames rendered (bounds stutter lifetime)
+    looped_frames: usize, // of those, how many went round a loop region; they don't count against the lifetime
+    pan_gains: (f32, f32), // (left, right), from the pan position
 }
 
 impl Voice {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         trim_start: usize,
         length: usize,
@@ -27,12 +116,23 @@ impl Voice {
         gain: f32,
         reverse: bool,
         stutter_period: Option<u32>,
+        hold: bool,
+        interp: Interpolation,
+        envelope: Envelope,
+        sustain_loop: Option<(usize, usize)>,
+        pan: f32,
     ) -> Self {
-        let pos = if reverse && length > 0 {
-            (length - 1) as f32
+        // a reversed stutter plays its slice backwards, so it starts at the slice's end
+        let span = stutter_period.map_or(length, |p| (p as usize).min(length));
+        let pos = if reverse && span > 0 {
+            (span - 1) as f32
         } else {
             0.0
         };
+        // a sustain loop plays in from the top like any hit, then goes round until released
+        let loop_region = sustain_loop
+            .map(|(start, end)| (start.min(length) as f32, end.min(length) as f32))
+            .filter(|(start, end)| end - start >= 2.0);
         Self {
             pos,
             pitch,
@@ -42,7 +142,85 @@ impl Voice {
             trim_start,
             length,
             stutter_period,
+            hold,
+            interp,
+            sustaining: loop_region.is_some(),
+            loop_region,
+            envelope,
+            fade_out: None,
             frames_rendered: 0,
+            looped_frames: 0,
+            pan_gains: pan_gains(pan),
+        }
+    }
+
+    /// Ramp to silence over FADE_SAMPLES and then deactivate, instead of cutting off mid-sample.
+    pub fn request_fade_out(&mut self) {
+        self.fade_over(FADE_SAMPLES);
+    }
+
+    /// Note-off: ramp down over the envelope's release (never quicker than the anti-click
+    /// fade), then deactivate.
+    pub fn release(&mut self) {
+        self.fade_over(self.envelope.release.max(FADE_SAMPLES));
+    }
+
+    fn fade_over(&mut self, frames: f32) {
+        if self.fade_out.is_none() {
+            self.fade_out = Some((frames as usize, frames));
+        }
+    }
+
+    /// False until the voice has rendered its first frame.
+    pub fn has_started(&self) -> bool {
+        self.frames_rendered > 0
+    }
+
+    /// Length of the slice a stutter loops: starts at trim_start, never longer than the region.
+    fn stutter_slice(&self) -> Option<usize> {
+        self.stutter_period
+            .map(|p| (p as usize).min(self.length))
+            .filter(|&p| p > 0)
+    }
+
+    /// Jump a stuttering voice back to the start of its slice (no-op otherwise),
+    /// so stutters triggered on different steps stay in phase with the grid.
+    pub fn resync_stutter(&mut self) {
+        if let Some(slice) = self.stutter_slice() {
+            self.pos = if self.reverse { (slice - 1) as f32 } else { 0.0 };
+        }
+    }
+
+    /// Lock the voice into repeating [start, end) of its region until cleared.
+    /// Regions too short to crossfade across are ignored.
+    pub fn set_loop_region(&mut self, start: f32, end: f32) {
+        let max = self.length as f32;
+        let (start, end) = (start.clamp(0.0, max), end.clamp(0.0, max));
+        if start.is_nan() || end.is_nan() || end - start < 2.0 {
+            return;
+        }
+        if self.pos < start || self.pos >= end {
+            self.pos = if self.reverse { end - 1.0 } else { start };
+        }
+        self.loop_region = Some((start, end));
+        self.sustaining = false;
+    }
+
+    /// Let a loop-rolled (or sustain-looped) voice carry on playing out from where it is.
+    pub fn clear_loop_region(&mut self) {
+        self.loop_region = None;
+        self.sustaining = false;
+    }
+
+    /// Note-off for a sustain loop: leave it and play out the rest of the region, under
+    /// the envelope's release if there is one. A loop roll (or a plain voice) carries on.
+    pub fn release_sustain(&mut self) {
+        if !self.sustaining {
+            return;
+        }
+        self.clear_loop_region();
+        if self.envelope.release > 0.0 {
+            self.release();
         }
     }
 
@@ -70,6 +248,18 @@ impl Voice {
         }
 
         let data = &buffer.data;
+        let slice = self.stutter_slice();
+        // the span reads are confined to, and whether reads past its end wrap to its start
+        let span = slice.unwrap_or(self.length);
+        let looping = slice.is_some() || self.hold;
+        // a reversed voice starts at the far end of its span, which new() could only guess
+        // from the requested length. If the buffer is shorter than that, reads were pinned
+        // to the last frame until pos came back into range (a flat stretch, then a click).
+        if self.reverse && self.frames_rendered == 0 {
+            self.pos = self.pos.min((span - 1) as f32);
+        }
+        // a loop roll keeps the voice alive like a drone until it's cleared
+        let sustained = self.hold || self.loop_region.is_some();
 
         for frame in out.iter_mut() { // for each frame in the output buffer
             if !self.active {
@@ -77,10 +267,30 @@ impl Voice {
             }
 
             // stutter blows up without this
-            if self.frames_rendered >= self.length {
+            let lifetime = self.frames_rendered - self.looped_frames;
+            if !sustained && lifetime >= self.length {
                 self.active = false;
                 break;
             }
+            // loop roll wrap: jump back by the region's length once we run off its far end
+            if let Some((start, end)) = self.loop_region {
+                let len = end - start;
+                if self.reverse && self.pos < start {
+                    self.pos += len;
+                } else if !self.reverse && self.pos >= end {
+                    self.pos -= len;
+                }
+            }
+
+            // held drones wrap around the region instead of ending
+            if self.hold && self.stutter_period.is_none() {
+                let len = self.length as f32;
+                if self.reverse && self.pos < 0.0 {
+                    self.pos += len;
+                } else if !self.reverse && self.pos >= len {
+                    self.pos -= len;
+                }
+            }
             if self.stutter_period.is_none() {
                 if self.reverse && self.pos < 0.0 {
                     self.active = false;
@@ -93,23 +303,28 @@ impl Voice {
             }
 
             // read sample at current position
-            let read_pos = self.pos.clamp(0.0, (self.length as f32) - 1.0);
-            let i = read_pos as usize;
-            if i >= self.length {
-                self.active = false;
-                break;
+            if slice.is_some() {
+                // stutter wrap: loop the slice, even if pitch jumped us past either end
+                self.pos = self.pos.rem_euclid(span as f32);
+            }
+            let mut sample = self.read(data, span, looping, self.pos);
+            // approaching the loop seam: fade into what's just past the other end
+            if let Some((start, end)) = self.loop_region {
+                let len = end - start;
+                let xfade = LOOP_XFADE_SAMPLES.min(len * 0.5);
+                let (dist, twin) = if self.reverse {
+                    (self.pos - start, self.pos + len)
+                } else {
+                    (end - self.pos, self.pos - len)
+                };
+                if dist < xfade {
+                    let t = (dist / xfade).max(0.0);
+                    let other = self.read(data, span, looping, twin);
+                    sample.left = lerp(other.left, sample.left, t);
+                    sample.right = lerp(other.right, sample.right, t);
+                }
             }
-            let frac = read_pos - i as f32;
-            let idx = self.trim_start + i;
-            let s0 = data[idx];
-            let s1 = data.get(idx + 1).copied().unwrap_or(s0);
-            let sample = StereoFrame {
-                left: lerp(s0.left, s1.left, frac),
-                right: lerp(s0.right, s1.right, frac),
-            };
 
-            // Short fade-out near the end to avoid hard clicks (~6ms at 44.1kHz)
-            const FADE_SAMPLES: f32 = 256.0;
             // Positional fade (end of sample region)
             let pos_dist = if self.reverse {
                 self.pos
@@ -118,14 +333,31 @@ impl Voice {
             };
             let pos_fade = (pos_dist / FADE_SAMPLES).min(1.0);
             // Lifetime fade (end of stutter lifetime)
-            let life_dist = self.length.saturating_sub(self.frames_rendered) as f32;
+            let life_dist = self.length.saturating_sub(lifetime) as f32;
             let life_fade = (life_dist / FADE_SAMPLES).min(1.0);
-            let fade = pos_fade.min(life_fade);
+            let mut fade = if sustained { 1.0 } else { pos_fade.min(life_fade) };
+            // Requested stop: ramp down, then deactivate
+            if let Some((left, total)) = self.fade_out {
+                if left == 0 {
+                    self.active = false;
+                    break;
+                }
+                fade = fade.min(left as f32 / total);
+                self.fade_out = Some((left - 1, total));
+            }
+
+            // Envelope. Unless something stops the voice first, the release is timed to run
+            // out together with the region (or the stutter's lifetime).
+            let mut env = self.envelope.level(self.frames_rendered as f32);
+            if !sustained && self.envelope.release > 0.0 {
+                let frames_left = (pos_dist / self.pitch.max(f32::EPSILON)).min(life_dist);
+                env *= (frames_left / self.envelope.release).min(1.0);
+            }
 
             // gain + fade
-            let g = self.gain * fade;
-            frame.left += sample.left * g;
-            frame.right += sample.right * g;
+            let g = self.gain * fade * env;
+            frame.left += sample.left * g * self.pan_gains.0;
+            frame.right += sample.right * g * self.pan_gains.1;
 
             // advance position
             if self.reverse {
@@ -134,23 +366,207 @@ impl Voice {
                 self.pos += self.pitch;
             }
 
-            // stutter wrap
-            if let Some(period) = self.stutter_period {
-                let p = (period as f32).min(self.length as f32);
-                if p > 0.0 {
-                    if self.reverse {
-                        while self.pos < 0.0 {
-                            self.pos += p;
-                        }
-                    } else {
-                        while self.pos >= p {
-                            self.pos -= p;
-                        }
-                    }
+            self.frames_rendered += 1;
+            if self.loop_region.is_some() {
+                self.looped_frames += 1;
+            }
+        }
+    }
+
+    /// Interpolated frame at `pos` within the span. Neighbours wrap across the seam
+    /// when looping, rather than reading past the slice.
+    fn read(&self, data: &[StereoFrame], span: usize, looping: bool, pos: f32) -> StereoFrame {
+        let read_pos = pos.clamp(0.0, (span as f32) - 1.0);
+        let i = read_pos as isize;
+        let frac = read_pos - i as f32;
+        let at = |k: isize| -> StereoFrame {
+            let k = if looping {
+                k.rem_euclid(span as isize)
+            } else {
+                k.clamp(0, span as isize - 1)
+            };
+            data[self.trim_start + k as usize]
+        };
+        match self.interp {
+            Interpolation::None => at(if frac < 0.5 { i } else { i + 1 }),
+            Interpolation::Linear => {
+                let (s0, s1) = (at(i), at(i + 1));
+                StereoFrame {
+                    left: lerp(s0.left, s1.left, frac),
+                    right: lerp(s0.right, s1.right, frac),
+                }
+            }
+            Interpolation::Cubic => {
+                let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+                StereoFrame {
+                    left: cubic(p0.left, p1.left, p2.left, p3.left, frac),
+                    right: cubic(p0.right, p1.right, p2.right, p3.right, frac),
                 }
             }
+        }
+    }
+}
 
-            self.frames_rendered += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIM: usize = 100;
+    const LEN: usize = 1024;
+
+    // a rising ramp (left channel only), so every frame says where it was read from
+    fn ramp() -> Vec<StereoFrame> {
+        (0..2 * LEN)
+            .map(|i| StereoFrame { left: i as f32 / (2 * LEN) as f32, right: 0.0 })
+            .collect()
+    }
+
+    fn render(voice: &mut Voice, frames: usize) -> Vec<f32> {
+        let mut out = vec![StereoFrame::default(); frames];
+        voice.render_into(&SampleBuffer::from_frames(ramp()), &mut out);
+        out.iter().map(|f| f.left).collect()
+    }
+
+    // the region cut out of the ramp's middle, played once each way
+    fn play(reverse: bool) -> (Vec<StereoFrame>, Vec<f32>) {
+        let mut voice = Voice::new(
+            TRIM, LEN, 1.0, 1.0, reverse, None, false,
+            Interpolation::default(), Envelope::default(), None, 0.0,
+        );
+        let out = render(&mut voice, LEN + 16);
+        assert!(!voice.active);
+        (ramp(), out)
+    }
+
+    fn stutter(length: usize, period: u32) -> Voice {
+        Voice::new(
+            TRIM, length, 1.0, 1.0, false, Some(period), false,
+            Interpolation::default(), Envelope::default(), None, 0.0,
+        )
+    }
+
+    #[test]
+    fn reverse_plays_the_trim_region_backwards() {
+        let (ramp, forward) = play(false);
+        let (_, reversed) = play(true);
+
+        // starts on the region's last frame, and stops after exactly LEN frames
+        assert!((reversed[0] - ramp[TRIM + LEN - 1].left).abs() < 1e-5);
+        assert!(reversed[LEN..].iter().all(|&s| s == 0.0));
+
+        // away from the faded edges it's the forward pass, frame for frame, mirrored
+        let fade = FADE_SAMPLES as usize;
+        for k in fade..LEN - fade {
+            assert!((reversed[k] - forward[LEN - 1 - k]).abs() < 1e-5, "frame {}", k);
+        }
+        // and the fade sits at the end it now finishes on
+        assert!(reversed[LEN - 1].abs() < reversed[LEN - fade].abs());
+    }
+
+    #[test]
+    fn stutter_repeats_a_slice_starting_at_trim_start() {
+        let ramp = ramp();
+        let out = render(&mut stutter(LEN, 64), LEN / 2);
+        for (k, &s) in out.iter().enumerate() {
+            assert!((s - ramp[TRIM + k % 64].left).abs() < 1e-6, "frame {}", k);
         }
+
+        // a period longer than the region loops the region, and never reads past it
+        let out = render(&mut stutter(300, 5000), 600);
+        let last = ramp[TRIM + 299].left;
+        assert!(out.iter().all(|&s| s <= last + 1e-6));
+        assert!((out[0] - ramp[TRIM].left).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sustain_loop_holds_until_released_then_plays_out() {
+        let ramp = ramp();
+        let mut voice = Voice::new(
+            TRIM, LEN, 1.0, 1.0, false, None, false,
+            Interpolation::default(), Envelope::default(), Some((256, 512)), 0.0,
+        );
+        // four times the region's length in, still going round the loop
+        let held = render(&mut voice, 4 * LEN);
+        assert!(voice.active);
+        let (lo, hi) = (ramp[TRIM + 256].left, ramp[TRIM + 511].left);
+        assert!(held[3 * LEN..].iter().all(|&s| s >= lo - 1e-6 && s <= hi + 1e-6));
+
+        // let go: carries on past the loop end to the end of the region, then stops
+        voice.clear_loop_region();
+        let tail = render(&mut voice, 2 * LEN);
+        assert!(!voice.active);
+        assert!(tail.iter().any(|&s| s > ramp[TRIM + 700].left));
+    }
+
+    #[test]
+    fn a_note_off_lets_go_of_a_sustain_loop_but_not_a_loop_roll() {
+        let mut rolled = Voice::new(
+            TRIM, LEN, 1.0, 1.0, false, None, false,
+            Interpolation::default(), Envelope::default(), None, 0.0,
+        );
+        rolled.set_loop_region(0.0, 256.0);
+        rolled.release_sustain();
+        render(&mut rolled, 4 * LEN);
+        assert!(rolled.active);
+
+        // with a release, the voice goes out over it, well before the 512+ frames left in the region
+        let envelope = Envelope { release: 300.0, ..Envelope::default() };
+        let mut sustained = Voice::new(
+            TRIM, LEN, 1.0, 1.0, false, None, false,
+            Interpolation::default(), envelope, Some((256, 512)), 0.0,
+        );
+        render(&mut sustained, 2 * LEN);
+        sustained.release_sustain();
+        let tail = render(&mut sustained, 400);
+        assert!(!sustained.active);
+        assert!(tail[0] > 0.0 && tail[300..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn hard_left_pan_leaves_the_right_channel_silent() {
+        let buffer = SampleBuffer::from_frames(vec![StereoFrame { left: 0.5, right: 0.5 }; LEN]);
+        let pan = |pan: f32| {
+            let mut voice = Voice::new(
+                0, LEN, 1.0, 1.0, false, None, false,
+                Interpolation::default(), Envelope::default(), None, pan,
+            );
+            let mut out = vec![StereoFrame::default(); 64];
+            voice.render_into(&buffer, &mut out);
+            out[32]
+        };
+        let left = pan(-1.0);
+        assert!(left.right.abs() < 1e-6, "right {}", left.right);
+        assert!(left.left > 0.5);
+        // equal power: centre is unity, so a hard pan carries the same energy on one side
+        let centre = pan(0.0);
+        assert!((centre.left - 0.5).abs() < 1e-6);
+        assert!((left.left.powi(2) - 2.0 * centre.left.powi(2)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cubic_repitches_a_sine_closer_than_linear() {
+        let step = 2.0 * std::f32::consts::PI * 2000.0 / 48000.0;
+        let sine: Vec<StereoFrame> = (0..4 * LEN)
+            .map(|i| {
+                let s = (i as f32 * step).sin();
+                StereoFrame { left: s, right: s }
+            })
+            .collect();
+        let buffer = SampleBuffer::from_frames(sine);
+        // worst error against the true sine at the fractional read positions, clear of the fades
+        let worst = |interp: Interpolation| {
+            let pitch = 1.37;
+            let mut voice = Voice::new(
+                0, 4 * LEN, pitch, 1.0, false, None, false,
+                interp, Envelope::default(), None, 0.0,
+            );
+            let mut out = vec![StereoFrame::default(); LEN];
+            voice.render_into(&buffer, &mut out);
+            out.iter().enumerate().skip(8)
+                .map(|(k, f)| (f.left - (k as f32 * pitch * step).sin()).abs())
+                .fold(0.0f32, f32::max)
+        };
+        let (linear, cubic) = (worst(Interpolation::Linear), worst(Interpolation::Cubic));
+        assert!(cubic < linear / 4.0, "cubic {} vs linear {}", cubic, linear);
     }
 }
@@ -1,4 +1,4 @@
-pub use crate::audio::{EffectSpec, SampleBuffer, SampleId};
+pub use crate::audio::{EffectSpec, Envelope, Interpolation, SampleBuffer, SampleId, StereoFrame};
 
 #[derive(Clone, Debug)]
 pub struct TriggerParams {
@@ -10,6 +10,14 @@ pub struct TriggerParams {
     pub effect_chain: Vec<EffectSpec>,
     pub reverse: bool,                         // reverse effect
     pub stutter_period_samples: Option<u32>,   // loop effects
+    pub hold: bool,                            // loop the region until stopped (drone)
+    pub interp: Interpolation,                 // how the voice reads between samples
+    pub envelope: Envelope,                    // amplitude envelope, in frames
+    pub loop_start: Option<usize>,             // sustain loop, frames from trim_start: the voice goes
+    pub loop_end: Option<usize>,               // round [start, end) until released
+    pub pan: f32,                              // -1.0 (left) .. 1.0 (right)
+    pub choke_group: Option<u8>,               // starting this voice fades out the others in its group
+    pub cue: bool,                             // goes to the headphone cue output (if there is one) instead of the mix
 }
 
 #[derive(Clone, Debug)]
@@ -18,6 +26,10 @@ pub enum AudioCommand {
     // register a preloaded buffer (see sample_loader.rs), then send that to 
     // the engine
     RegisterSample { id: SampleId, buffer: SampleBuffer },  
+
+    // Drop a buffer nothing points at anymore (slot reloaded/cleared), so
+    // long sessions don't pile up every sample ever loaded
+    UnregisterSample { id: SampleId },
     
     // The engine then uses the sample id to trigger the sound 
     Trigger(TriggerParams),
@@ -25,9 +37,61 @@ pub enum AudioCommand {
     StartRecording { sample_id: SampleId },
     StopRecording,
 
+    // Capture the master output (fx and all) into `buffer` as a new sample, handed back
+    // like a finished recording. As with StartMasterCapture the sender allocates it, and
+    // its capacity is how many frames get grabbed.
+    GrabOutput { sample_id: SampleId, buffer: Vec<StereoFrame> },
+
     // Scatch effects
     SetPlaybackPosition { sample_id: SampleId, position: f32 },
 
-    // Kill all playing voices immediately (used when stopping playback)
+    // Live loop roll: the newest voice of this sample repeats [start, end) of its region
+    // (frames from its trim start) until cleared, then plays on from there
+    SetLoopRegion { sample_id: SampleId, start: f32, end: f32 },
+    ClearLoopRegion { sample_id: SampleId },
+
+    // Sound filter turned while the newest voice of this sample is still sounding
+    SetVoiceFilter { sample_id: SampleId, cutoff_hz: f32, resonance: f32 },
+
+    // Note-off: voices of this sample stop going round their sustain loop and play out the
+    // tail, under the envelope's release if it has one. Unlike ClearLoopRegion, a loop roll
+    // on the sample keeps going.
+    ReleaseVoice { sample_id: SampleId },
+
+    // Quickly fade out all playing voices (used when stopping playback)
     StopAllVoices,
+
+    // Fade out only the voices playing this sample (hold, etc.)
+    StopVoices { sample_id: SampleId },
+
+    // Snap every stuttering voice back to its slice start (sent on step boundaries)
+    SyncStutter,
+
+    // Sidechain: drop every voice of `target` by `amount` (0-1), recovering over release_ms
+    Duck { target: SampleId, amount: f32, release_ms: f32 },
+
+    // ~20 Hz high-pass on the master output to strip DC offset (on by default)
+    SetDcFilter { enabled: bool },
+
+    // Input peak an armed recording waits for before it starts capturing (0.001-0.5)
+    SetRecordThreshold(f32),
+
+    // Bring finished mic recordings up (or down) to -1 dBFS peak
+    SetNormalizeRecordings(bool),
+
+    // Cut the near-silent tail off finished mic recordings
+    SetTrimRecordings(bool),
+
+    // Polyphony cap: a hit past it steals (quickly fades) the oldest voice
+    SetMaxVoices(usize),
+
+    // Hear the input while a recording is armed or capturing
+    SetInputMonitor(bool),
+    SetMonitorGain(f32), // 0.0-1.0
+
+    // Record the master output as it plays (a jam) into `buffer`, allocated by the sender
+    // so the audio thread never has to. Its capacity is the length cap: the take comes
+    // back through AudioHandle::poll_master_capture on stop, or on its own once it's full.
+    StartMasterCapture { buffer: Vec<StereoFrame> },
+    StopMasterCapture,
 }
@@ -1,26 +1,149 @@
 use std::path::{Path, PathBuf};
+use crossbeam_channel::{Receiver, Sender};
 use crate::audio::{next_sample_id, SampleId, SampleBuffer};
 
-// Load a WAV from disk, prepare for registration with the engine
+const AUDIO_EXTENSIONS: [&str; 3] = ["wav", "flac", "mp3"];
+
+// Load a WAV, FLAC or MP3 from disk, prepare for registration with the engine
 pub fn load(path: &Path, target_rate: u32) -> anyhow::Result<(SampleId, SampleBuffer)> {
     let id = next_sample_id();
-    let buffer = SampleBuffer::load_wav(path, target_rate, 2)?;
+    let buffer = if has_extension(path, "flac") {
+        SampleBuffer::load_flac(path, target_rate, 2)?
+    } else if has_extension(path, "mp3") {
+        SampleBuffer::load_mp3(path, target_rate, 2)?
+    } else {
+        SampleBuffer::load_wav(path, target_rate, 2)?
+    };
     Ok((id, buffer))
 }
 
+type Loaded = (PathBuf, anyhow::Result<(SampleId, SampleBuffer)>);
+
+/// Loads browser auditions on a worker thread so a long file never stalls the UI.
+/// Only the newest request counts: files the cursor has already moved past are skipped.
+pub struct AuditionLoader {
+    requests: Sender<(PathBuf, u32)>,
+    loaded: Receiver<Loaded>,
+    wanted: Option<PathBuf>,
+}
+
+impl AuditionLoader {
+    pub fn spawn() -> Self {
+        let (requests, request_rx) = crossbeam_channel::unbounded::<(PathBuf, u32)>();
+        let (loaded_tx, loaded) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            while let Ok(mut request) = request_rx.recv() {
+                while let Ok(newer) = request_rx.try_recv() {
+                    request = newer;
+                }
+                let result = load(&request.0, request.1);
+                if loaded_tx.send((request.0, result)).is_err() {
+                    return; // main loop is gone
+                }
+            }
+        });
+        Self { requests, loaded, wanted: None }
+    }
+
+    /// Start loading `path`; whatever was asked for before is no longer wanted.
+    pub fn request(&mut self, path: PathBuf, target_rate: u32) {
+        self.wanted = Some(path.clone());
+        let _ = self.requests.send((path, target_rate));
+    }
+
+    /// Drop the pending request (the browser closed).
+    pub fn cancel(&mut self) {
+        self.wanted = None;
+    }
+
+    /// The wanted file, once it has loaded. A file that fails to load is dropped.
+    pub fn poll(&mut self) -> Option<(SampleId, SampleBuffer)> {
+        while let Ok((path, result)) = self.loaded.try_recv() {
+            if self.wanted.as_ref() == Some(&path) {
+                self.wanted = None;
+                return result.ok();
+            }
+        }
+        None
+    }
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
+
+const MAX_INDEX_DEPTH: usize = 4; // how many folders deep we look below the project dir
+
 // Auto-assigning samples to slots at startup, will be expanded later.
-pub fn index_wav_in_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+// Walks subfolders too (drums/, vox/, ...) but never into .pocketty/ or other hidden dirs.
+pub fn index_audio_in_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    collect_audio(dir, 0, &mut paths)?;
+
+    // sort by path relative to the root so "drums/kick.wav" < "vox/a.wav" regardless of walk order
+    paths.sort_by_cached_key(|p| {
+        p.strip_prefix(dir)
+            .unwrap_or(p)
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    Ok(paths) // returns the sorted paths
+}
+
+fn collect_audio(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if path.is_dir() {
+            // an unreadable subfolder shouldn't hide everything else
+            if !hidden && depth < MAX_INDEX_DEPTH {
+                let _ = collect_audio(&path, depth + 1, out);
+            }
+        } else if path.is_file() && AUDIO_EXTENSIONS.iter().any(|ext| has_extension(&path, ext)) {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+// The .mid file a pattern import reads: first by name in the project dir (not subfolders)
+pub fn first_midi_in_dir(dir: &Path) -> anyhow::Result<PathBuf> {
     let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
         .filter_map(|e| e.ok())
         .map(|e| e.path())
-        .filter(|p| p.is_file() && p.extension().map_or(false, |e| e.eq_ignore_ascii_case("wav")))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("mid")))
         .collect();
+    paths.sort();
+    paths.into_iter().next().ok_or_else(|| anyhow::anyhow!("no .mid file in {}", dir.display()))
+}
 
-    paths.sort_by_cached_key(|p| {
-        p.file_name()
-            .map(|n| n.to_string_lossy().into_owned())
-            .unwrap_or_default()
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(paths) // returns the sorted paths
+    #[test]
+    fn auditions_load_in_the_background_and_only_the_last_one_counts() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+        let mut loader = AuditionLoader::spawn();
+        loader.request(fixtures.join("tone.wav"), 48000);
+        loader.request(fixtures.join("tone.flac"), 44100); // the cursor moved on
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let (_, buffer) = loop {
+            if let Some(loaded) = loader.poll() {
+                break loaded;
+            }
+            assert!(std::time::Instant::now() < deadline, "audition never arrived");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(buffer.data.len(), 512); // the flac, at its own rate
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(loader.poll().is_none()); // the wav, if it loaded at all, is stale
+
+        loader.request(fixtures.join("tone.wav"), 48000);
+        loader.cancel();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(loader.poll().is_none());
+    }
 }
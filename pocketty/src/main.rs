@@ -5,16 +5,22 @@ mod audio;
 mod loader;
 mod middle;
 mod pipeline;
+mod osc;
+#[cfg(feature = "midi")]
+mod midi;
 
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use crossterm::terminal;
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 
 use middle::Middle;
 use pipeline::persistence;
-use shared::InputEvent;
+use shared::{DeviceChoice, DeviceMenu, InputEvent, SampleBrowser};
+
+// unsaved edits get written this often, so a crash or a closed terminal loses at most this much
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
 
 fn main() {
     if let Err(e) = run() {
@@ -23,7 +29,78 @@ fn main() {
     }
 }
 
+// pocketty [project_dir] [--start-slot N] [--max-load M] [--max-voices V] [--normalize-samples] [--osc-port P] [--input NAME] [--monitor-same-device] [--list-midi] [--list-projects]
+// with the midi feature, POCKETTY_MIDI_IN picks the controller by (part of) its port name
+struct Args {
+    project_dir: Option<PathBuf>,
+    start_slot: usize, // first slot the folder's WAVs are auto-loaded into
+    max_load: usize,   // how many get auto-loaded; slots outside that range keep their recordings
+    max_voices: Option<usize>, // polyphony cap; the engine's default when unset
+    normalize_samples: bool, // bring newly loaded files to a common peak (raw levels otherwise)
+    osc_port: Option<u16>, // UDP port for the OSC listener (or POCKETTY_OSC_PORT); off when unset
+    input_device: Option<String>, // input to pin by name (or POCKETTY_INPUT); system default when unset
+    // monitor even when input and output look like one device; ALSA calls both "default"
+    // whatever hardware is behind them, and headphones don't feed back anyway
+    monitor_same_device: bool,
+    list_projects: bool, // print the project save slots in use and exit
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        project_dir: None,
+        start_slot: 0,
+        max_load: shared::NUM_SLOTS,
+        max_voices: None,
+        normalize_samples: false,
+        osc_port: std::env::var("POCKETTY_OSC_PORT").ok().and_then(|p| p.parse().ok()),
+        input_device: std::env::var("POCKETTY_INPUT").ok(),
+        monitor_same_device: false,
+        list_projects: false,
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(arg) = it.next() {
+        let mut number = |flag: &str| -> anyhow::Result<usize> {
+            it.next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("{} needs a number", flag))
+        };
+        match arg.as_str() {
+            "--start-slot" => args.start_slot = number("--start-slot")?.min(shared::NUM_SLOTS),
+            "--max-load" => args.max_load = number("--max-load")?,
+            "--max-voices" => args.max_voices = Some(number("--max-voices")?),
+            "--normalize-samples" => args.normalize_samples = true,
+            "--list-projects" => args.list_projects = true,
+            "--monitor-same-device" => args.monitor_same_device = true,
+            "--osc-port" => {
+                let port = number("--osc-port")?;
+                args.osc_port = Some(u16::try_from(port).map_err(|_| anyhow::anyhow!("--osc-port out of range"))?);
+            }
+            "--input" => args.input_device = Some(it.next().ok_or_else(|| anyhow::anyhow!("--input needs a device name"))?),
+            #[cfg(feature = "midi")]
+            "--list-midi" => {
+                for port in midi::list_midi_inputs() {
+                    println!("{}", port);
+                }
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") => anyhow::bail!("unknown flag {}", arg),
+            _ => args.project_dir = Some(PathBuf::from(arg)),
+        }
+    }
+    Ok(args)
+}
+
 fn run() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    if args.list_projects {
+        // numbered like the pads that open them; * is the one that opens next
+        let dir = args.project_dir.unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+        let active = persistence::load_active_slot(&dir).unwrap_or(0);
+        for slot in persistence::list_project_slots(&dir) {
+            println!("{}{}", slot + 1, if slot == active { " *" } else { "" });
+        }
+        return Ok(());
+    }
     terminal::enable_raw_mode()?;
     // Enable keyboard enhancement for real press/release detection.
     // Falls back gracefully if the terminal doesn't support it.
@@ -35,11 +112,22 @@ fn run() -> anyhow::Result<()> {
     );
     let _guard = RawModeGuard; // auto drops when out of scope
     let mut audio = audio::start_audio()?;
-    let project_dir: PathBuf = std::env::args()
-        .nth(1)
-        .map(PathBuf::from)
+    if args.monitor_same_device {
+        audio.set_monitor_same_device(true);
+    }
+    if let Some(name) = &args.input_device {
+        // not there (unplugged, renamed): stay on the default, the LCD shows which one it is
+        let _ = audio.set_input_device_by_name(name);
+    }
+    let osc_rx = args.osc_port.map(osc::spawn_listener).transpose()?;
+    // a controller is optional: no port (or none matching POCKETTY_MIDI_IN) just means no MIDI
+    #[cfg(feature = "midi")]
+    let midi_in = midi::spawn_listener(std::env::var("POCKETTY_MIDI_IN").ok().as_deref()).ok();
+    let project_dir: PathBuf = args.project_dir
         .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
-    let state = persistence::load_project(&project_dir)
+    // reopen whichever save slot was in use last time
+    let mut project_slot = persistence::load_active_slot(&project_dir).unwrap_or(0);
+    let state = persistence::load_project_slot(&project_dir, project_slot)
         .unwrap_or_default();
     // remember previously recorded samples
     let saved_paths: Vec<String> = state.sounds.iter()
@@ -47,21 +135,35 @@ fn run() -> anyhow::Result<()> {
         .collect();
     let mut middle = Middle::with_state(state);
     middle.set_input_device_name(audio.current_input_name());
+    middle.set_sample_rate(audio.sample_rate());
+    middle.set_normalize_on_load(args.normalize_samples);
 
-    const SAMPLE_RATE: u32 = 44100;
-    let wav_paths = loader::sample_loader::index_wav_in_dir(&project_dir)
+    let sample_rate = audio.sample_rate(); // samples load at the engine's rate so they keep their pitch
+    let wav_paths = loader::sample_loader::index_audio_in_dir(&project_dir)
         .unwrap_or_default();
-    let num_loaded = wav_paths.len().min(shared::NUM_SOUNDS); // always refresh from disk
-    for (slot, path) in wav_paths.into_iter().take(shared::NUM_SOUNDS).enumerate() {
-        if let Ok(cmd) = middle.load_sample_into_slot(slot as u8, &path, SAMPLE_RATE) {
+    // slots.toml pins always win, wherever they are. The rest of the WAVs fill the
+    // unpinned slots in sort order, but only within --start-slot/--max-load.
+    let pinned = persistence::load_slot_manifest(&project_dir).unwrap_or_default();
+    let mut unpinned = wav_paths.into_iter()
+        .filter(|p| !pinned.values().any(|q| q == p));
+    let auto_slots = args.start_slot..args.start_slot.saturating_add(args.max_load).min(shared::NUM_SLOTS);
+    for slot in 0..shared::NUM_SLOTS { // always refresh from disk, filling banks in order
+        let auto = auto_slots.contains(&slot);
+        let path = match pinned.get(&slot) {
+            Some(path) => Some(path.clone()),
+            None if auto => unpinned.next(),
+            None => continue, // reserved: keeps whatever the project had there
+        };
+        if let Some(path) = path {
+            if let Ok(cmds) = middle.load_sample_into_slot(slot as u8, &path, sample_rate) {
+                cmds.into_iter().for_each(|cmd| audio.send(cmd));
+            }
+        } else if let Some(cmd) = middle.clear_slot(slot as u8) { // clear any samples removed from disk
             audio.send(cmd);
         }
     }
-    for slot in num_loaded..shared::NUM_SOUNDS { // clear any samples removed from disk
-        middle.clear_slot(slot as u8);
-    }
 
-    for slot in 0..shared::NUM_SOUNDS {
+    for slot in 0..shared::NUM_SLOTS {
         let sample_path = &saved_paths[slot];
         if sample_path.is_empty() {
             continue;
@@ -69,41 +171,93 @@ fn run() -> anyhow::Result<()> {
         let path = std::path::Path::new(sample_path);
         let already_loaded = middle.state.sounds[slot].sample_id.is_some();
         if !already_loaded && path.exists() {
-            if let Ok(cmd) = middle.load_sample_into_slot(slot as u8, path, SAMPLE_RATE) {
-                audio.send(cmd);
+            if let Ok(cmds) = middle.load_sample_into_slot(slot as u8, path, sample_rate) {
+                cmds.into_iter().for_each(|cmd| audio.send(cmd));
             }
         }
     }
 
+    audio.send(audio_api::AudioCommand::SetDcFilter { enabled: middle.state.dc_filter });
+    audio.send(audio_api::AudioCommand::SetRecordThreshold(middle.state.record_threshold));
+    audio.send(audio_api::AudioCommand::SetNormalizeRecordings(middle.state.normalize_recordings));
+    audio.send(audio_api::AudioCommand::SetTrimRecordings(middle.state.trim_recordings));
+    audio.send(audio_api::AudioCommand::SetInputMonitor(middle.state.input_monitor));
+    audio.send(audio_api::AudioCommand::SetMonitorGain(middle.state.monitor_gain));
+    if let Some(max) = args.max_voices {
+        audio.send(audio_api::AudioCommand::SetMaxVoices(max));
+    }
+    middle.metronome_setup().into_iter().for_each(|cmd| audio.send(cmd));
+
     let backend = CrosstermBackend::new(std::io::stdout());
     let mut term = Terminal::new(backend)?;
     term.clear()?;
 
-    let tick_rate = std::time::Duration::from_millis(16); // ~60fps
+    // Drawing runs at the frame rate (POCKETTY_FPS, for slow terminals); the sequencer
+    // gets ticked whenever a step is due, so a slow draw no longer delays it.
+    let frame_interval = Duration::from_secs_f64(1.0 / std::env::var("POCKETTY_FPS").ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(60.0)
+        .clamp(10.0, 240.0));
     let mut last_tick = Instant::now();
+    let mut next_draw = Instant::now();
+    let mut last_save = Instant::now();
+    let mut autosave = persistence::Autosave::default();
+    let mut auditions = loader::sample_loader::AuditionLoader::spawn();
     let blink_start = Instant::now();
     let mut tui_state = tui::mode::TuiState::default();
 
     loop {
-        // Always update blink and UI at the tick rate
-        let blink_on = (blink_start.elapsed().as_millis() / 250) % 2 == 0;
-        // Sync recording capture state from engine → middle → display
-        middle.set_capturing(audio.is_capturing());
-        let ds = middle.display_state().clone();
+        if Instant::now() >= next_draw {
+            next_draw = Instant::now() + frame_interval;
+            let blink_on = (blink_start.elapsed().as_millis() / 250) % 2 == 0;
+            // Sync recording capture state from engine → middle → display
+            middle.set_capturing(audio.is_capturing());
+            middle.set_input_ok(audio.input_ok());
+            middle.set_monitor_guarded(audio.monitor_guarded());
+            middle.set_input_peak(audio.take_input_peak());
+            middle.set_output_peak(audio.take_output_peak());
+            middle.set_sound_peaks(&audio.take_sample_peaks());
+            middle.set_engine_load(audio.active_voices(), audio.callback_load());
+            let ds = middle.display_state().clone();
 
-        tui_state.playing = ds.playing;
-        tui_state.write_mode = ds.write_mode;
-        tui_state.param_page = ds.param_page;
+            tui_state.playing = ds.playing;
+            tui_state.write_mode = ds.write_mode;
+            tui_state.param_page = ds.param_page;
+            tui_state.macro_targets = ds.macro_targets;
+            tui_state.settings_open = ds.device_menu.is_some();
+            tui_state.browser_open = ds.browser.is_some();
 
-        term.draw(|frame| {
-            tui::view::render(frame, frame.area(), &ds, blink_on);
-        })?;
+            term.draw(|frame| {
+                tui::view::render(frame, frame.area(), &ds, blink_on);
+            })?;
+        }
 
-        let events = tui::input::poll_input(tick_rate, &mut tui_state)?;
+        // wake for whichever comes first: a key, the next step, or the next frame
+        let mut timeout = next_draw.saturating_duration_since(Instant::now());
+        if let Some(step) = middle.until_next_step() {
+            timeout = timeout.min(Duration::from_secs_f64(step));
+        }
+        let mut events = tui::input::poll_input(timeout, &mut tui_state)?;
+        // OSC waits out the poll, so it lands within a frame of arriving
+        if let Some(rx) = &osc_rx {
+            for input in rx.try_iter() {
+                events.extend(tui::input::resolve_osc(input, &mut tui_state));
+            }
+        }
+        #[cfg(feature = "midi")]
+        if let Some(midi) = &midi_in {
+            for input in midi.rx.try_iter() {
+                events.extend(tui::input::resolve_midi(input, &mut tui_state));
+            }
+        }
+        if !events.is_empty() {
+            next_draw = Instant::now(); // show the result of a key press right away
+        }
         for event in events {
             if event == InputEvent::Quit {
                 // save before quitting
-                let _ = persistence::save_project(&project_dir, &middle.state);
+                autosave.wait();
+                let _ = persistence::save_project_slot(&project_dir, &middle.state, project_slot);
                 drop(term);
                 drop(audio);
                 return Ok(());
@@ -113,19 +267,128 @@ fn run() -> anyhow::Result<()> {
                 middle.set_input_device_name(name);
                 continue;
             }
+            if event == InputEvent::ToggleSettings {
+                if middle.device_menu_open() {
+                    middle.close_device_menu();
+                } else {
+                    middle.open_device_menu(DeviceMenu {
+                        inputs: audio::AudioHandle::list_input_devices(),
+                        outputs: audio::AudioHandle::list_output_devices(),
+                        current_input: audio.input_device_index(),
+                        current_output: audio.output_device_index(),
+                        ..Default::default()
+                    });
+                }
+                continue;
+            }
+            if event == InputEvent::ToggleBrowser {
+                if middle.browser_open() {
+                    auditions.cancel();
+                    for cmd in middle.close_browser() {
+                        audio.send(cmd);
+                    }
+                } else {
+                    let paths = loader::sample_loader::index_audio_in_dir(&project_dir).unwrap_or_default();
+                    middle.open_browser(SampleBrowser::new(paths, &project_dir));
+                }
+                continue;
+            }
+            if let InputEvent::BrowserMove(delta) = event {
+                // decoded on a worker; it plays when it arrives, further down the loop
+                if let Some(path) = middle.browser_move(delta) {
+                    auditions.request(path, audio.sample_rate());
+                }
+                continue;
+            }
+            if event == InputEvent::BrowserConfirm {
+                auditions.cancel();
+                match middle.load_browser_selection(audio.sample_rate()) {
+                    Ok(cmds) => {
+                        let loaded = cmds.iter().any(|cmd| matches!(cmd, audio_api::AudioCommand::RegisterSample { .. }));
+                        for cmd in cmds {
+                            audio.send(cmd);
+                        }
+                        // a sample picked by hand stays in its slot, whatever the folder does later
+                        if loaded {
+                            let slot = middle.state.selected_sound as usize;
+                            let mut pins = persistence::load_slot_manifest(&project_dir).unwrap_or_default();
+                            pins.insert(slot, PathBuf::from(&middle.state.sounds[slot].sample_path));
+                            let _ = persistence::save_slot_manifest(&project_dir, &pins);
+                        }
+                    }
+                    // close it so the LCD (and its LOAD ERR) shows; the slot keeps what it had
+                    Err(_) => middle.close_browser().into_iter().for_each(|cmd| audio.send(cmd)),
+                }
+                continue;
+            }
+            if event == InputEvent::SettingsConfirm {
+                if let Some(choice) = middle.device_menu_selection() {
+                    let rate_before = audio.sample_rate();
+                    let result = match choice {
+                        DeviceChoice::Input(i) => audio.select_input_device(i),
+                        DeviceChoice::Output(i) => audio.select_output_device(i),
+                    };
+                    middle.on_device_switched(choice, result);
+                    if audio.sample_rate() != rate_before {
+                        // samples were loaded for the old rate and would play off pitch
+                        middle.set_sample_rate(audio.sample_rate());
+                        middle.reload_samples().into_iter().for_each(|cmd| audio.send(cmd));
+                    }
+                }
+                continue;
+            }
+            if event == InputEvent::DumpState {
+                let dump_dir = project_dir.join(".pocketty");
+                let _ = std::fs::create_dir_all(&dump_dir);
+                let _ = std::fs::write(dump_dir.join("state_dump.txt"), middle.dump_state());
+                continue;
+            }
             if event == InputEvent::BouncePattern {
-                let sr = audio.sample_rate();
-                let secs_per_step = 60.0 / (middle.state.bpm as f64 * 4.0);
-                let frames_per_step = (secs_per_step * sr as f64) as usize;
-                let step_cmds = middle.generate_pattern_commands();
-                let buffer = audio::bounce_offline(
-                    audio.samples(), &step_cmds, frames_per_step,
+                let pat = middle.state.selected_pattern as usize;
+                let result = pipeline::bounce::export_pattern_wav(
+                    &middle, audio.samples(), audio.sample_rate(), &project_dir, pat,
                 );
-                let bounce_dir = project_dir.join(".pocketty");
-                let _ = std::fs::create_dir_all(&bounce_dir);
-                let pat = middle.state.selected_pattern + 1;
-                let path = bounce_dir.join(format!("bounce_pat{}.wav", pat));
-                let _ = buffer.save_wav(&path, sr);
+                middle.on_bounced(&format!("P{}", pat + 1), result);
+                continue;
+            }
+            if event == InputEvent::BounceSong {
+                let result = pipeline::bounce::export_song_wav(
+                    &middle, audio.samples(), audio.sample_rate(), &project_dir, pipeline::bounce::SONG_TAIL_SECS,
+                );
+                middle.on_bounced("SONG", result);
+                continue;
+            }
+            if event == InputEvent::Save {
+                autosave.wait(); // a background save finishing later would overwrite this one
+                let result = persistence::save_project_slot(&project_dir, &middle.state, project_slot);
+                middle.on_manual_save(result);
+                continue;
+            }
+            if let InputEvent::SwitchProjectSlot(slot) = event {
+                // the current project is saved first, so switching back finds it as it was
+                autosave.wait();
+                if slot != project_slot
+                    && persistence::save_project_slot(&project_dir, &middle.state, project_slot).is_ok()
+                {
+                    let state = persistence::load_project_slot(&project_dir, slot);
+                    middle.switch_project(state, slot).into_iter().for_each(|cmd| audio.send(cmd));
+                    project_slot = slot;
+                    let _ = persistence::save_active_slot(&project_dir, slot);
+                }
+                continue;
+            }
+            if event == InputEvent::ExportMidi {
+                let pat = middle.state.selected_pattern as usize;
+                let path = project_dir.join("export").join(format!("pattern_{:02}.mid", pat + 1));
+                let result = pipeline::midi::export_pattern(&middle.state, pat, &path);
+                middle.on_midi_exported(result);
+                continue;
+            }
+            if event == InputEvent::ImportMidi {
+                let pat = middle.state.selected_pattern as usize;
+                let result = loader::sample_loader::first_midi_in_dir(&project_dir)
+                    .and_then(|path| pipeline::midi::import_pattern(&mut middle.state, pat, &path));
+                middle.on_midi_imported(result);
                 continue;
             }
             let cmds = middle.handle_input(event);
@@ -134,9 +397,36 @@ fn run() -> anyhow::Result<()> {
             }
         }
 
-        // Check if a recording just finished; save the WAV to the project dir
-        if let Some(rec) = audio.poll_completed_recording() {
-            let _ = middle.on_recording_complete(rec.sample_id, &rec.buffer, &project_dir);
+        // Save any finished recordings to the project dir, then persist the project so
+        // the slot keeps pointing at the WAV even if we never reach a clean quit
+        while let Some(rec) = audio.poll_completed_recording() {
+            autosave.wait();
+            if middle.on_recording_complete(rec.sample_id, &rec.buffer, &project_dir).is_ok()
+                && persistence::save_project_slot(&project_dir, &middle.state, project_slot).is_ok()
+            {
+                middle.mark_saved();
+            }
+        }
+
+        // The autosave writes a snapshot on a worker thread; only the edits it covered
+        // count as saved, and only once the write has landed.
+        if let Some(edits) = autosave.poll() {
+            middle.mark_saved_at(edits);
+        }
+        if middle.is_dirty() && last_save.elapsed() >= AUTOSAVE_INTERVAL {
+            last_save = Instant::now(); // a failed save waits for the next round too
+            autosave.start(&project_dir, middle.state.clone(), project_slot, middle.edit_count());
+        }
+
+        while let Some(jam) = audio.poll_master_capture() {
+            let result = pipeline::bounce::save_master_capture(&jam, audio.sample_rate(), &project_dir);
+            middle.on_master_capture_saved(result);
+        }
+
+        if let Some((id, buffer)) = auditions.poll() {
+            for cmd in middle.audition(id, buffer) {
+                audio.send(cmd);
+            }
         }
 
         let elapsed = last_tick.elapsed().as_secs_f64();
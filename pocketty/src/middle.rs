@@ -1,16 +1,49 @@
 // middle.rs is the brain of the PO
 
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-use crate::audio_api::{AudioCommand, TriggerParams};
-use crate::audio::{next_sample_id, EffectSpec, SampleBuffer, SampleId};
+use crate::audio_api::{AudioCommand, Envelope, TriggerParams};
+use crate::audio::{next_sample_id, EffectSpec, Interpolation, SampleBuffer, SampleId, StereoFrame};
 use crate::loader::sample_loader;
-use crate::pipeline::project::{HeldButtons, ProjectState, SoundSlot};
+use crate::pipeline;
+use crate::pipeline::generate::Rng;
+use crate::pipeline::project::{HeldButtons, LengthMode, Pattern, ProjectState, SoundSlot, Step, Track, CHOKE_GROUPS, MAX_RATCHET};
 use crate::shared::*;
 
 const FX_TAP_THRESHOLD_MS: u128 = 200;
-const SAMPLE_RATE: f32 = 44100.0;
+const DEFAULT_SAMPLE_RATE: f32 = 44100.0; // until main tells us the output device's
+const REFERENCE_TUNING_HZ: f32 = 440.0; // pitch ratios are relative to A=440
+const VOICE_PITCH_RANGE: (f32, f32) = (0.25, 4.0); // widest playback rate we hand to a voice
+// Real-time FX quantize choices, in steps (0 = engage immediately)
+const FX_QUANTIZE_DIVISIONS: [u8; 6] = [0, 1, 2, 4, 8, 16];
+const MIN_TRIM_LENGTH: usize = 256; // shortest region the trim knobs will leave (~6ms at 44.1kHz)
+const VOLUME_DB_PER_STEP: f32 = 1.5; // volume 16 is unity, each step down is the same loudness drop
+const MAX_CATCHUP_STEPS: u32 = 1; // steps one tick may fire; a stalled frame drops time instead of bunching hits
+const HIGH_PASS_FX_HZ: f32 = 300.0; // fx 12 cutoff: clears rumble and thins kicks/bass without gutting them
+const AUDITION_MAX_SECS: f32 = 3.0; // browser previews stop after this long
+const CHANCE_SEED: u64 = 0x5EED_0FC4_A7CE; // fixed, so the same pattern rolls the same way every run
+const TAP_TEMPO_TAPS: usize = 4; // taps the tempo is worked out from
+const TAP_TEMPO_RESET_SECS: f64 = 2.0; // a longer gap starts a new count
+const CLICK_HZ: f32 = 1500.0;
+const CLICK_SECS: f32 = 0.03;
+const CLICK_GAIN: (f32, f32) = (0.25, 0.4); // (beat, downbeat), fixed whatever the volume is
+const CLICK_ACCENT_PITCH: f32 = 1.5; // the one is a fifth up
+const SWING_DISPLAY_MAX: f32 = 50.0; // raw swing 0.0-1.0 reads as 0% (straight) .. 50% (off-beats half a step late)
+const CLIP_HOLD_SECS: f32 = 1.0; // how long the clip light stays on after the output goes over
+const LOAD_NORMALIZE_PEAK: f32 = 0.891; // -1 dBFS, same as normalized recordings
+const ENV_MAX_SECS: f32 = 2.0; // longest attack/decay/release the knobs reach
+const MASTER_CAPTURE_MAX_SECS: f32 = 600.0; // a jam stops itself after 10 minutes (~230MB at 48k)
+const UNDO_DEPTH: usize = 32; // oldest entries fall off past this
+
+/// What a destructive edit replaced, so Undo can put it back. The redo stack holds
+/// the same thing the other way round: what an undo replaced.
+enum Undo {
+    Pattern(usize, Box<Pattern>),
+    AllPatterns(Box<[Pattern; NUM_PATTERNS]>),
+    Sound(u8, Box<SoundSlot>), // a deleted sound; its sample comes back from sample_path
+}
 
 pub struct Middle {
     pub state: ProjectState,
@@ -18,14 +51,52 @@ pub struct Middle {
     playing: bool,
     write_mode: bool,
     current_step: u8,
+    bar: u32, // bars since play was pressed, counting across chain/pattern switches (1-based once playing)
     step_accumulator: f64,
+    playing_pattern: u8, // what the sequencer plays; selected_pattern is only what's being edited
+    pattern_steps: usize, // steps since the playing pattern started; each track wraps it at its own length
     chain_position: usize,
+    queued_pattern: Option<u8>, // takes over at the next loop point
     param_page: ParamPage,
     fx_down_at: Option<Instant>, // tap/hold detection
+    taps: VecDeque<Instant>, // recent TapTempo presses, oldest first
+    velocity: Option<f32>, // MIDI velocity for the pad event right behind it
+    normalize_on_load: bool, // newly loaded files get brought to LOAD_NORMALIZE_PEAK (off: raw levels)
+    metronome_on: bool,
+    metronome_click: Option<SampleId>, // registered by metronome_setup
     active_rt_effect: Option<u8>, // active real-time effect while fx held
+    pending_rt_effect: Option<Option<u8>>, // fx change waiting for the next quantize point
+    hold: bool, // drone mode: the last pad played keeps sounding
+    held_voice: Option<SampleId>, // sample of the currently sustaining drone
+    swung: Vec<(f64, AudioCommand)>, // off-beat hits waiting out their swing delay (secs left)
+    loop_roll: Option<SampleId>, // sample whose voice is locked into a loop region
+    grab_armed: bool, // GrabBar pressed, waiting for the next bar to start
+    jam_started: Option<Instant>, // master output recording since
+    trim_at_floor: bool, // last trim turn ran into MIN_TRIM_LENGTH (shown on the LCD)
+    notice: Option<String>, // one-off LCD message (midi import result), cleared by the next input
+    edits: u64, // project changes so far (a count, so a save can say which ones it covered)
+    saved_edits: u64, // edits that are on disk
+    gen_seed: u64, // advances on every generated pattern
+    chance: Rng, // rolls for steps with a probability
+    undo: Vec<Undo>, // newest last; filled by step toggles, generate, paste, the clears and sound deletes
+    redo: Vec<Undo>, // what Undo put back, emptied by any other edit
+    clipboard: Option<Box<Pattern>>, // last copied pattern
+    clear_all_pending: bool, // clear-all asked, waiting on ConfirmClearAll
     recording_armed: bool, // true between RecordDown and RecordUp
     is_capturing: bool,    // true when engine is actively capturing audio (set from main loop)
+    capture_started: Option<Instant>, // when the current capture crossed the threshold
+    input_peak: f32, // metered input level with a little decay (set from main loop)
+    output_peak: f32, // master output level, decayed like input_peak
+    clipped_at: Option<Instant>, // last time the output went over 1.0
+    sound_peaks: Vec<f32>, // output level per slot, decayed like input_peak
     input_device_name: String, // current input device name
+    input_ok: bool, // input stream health (set from main loop)
+    sample_rate: f32, // output device rate the engine runs at (set from main loop)
+    monitor_guarded: bool, // input and output are one device, so monitoring is held off (set from main loop)
+    engine_load: (usize, u32), // (voices, callback %) reported by the engine
+    device_menu: Option<DeviceMenu>, // settings overlay (populated by the main loop)
+    browser: Option<SampleBrowser>, // sample browser overlay (populated by the main loop)
+    audition: Option<SampleId>, // temp sample the browser is previewing
     display: DisplayState,
 }
 
@@ -37,14 +108,54 @@ impl Middle {
             playing: false,
             write_mode: false,
             current_step: 0,
+            bar: 0,
             step_accumulator: 0.0,
+            playing_pattern: 0,
+            pattern_steps: 0,
             chain_position: 0,
+            queued_pattern: None,
             param_page: ParamPage::Tone,
             fx_down_at: None,
+            taps: VecDeque::with_capacity(TAP_TEMPO_TAPS),
+            velocity: None,
+            normalize_on_load: false,
+            metronome_on: false,
+            metronome_click: None,
             active_rt_effect: None,
+            pending_rt_effect: None,
+            hold: false,
+            held_voice: None,
+            swung: Vec::new(),
+            loop_roll: None,
+            trim_at_floor: false,
+            grab_armed: false,
+            jam_started: None,
+            notice: None,
+            edits: 0,
+            saved_edits: 0,
+            gen_seed: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+            chance: Rng::new(CHANCE_SEED),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            clipboard: None,
+            clear_all_pending: false,
             recording_armed: false,
             is_capturing: false,
+            capture_started: None,
+            input_peak: 0.0,
+            output_peak: 0.0,
+            clipped_at: None,
+            sound_peaks: vec![0.0; NUM_SLOTS],
             input_device_name: String::from("default"),
+            input_ok: true,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            monitor_guarded: false,
+            engine_load: (0, 0),
+            device_menu: None,
+            browser: None,
+            audition: None,
             display: Self::empty_display(),
         }
     }
@@ -54,6 +165,8 @@ impl Middle {
         // cause glitches if they survive across sessions
         Self::clear_all_step_effects(&mut state);
         let mut m = Self::new();
+        m.param_page = state.param_page;
+        m.write_mode = state.write_mode;
         m.state = state;
         m
     }
@@ -72,6 +185,201 @@ impl Middle {
     /// Called from the main loop to update recording capture state from the engine.
     pub fn set_capturing(&mut self, capturing: bool) {
         self.is_capturing = capturing;
+        if !capturing {
+            self.capture_started = None;
+        } else if self.capture_started.is_none() {
+            self.capture_started = Some(Instant::now());
+        }
+    }
+
+    /// Called from the main loop with the loudest input sample since the last frame.
+    pub fn set_input_peak(&mut self, peak: f32) {
+        // fall back gently so the meter is readable at frame rate
+        self.input_peak = peak.max(self.input_peak * 0.85).min(1.0);
+    }
+
+    /// Called from the main loop with the loudest master output sample since the last frame.
+    pub fn set_output_peak(&mut self, peak: f32) {
+        if peak > 1.0 {
+            self.clipped_at = Some(Instant::now());
+        }
+        self.output_peak = peak.max(self.output_peak * 0.85).min(1.0);
+    }
+
+    /// Called from the main loop with each sample's loudest output since the last frame.
+    pub fn set_sound_peaks(&mut self, peaks: &[(SampleId, f32)]) {
+        for p in &mut self.sound_peaks {
+            *p *= 0.85;
+        }
+        for &(id, peak) in peaks {
+            if let Some(slot) = self.state.sounds.iter().position(|s| s.sample_id == Some(id)) {
+                self.sound_peaks[slot] = peak.max(self.sound_peaks[slot]).min(1.0);
+            }
+        }
+    }
+
+    /// Called from the main loop with whether the input stream is healthy.
+    pub fn set_input_ok(&mut self, ok: bool) {
+        self.input_ok = ok;
+    }
+
+    /// Called from the main loop with the output device's sample rate, at startup and
+    /// after an output switch. Stutter lengths, gates and sample loads all count in it.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate as f32;
+    }
+
+    /// Whether files loaded into empty (or newly browsed) slots get normalized from here on.
+    pub fn set_normalize_on_load(&mut self, enabled: bool) {
+        self.normalize_on_load = enabled;
+    }
+
+    /// Called from the main loop with whether monitoring is held off because the input
+    /// and output are the same device.
+    pub fn set_monitor_guarded(&mut self, guarded: bool) {
+        self.monitor_guarded = guarded;
+    }
+
+    /// Called from the main loop with the engine's voice count and callback load.
+    pub fn set_engine_load(&mut self, voices: usize, load: u32) {
+        self.engine_load = (voices, load);
+    }
+
+    /// Called from the main loop after the project was written to disk.
+    pub fn mark_saved(&mut self) {
+        self.saved_edits = self.edits;
+    }
+
+    /// Called from the main loop when a background save of the project as it was at
+    /// `edit_count() == edits` lands; anything edited since stays unsaved.
+    pub fn mark_saved_at(&mut self, edits: u64) {
+        self.saved_edits = self.saved_edits.max(edits.min(self.edits));
+    }
+
+    /// Bumped by every project edit; tags a snapshot handed to a background save.
+    pub fn edit_count(&self) -> u64 {
+        self.edits
+    }
+
+    /// Whether the project changed since it was last saved (the main loop autosaves it).
+    pub fn is_dirty(&self) -> bool {
+        self.edits != self.saved_edits
+    }
+
+    /// Called from the main loop after an explicit save.
+    pub fn on_manual_save(&mut self, result: anyhow::Result<()>) {
+        self.notice = Some(match result {
+            Ok(()) => {
+                self.mark_saved();
+                String::from("SAVED")
+            }
+            Err(_) => String::from("SAVE ERR"),
+        });
+    }
+
+    /// Load every sound's file again at the current sample rate, e.g. after the output
+    /// device changed rate. Trim and loop points move with the buffer so they still
+    /// mark the same audio. Sounds whose file is gone keep what they had.
+    pub fn reload_samples(&mut self) -> Vec<AudioCommand> {
+        let rate = self.sample_rate as u32;
+        let mut cmds = Vec::new();
+        for slot in 0..self.state.sounds.len() {
+            let path = PathBuf::from(&self.state.sounds[slot].sample_path);
+            let old_len = self.state.sounds[slot].buffer_len;
+            if !path.exists() {
+                continue;
+            }
+            let sound = &self.state.sounds[slot];
+            let (trim_start, length, loop_start, loop_end) = (sound.trim_start, sound.length, sound.loop_start, sound.loop_end);
+            let Ok(load) = self.load_sample_into_slot(slot as u8, &path, rate) else { continue; };
+            cmds.extend(load);
+            let sound = &mut self.state.sounds[slot];
+            if old_len > 0 && sound.buffer_len != old_len {
+                let ratio = sound.buffer_len as f64 / old_len as f64;
+                let scale = |frames: usize| (frames as f64 * ratio) as usize;
+                sound.trim_start = scale(trim_start).min(sound.buffer_len.saturating_sub(1));
+                // min then max: a file that shrank to nothing leaves no room for even one frame
+                sound.length = scale(length).min(sound.buffer_len - sound.trim_start).max(1);
+                sound.loop_start = loop_start.map(scale);
+                sound.loop_end = loop_end.map(scale);
+            }
+        }
+        cmds
+    }
+
+    /// Called from the main loop after the current project was saved to its slot.
+    /// `state` is what was saved in the new slot; an empty slot (None) starts a new
+    /// beat on the same kit, so only the patterns are cleared.
+    pub fn switch_project(&mut self, state: Option<ProjectState>, slot: u8) -> Vec<AudioCommand> {
+        let mut cmds = Vec::new();
+        if self.playing {
+            cmds.extend(self.handle_input(InputEvent::PlayPress));
+        }
+        match state {
+            Some(mut state) => {
+                cmds.extend(self.state.sounds.iter()
+                    .filter_map(|s| s.sample_id)
+                    .map(|id| AudioCommand::UnregisterSample { id }));
+                Self::clear_all_step_effects(&mut state);
+                self.param_page = state.param_page;
+                self.write_mode = state.write_mode;
+                self.state = state;
+                cmds.extend(self.reload_samples());
+            }
+            None => {
+                self.state.patterns = Default::default();
+                self.state.pattern_chain.clear();
+                self.state.selected_pattern = 0;
+            }
+        }
+        self.undo.clear();
+        self.redo.clear();
+        self.mark_saved();
+        self.notice = Some(format!("PROJECT {}", slot + 1));
+        cmds.extend([
+            AudioCommand::SetDcFilter { enabled: self.state.dc_filter },
+            AudioCommand::SetRecordThreshold(self.state.record_threshold),
+            AudioCommand::SetNormalizeRecordings(self.state.normalize_recordings),
+            AudioCommand::SetTrimRecordings(self.state.trim_recordings),
+            AudioCommand::SetInputMonitor(self.state.input_monitor),
+            AudioCommand::SetMonitorGain(self.state.monitor_gain),
+        ]);
+        cmds
+    }
+
+    /// Called from the main loop with the outcome of a MIDI export.
+    pub fn on_midi_exported(&mut self, result: anyhow::Result<()>) {
+        self.notice = Some(String::from(if result.is_ok() { "MIDI OUT" } else { "MIDI ERR" }));
+    }
+
+    /// Called from the main loop with the outcome of a MIDI import.
+    pub fn on_midi_imported(&mut self, result: anyhow::Result<pipeline::midi::ImportReport>) {
+        if result.is_ok() {
+            self.edits += 1;
+        }
+        self.notice = Some(match result {
+            Ok(r) if r.wrapped > 0 => format!("MIDI {} NOTES {} WRAPPED", r.notes, r.wrapped),
+            Ok(r) => format!("MIDI {} NOTES", r.notes),
+            Err(_) => String::from("MIDI ERR"),
+        });
+    }
+
+    /// Called from the main loop with the outcome of a pattern or song bounce.
+    pub fn on_bounced(&mut self, what: &str, result: anyhow::Result<std::path::PathBuf>) {
+        self.notice = Some(match result {
+            Ok(_) => format!("BOUNCED {}", what),
+            Err(_) => String::from("BOUNCE ERR"),
+        });
+    }
+
+    /// Called from the main loop once a master recording is back from the engine
+    /// (stopped, or at its length cap) and written out.
+    pub fn on_master_capture_saved(&mut self, result: anyhow::Result<std::path::PathBuf>) {
+        self.jam_started = None;
+        self.notice = Some(match result {
+            Ok(path) => format!("SAVED {}", path.file_stem().unwrap_or_default().to_string_lossy().to_uppercase()),
+            Err(_) => String::from("JAM SAVE ERR"),
+        });
     }
 
     /// Called from the main loop when the input device is switched.
@@ -79,7 +387,177 @@ impl Middle {
         self.input_device_name = name;
     }
 
+    /// Called from the main loop with the device lists when the settings overlay opens.
+    pub fn open_device_menu(&mut self, mut menu: DeviceMenu) {
+        menu.cursor = menu.current_input.min(menu.len().saturating_sub(1));
+        self.device_menu = Some(menu);
+    }
+
+    pub fn close_device_menu(&mut self) {
+        self.device_menu = None;
+    }
+
+    pub fn device_menu_open(&self) -> bool {
+        self.device_menu.is_some()
+    }
+
+    /// The device under the overlay cursor, if the overlay is open.
+    pub fn device_menu_selection(&self) -> Option<DeviceChoice> {
+        self.device_menu.as_ref().and_then(|m| m.selected())
+    }
+
+    /// Called from the main loop after trying to switch to `choice`.
+    pub fn on_device_switched(&mut self, choice: DeviceChoice, result: anyhow::Result<String>) {
+        match result {
+            Ok(name) => {
+                if let Some(menu) = &mut self.device_menu {
+                    menu.error = None;
+                    match choice {
+                        DeviceChoice::Input(i) => menu.current_input = i,
+                        DeviceChoice::Output(i) => menu.current_output = i,
+                    }
+                }
+                if let DeviceChoice::Input(_) = choice {
+                    self.input_device_name = name;
+                }
+            }
+            Err(e) => {
+                if let Some(menu) = &mut self.device_menu {
+                    menu.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Called from the main loop with the WAVs it found when the browser opens.
+    pub fn open_browser(&mut self, browser: SampleBrowser) {
+        self.browser = Some(browser);
+    }
+
+    /// Close the browser, dropping whatever it was auditioning.
+    pub fn close_browser(&mut self) -> Vec<AudioCommand> {
+        self.browser = None;
+        self.stop_audition()
+    }
+
+    pub fn browser_open(&self) -> bool {
+        self.browser.is_some()
+    }
+
+    /// Move the browser cursor; returns the file now under it, for the main loop to audition.
+    pub fn browser_move(&mut self, delta: i32) -> Option<PathBuf> {
+        let browser = self.browser.as_mut()?;
+        browser.move_cursor(delta);
+        browser.selected().map(Path::to_path_buf)
+    }
+
+    /// Play the first part of a browsed file, replacing the previous preview.
+    pub fn audition(&mut self, id: SampleId, mut buffer: SampleBuffer) -> Vec<AudioCommand> {
+        let mut cmds = self.stop_audition();
+        buffer.data.truncate((AUDITION_MAX_SECS * self.sample_rate) as usize);
+        if self.normalize_on_load {
+            buffer.normalize(LOAD_NORMALIZE_PEAK); // preview at the level it would load at
+        }
+        let len = buffer.data.len();
+        cmds.push(AudioCommand::RegisterSample { id, buffer });
+        let preview = SoundSlot { sample_id: Some(id), buffer_len: len, length: len, ..Default::default() };
+        cmds.extend(self.trigger_commands(&preview, None, None, 1.0));
+        self.audition = Some(id);
+        cmds
+    }
+
+    /// Synthesize the metronome click and hand it to the engine. Call once at startup.
+    pub fn metronome_setup(&mut self) -> Vec<AudioCommand> {
+        // a short sine ping with a fast exponential decay
+        let frames = (CLICK_SECS * self.sample_rate) as usize;
+        let data = (0..frames)
+            .map(|i| {
+                let t = i as f32 / self.sample_rate;
+                let s = (std::f32::consts::TAU * CLICK_HZ * t).sin() * (-t / (CLICK_SECS / 5.0)).exp();
+                StereoFrame { left: s, right: s }
+            })
+            .collect();
+        let id = next_sample_id();
+        self.metronome_click = Some(id);
+        vec![AudioCommand::RegisterSample { id, buffer: SampleBuffer::from_frames(data) }]
+    }
+
+    // Straight to the engine rather than through trigger_commands: no sound slot, no
+    // master volume, no fx
+    fn metronome_tick(&self) -> Option<AudioCommand> {
+        let sample_id = self.metronome_click.filter(|_| self.metronome_on)?;
+        if !self.current_step.is_multiple_of(4) {
+            return None;
+        }
+        let downbeat = self.current_step == 0;
+        Some(AudioCommand::Trigger(TriggerParams {
+            sample_id,
+            trim_start: 0,
+            length: (CLICK_SECS * self.sample_rate) as usize,
+            gain: if downbeat { CLICK_GAIN.1 } else { CLICK_GAIN.0 },
+            pitch: if downbeat { CLICK_ACCENT_PITCH } else { 1.0 },
+            effect_chain: Vec::new(),
+            reverse: false,
+            stutter_period_samples: None,
+            hold: false,
+            interp: Interpolation::Linear,
+            envelope: Envelope::default(),
+            loop_start: None,
+            loop_end: None,
+            pan: 0.0,
+            choke_group: None,
+            cue: false,
+        }))
+    }
+
+    // the engine fades out anything still playing an unregistered sample
+    fn stop_audition(&mut self) -> Vec<AudioCommand> {
+        self.audition.take().map(|id| AudioCommand::UnregisterSample { id }).into_iter().collect()
+    }
+
+    /// Load the file under the browser cursor into the selected slot and close the browser.
+    /// If it won't load, the slot keeps what it had and the LCD says LOAD ERR.
+    pub fn load_browser_selection(&mut self, target_rate: u32) -> anyhow::Result<Vec<AudioCommand>> {
+        let Some(path) = self.browser.as_ref().and_then(|b| b.selected()).map(Path::to_path_buf) else {
+            return Ok(self.close_browser());
+        };
+        let slot = self.state.selected_sound;
+        // a new file, so start from the whole buffer rather than keep the old trim; the
+        // old path only goes once the new one has actually loaded
+        let old_path = std::mem::take(&mut self.state.sounds[slot as usize].sample_path);
+        let mut cmds = match self.load_sample_into_slot(slot, &path, target_rate) {
+            Ok(cmds) => cmds,
+            Err(e) => {
+                self.state.sounds[slot as usize].sample_path = old_path;
+                self.notice = Some(String::from("LOAD ERR"));
+                return Err(e);
+            }
+        };
+        self.edits += 1; // main handles BrowserConfirm, so handle_input never marks it
+        cmds.extend(self.close_browser());
+        Ok(cmds)
+    }
+
     pub fn handle_input(&mut self, event: InputEvent) -> Vec<AudioCommand> {
+        // velocity applies to the very next event only, whatever that turns out to be
+        if let InputEvent::PadVelocity(v) = event {
+            self.velocity = Some(v.clamp(0.0, 1.0));
+            return vec![];
+        }
+        let velocity = self.velocity.take().unwrap_or(1.0);
+        // the floor warning only sticks around while you keep turning the trim knobs
+        if !matches!(event, InputEvent::AdjustTrimStart(_) | InputEvent::AdjustTrimLength(_)) {
+            self.trim_at_floor = false;
+        }
+        self.notice = None;
+        // the clear-all prompt only answers the very next input
+        let clear_all_asked = std::mem::take(&mut self.clear_all_pending);
+        if Self::edits_project(&event) {
+            self.edits += 1;
+            if !matches!(event, InputEvent::Undo | InputEvent::Redo) {
+                self.redo.clear();
+            }
+        }
         match event {
             InputEvent::SoundDown => { self.held.sound = true; vec![] }
             InputEvent::SoundUp => { self.held.sound = false; vec![] }
@@ -95,6 +573,7 @@ impl Middle {
                 self.held.write_held = true;
                 // Toggle write mode (stopped or playing)
                 self.write_mode = !self.write_mode;
+                self.state.write_mode = self.write_mode;
                 vec![]
             }
             InputEvent::WriteUp => {
@@ -107,13 +586,24 @@ impl Middle {
                 if self.playing {
                     // Start one step behind so the first advance_step() lands on step 0
                     self.current_step = (STEPS_PER_PATTERN as u8).wrapping_sub(1);
+                    self.bar = 0;
                     self.step_accumulator = 0.0;
+                    self.pattern_steps = usize::MAX; // wraps to 0 with current_step
+                    self.playing_pattern = self.state.pattern_chain.first()
+                        .copied()
+                        .unwrap_or(self.state.selected_pattern);
                     self.chain_position = 0;
                     self.active_rt_effect = None;
                     vec![]
                 } else {
                     // Stopping: kill all playing voices and clear realtime effect
                     self.active_rt_effect = None;
+                    self.pending_rt_effect = None;
+                    self.queued_pattern = None;
+                    self.held_voice = None;
+                    self.loop_roll = None;
+                    self.grab_armed = false;
+                    self.swung.clear();
                     vec![AudioCommand::StopAllVoices]
                 }
             }
@@ -123,7 +613,9 @@ impl Middle {
                 // Record + Pattern = clear pattern
                 if self.held.pattern {
                     let pi = self.state.selected_pattern as usize;
-                    self.state.patterns[pi] = Default::default();
+                    self.snapshot_pattern(pi);
+                    let kit = self.state.patterns[pi].kit; // clears the notes, not the kit
+                    self.state.patterns[pi] = Pattern { kit, ..Default::default() };
                     return vec![];
                 }
                 // Record alone = arm mic recording into selected sound slot
@@ -132,12 +624,17 @@ impl Middle {
                     let sid = next_sample_id();
                     let slot = self.state.selected_sound as usize;
                     let sound = &mut self.state.sounds[slot];
+                    let mut cmds: Vec<AudioCommand> = sound.sample_id
+                        .map(|id| AudioCommand::UnregisterSample { id })
+                        .into_iter()
+                        .collect();
                     sound.sample_id = Some(sid);
                     sound.sample_path = "(recording)".into();
                     sound.trim_start = 0;
                     sound.buffer_len = 0;
                     sound.length = usize::MAX; // voice clamps to actual buffer length
-                    return vec![AudioCommand::StartRecording { sample_id: sid }];
+                    cmds.push(AudioCommand::StartRecording { sample_id: sid });
+                    return cmds;
                 }
                 vec![]
             }
@@ -148,6 +645,9 @@ impl Middle {
                 vec![AudioCommand::StopRecording]
             }
 
+            InputEvent::PreviewDown => { self.held.preview = true; vec![] }
+            InputEvent::PreviewUp => { self.held.preview = false; vec![] }
+
             InputEvent::FxDown => {
                 self.held.fx = true;
                 self.fx_down_at = Some(Instant::now());
@@ -156,13 +656,21 @@ impl Middle {
             InputEvent::FxUp => {
                 self.held.fx = false;
                 let had_effect = self.active_rt_effect.is_some();
-                self.active_rt_effect = None;
                 // If it was a quick tap (< threshold), cycle param page
                 if let Some(at) = self.fx_down_at.take() {
                     if at.elapsed().as_millis() < FX_TAP_THRESHOLD_MS {
                         self.param_page = self.param_page.next();
+                        self.state.param_page = self.param_page;
+                    }
+                }
+                if self.fx_quantized() {
+                    // let go on the grid too (if anything is engaged or about to be)
+                    if had_effect || self.pending_rt_effect.is_some() {
+                        self.pending_rt_effect = Some(None);
                     }
+                    return vec![];
                 }
+                self.active_rt_effect = None;
                 // Kill lingering voices (stutter/loop) when leaving fx mode
                 if had_effect { vec![AudioCommand::StopAllVoices] } else { vec![] }
             }
@@ -180,11 +688,129 @@ impl Middle {
             // semantic grid events resolved and sent by tui
 
             InputEvent::SelectSound(n) => {
-                self.state.selected_sound = n;
+                let bank = self.state.selected_bank();
+                self.state.selected_sound = (bank * NUM_SOUNDS) as u8 + n;
+                vec![]
+            }
+            InputEvent::ToggleSoundReverse => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.reverse = !sound.reverse;
+                vec![]
+            }
+            InputEvent::ToggleSustainLoop => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                if sound.loop_start.take().is_some() {
+                    sound.loop_end = None;
+                    self.notice = Some(String::from("SUSTAIN LOOP OFF"));
+                } else {
+                    let len = if sound.buffer_len > 0 {
+                        sound.length.min(sound.buffer_len.saturating_sub(sound.trim_start))
+                    } else {
+                        sound.length
+                    };
+                    sound.loop_start = Some(len / 2);
+                    sound.loop_end = Some(len);
+                    self.notice = Some(String::from("SUSTAIN LOOP ON"));
+                }
+                vec![]
+            }
+            InputEvent::CycleChokeGroup => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.choke_group = match sound.choke_group {
+                    None => Some(1),
+                    Some(g) if g < CHOKE_GROUPS => Some(g + 1),
+                    Some(_) => None,
+                };
+                self.notice = Some(sound.choke_group.map_or(String::from("CHOKE OFF"), |g| format!("CHOKE {}", g)));
+                vec![]
+            }
+            InputEvent::ReleasePad(_) => {
+                // pads play the selected sound, so that's the one to let go of
+                let sound = &self.state.sounds[self.state.selected_sound as usize];
+                match sound.sample_id {
+                    Some(sample_id) if sound.loop_start.is_some() => vec![AudioCommand::ReleaseVoice { sample_id }],
+                    _ => vec![],
+                }
+            }
+            InputEvent::ToggleSoundCue => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.cue = !sound.cue;
+                vec![]
+            }
+            InputEvent::CycleLengthMode => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.length_mode = sound.length_mode.next();
+                vec![]
+            }
+            InputEvent::ToggleSoundCompressor => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.compress = !sound.compress;
+                vec![]
+            }
+            InputEvent::SetDuckSource => {
+                let slot = self.state.selected_sound;
+                let sc = &mut self.state.sidechain;
+                sc.source = if sc.source == Some(slot) { None } else { Some(slot) };
+                sc.targets.retain(|&t| t != slot); // a sound can't duck itself
+                vec![]
+            }
+            InputEvent::ToggleDuckTarget => {
+                let slot = self.state.selected_sound;
+                let sc = &mut self.state.sidechain;
+                if let Some(i) = sc.targets.iter().position(|&t| t == slot) {
+                    sc.targets.remove(i);
+                } else if sc.source != Some(slot) {
+                    sc.targets.push(slot);
+                }
+                vec![]
+            }
+            InputEvent::CycleInterpolation => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.interp = sound.interp.next();
+                vec![]
+            }
+            InputEvent::ShiftBank(dir) => {
+                // same pad, neighbouring bank
+                let bank = (self.state.selected_bank() as i32 + dir).rem_euclid(NUM_BANKS as i32);
+                let pad = self.state.selected_sound as usize % NUM_SOUNDS;
+                self.state.selected_sound = (bank as usize * NUM_SOUNDS + pad) as u8;
                 vec![]
             }
             InputEvent::SelectPattern(n) => {
+                // only sent while stopped: picking a pattern to play replaces any chain
                 self.state.selected_pattern = n;
+                self.state.pattern_chain.clear();
+                self.state.follow_kit();
+                vec![]
+            }
+            InputEvent::TogglePatternKit => {
+                let bank = self.state.selected_bank() as u8;
+                let pattern = &mut self.state.patterns[self.state.selected_pattern as usize];
+                pattern.kit = if pattern.kit == Some(bank) { None } else { Some(bank) };
+                vec![]
+            }
+            InputEvent::CopyPattern => {
+                let pi = self.state.selected_pattern as usize;
+                self.clipboard = Some(Box::new(self.state.patterns[pi].clone()));
+                self.notice = Some(format!("COPY PAT {}", pi + 1));
+                vec![]
+            }
+            InputEvent::PastePattern => {
+                let pi = self.state.selected_pattern as usize;
+                let Some(pattern) = &self.clipboard else {
+                    self.notice = Some(String::from("NOTHING COPIED"));
+                    return vec![];
+                };
+                let pattern = (**pattern).clone(); // the clipboard can be pasted again
+                self.snapshot_pattern(pi);
+                self.state.patterns[pi] = pattern;
+                self.notice = Some(format!("PASTE PAT {}", pi + 1));
+                vec![]
+            }
+            InputEvent::QueuePattern(n) => {
+                // Start a new chain with this pattern; it kicks in when the current loop ends
+                self.queued_pattern = Some(n);
+                self.state.pattern_chain = vec![n];
                 vec![]
             }
             InputEvent::ChainPattern(n) => {
@@ -192,12 +818,13 @@ impl Middle {
                 vec![]
             }
             InputEvent::SetVolume(n) => {
-                self.state.master_volume = n; // 1-16
+                self.state.master_volume = n as f32; // 1-16
                 vec![]
             }
             InputEvent::ToggleStep(n) => {
                 let pi = self.state.selected_pattern as usize;
                 let si = self.state.selected_sound as usize;
+                self.snapshot_pattern(pi);
                 let step = &mut self.state.patterns[pi].tracks[si].steps[n as usize];
                 step.active = !step.active;
                 if !step.active {
@@ -206,6 +833,9 @@ impl Middle {
                     step.gain_lock = None;
                     step.filter_cutoff_lock = None;
                     step.filter_resonance_lock = None;
+                    step.probability = None;
+                    step.nudge = 0.0;
+                    step.ratchet = 0;
                     step.effect = None;
                 }
                 vec![]
@@ -215,27 +845,89 @@ impl Middle {
                 let pi = self.state.selected_pattern as usize;
                 let si = self.state.selected_sound as usize;
                 let pitch_mult = Self::pad_to_major_scale_pitch(n);
-                let step = &mut self.state.patterns[pi].tracks[si].steps[quantized_step];
+                let gain = self.state.sounds[si].gain;
+                let track = &mut self.state.patterns[pi].tracks[si];
+                let step = &mut track.steps[track.wrap(quantized_step)];
                 step.active = true;
                 step.pitch_lock = Some(pitch_mult);
+                if velocity < 1.0 {
+                    step.gain_lock = Some(gain * velocity); // a soft MIDI hit records soft
+                }
                 // Also trigger immediately at the recorded pitch so you hear what you played
-                self.trigger_sound_with_pitch(self.state.selected_sound, Some(pitch_mult))
+                let mut cmds = self.trigger_sound_with_pitch(self.state.selected_sound, Some(pitch_mult));
+                Self::scale_trigger_gain(&mut cmds, velocity);
+                cmds
             }
             InputEvent::SetRealtimeEffect(fx_num) => {
-                // Kill old effect voices before switching to new effect
-                let mut cmds = vec![AudioCommand::StopAllVoices];
-                self.active_rt_effect = Some(fx_num);
-                if self.write_mode {
-                    let pi = self.state.selected_pattern as usize;
-                    let sound_idx = self.state.selected_sound as usize;
-                    let si = self.current_step as usize;
-                    self.state.patterns[pi].tracks[sound_idx].steps[si].effect =
-                        Some(fx_num);
+                if self.fx_quantized() {
+                    self.pending_rt_effect = Some(Some(fx_num));
+                    return vec![];
                 }
-                cmds
+                self.engage_rt_effect(Some(fx_num))
+            }
+            InputEvent::ToggleDcFilter => {
+                self.state.dc_filter = !self.state.dc_filter;
+                vec![AudioCommand::SetDcFilter { enabled: self.state.dc_filter }]
+            }
+            InputEvent::ToggleStutterSync => {
+                self.state.stutter_sync = !self.state.stutter_sync;
+                vec![]
+            }
+            InputEvent::ToggleNormalizeLoads => {
+                self.normalize_on_load = !self.normalize_on_load;
+                self.notice = Some(String::from(if self.normalize_on_load { "LEVEL LOADS ON" } else { "LEVEL LOADS OFF" }));
+                vec![]
+            }
+            InputEvent::ToggleNormalize => {
+                self.state.normalize_recordings = !self.state.normalize_recordings;
+                self.notice = Some(String::from(if self.state.normalize_recordings { "NORMALIZE ON" } else { "NORMALIZE OFF" }));
+                vec![AudioCommand::SetNormalizeRecordings(self.state.normalize_recordings)]
+            }
+            InputEvent::ToggleTrimSilence => {
+                self.state.trim_recordings = !self.state.trim_recordings;
+                self.notice = Some(String::from(if self.state.trim_recordings { "TRIM TAIL ON" } else { "TRIM TAIL OFF" }));
+                vec![AudioCommand::SetTrimRecordings(self.state.trim_recordings)]
+            }
+            InputEvent::ToggleInputMonitor => {
+                self.state.input_monitor = !self.state.input_monitor;
+                self.notice = Some(String::from(match (self.state.input_monitor, self.monitor_guarded) {
+                    (false, _) => "MONITOR OFF",
+                    (true, false) => "MONITOR ON",
+                    (true, true) => "MONITOR HELD: SAME DEVICE",
+                }));
+                vec![AudioCommand::SetInputMonitor(self.state.input_monitor)]
+            }
+            InputEvent::AdjustMonitorGain(delta) => {
+                let gain = ((self.state.monitor_gain + delta) * 20.0).round() / 20.0;
+                self.state.monitor_gain = gain.clamp(0.0, 1.0);
+                self.notice = Some(format!("MONITOR {}%", (self.state.monitor_gain * 100.0).round()));
+                vec![AudioCommand::SetMonitorGain(self.state.monitor_gain)]
+            }
+            InputEvent::ToggleMetronome => {
+                self.metronome_on = !self.metronome_on;
+                self.notice = Some(String::from(if self.metronome_on { "CLICK ON" } else { "CLICK OFF" }));
+                vec![]
+            }
+            InputEvent::CycleMacroTarget { knob, dir } => {
+                let targets = &mut self.state.macro_targets;
+                if knob == 0 {
+                    targets.0 = targets.0.cycle(dir);
+                } else {
+                    targets.1 = targets.1.cycle(dir);
+                }
+                vec![]
+            }
+            InputEvent::CycleFxQuantize(dir) => {
+                let i = FX_QUANTIZE_DIVISIONS.iter()
+                    .position(|&d| d == self.state.fx_quantize)
+                    .unwrap_or(0) as i32;
+                let n = FX_QUANTIZE_DIVISIONS.len() as i32;
+                self.state.fx_quantize = FX_QUANTIZE_DIVISIONS[(i + dir).rem_euclid(n) as usize];
+                vec![]
             }
             InputEvent::ClearRealtimeEffect => {
                 self.active_rt_effect = None;
+                self.pending_rt_effect = None;
                 // Always clear ALL saved per-step effects in the current pattern
                 let pi = self.state.selected_pattern as usize;
                 for track in &mut self.state.patterns[pi].tracks {
@@ -246,25 +938,199 @@ impl Middle {
                 // Kill lingering stutter/loop voices immediately
                 vec![AudioCommand::StopAllVoices]
             }
+            InputEvent::SetTrackEffect(fx) => {
+                let pi = self.state.selected_pattern as usize;
+                let si = self.state.selected_sound as usize;
+                self.state.patterns[pi].tracks[si].effect = fx;
+                vec![]
+            }
+            InputEvent::SetTrackLength(n) => {
+                let pi = self.state.selected_pattern as usize;
+                let si = self.state.selected_sound as usize;
+                self.state.patterns[pi].tracks[si].length = (n as usize).clamp(1, STEPS_PER_PATTERN);
+                self.notice = Some(format!("LEN {}", n));
+                vec![]
+            }
             InputEvent::DeleteSound => {
-                self.state.sounds[self.state.selected_sound as usize] = SoundSlot::default();
+                let slot = self.state.selected_sound;
+                let mut sound = self.state.sounds[slot as usize].clone();
+                sound.sample_id = None; // unregistered below; undo loads the file again
+                self.push_undo(Undo::Sound(slot, Box::new(sound)));
+                self.clear_slot(slot).into_iter().collect()
+            }
+            InputEvent::GeneratePattern { density } => {
+                let pi = self.state.selected_pattern as usize;
+                self.snapshot_pattern(pi);
+                self.gen_seed = self.gen_seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let hits = pipeline::generate::generate_pattern(&mut self.state, pi, density, self.gen_seed);
+                self.notice = Some(if hits == 0 {
+                    String::from("GEN: NO SOUNDS IN BANK")
+                } else {
+                    format!("GEN {:.0}% {} HITS", density * 100.0, hits)
+                });
+                vec![]
+            }
+            InputEvent::Undo => {
+                let Some(entry) = self.undo.pop() else {
+                    self.notice = Some(String::from("NOTHING TO UNDO"));
+                    return vec![];
+                };
+                self.notice = Some(String::from("UNDONE"));
+                let mut cmds = Vec::new();
+                let replaced = self.restore(entry, &mut cmds);
+                self.redo.push(replaced);
+                cmds
+            }
+            InputEvent::Redo => {
+                let Some(entry) = self.redo.pop() else {
+                    self.notice = Some(String::from("NOTHING TO REDO"));
+                    return vec![];
+                };
+                self.notice = Some(String::from("REDONE"));
+                let mut cmds = Vec::new();
+                let replaced = self.restore(entry, &mut cmds);
+                self.undo.push(replaced); // no push_undo: that would drop the rest of the redos
+                cmds
+            }
+            InputEvent::ClearAllPatterns => {
+                self.clear_all_pending = true;
+                self.notice = Some(String::from("CLEAR ALL? (y)"));
+                vec![]
+            }
+            InputEvent::ConfirmClearAll(yes) => {
+                if yes && clear_all_asked {
+                    let patterns = std::mem::take(&mut self.state.patterns);
+                    self.push_undo(Undo::AllPatterns(Box::new(patterns)));
+                    self.queued_pattern = None;
+                    self.notice = Some(String::from("ALL CLEARED"));
+                }
                 vec![]
             }
             InputEvent::ClearTrack => {
                 let pattern_idx = self.state.selected_pattern as usize;
                 let sound_idx = self.state.selected_sound as usize;
+                self.snapshot_pattern(pattern_idx);
                 self.state.patterns[pattern_idx].tracks[sound_idx] = Default::default();
                 vec![]
             }
+            InputEvent::PadVelocity(_) => vec![], // taken above
             InputEvent::TriggerPad(n) => {
                 let pitch = Self::pad_to_major_scale_pitch(n);
-                self.trigger_sound_with_pitch(self.state.selected_sound, Some(pitch))
+                let mut cmds = self.trigger_sound_with_pitch(self.state.selected_sound, Some(pitch));
+                Self::scale_trigger_gain(&mut cmds, velocity);
+                if self.hold && !cmds.is_empty() {
+                    // New drone replaces the old one
+                    if let Some(sample_id) = self.held_voice {
+                        cmds.insert(0, AudioCommand::StopVoices { sample_id });
+                    }
+                    for cmd in &mut cmds {
+                        if let AudioCommand::Trigger(params) = cmd {
+                            params.hold = true;
+                            self.held_voice = Some(params.sample_id);
+                        }
+                    }
+                }
+                cmds
+            }
+            InputEvent::ToggleHold => {
+                self.hold = !self.hold;
+                // Releasing hold lets go of the drone
+                match self.held_voice.take() {
+                    Some(sample_id) if !self.hold => vec![AudioCommand::StopVoices { sample_id }],
+                    held => {
+                        self.held_voice = held;
+                        vec![]
+                    }
+                }
+            }
+
+            InputEvent::GrabBar => {
+                self.grab_armed = self.playing;
+                self.notice = Some(String::from(if self.playing { "GRAB NEXT BAR" } else { "PLAY TO GRAB" }));
+                vec![]
+            }
+            InputEvent::ToggleLoopRoll => {
+                if let Some(sample_id) = self.loop_roll.take() {
+                    return vec![AudioCommand::ClearLoopRegion { sample_id }];
+                }
+                // the drone if there is one, otherwise whatever the selected sound is playing
+                let sound = &self.state.sounds[self.state.selected_sound as usize];
+                let Some(sample_id) = self.held_voice.or(sound.sample_id) else {
+                    return vec![];
+                };
+                self.loop_roll = Some(sample_id);
+                let step_frames = self.secs_per_step() as f32 * self.sample_rate;
+                vec![AudioCommand::SetLoopRegion { sample_id, start: 0.0, end: step_frames }]
             }
 
             // semantic knob events resolved and sent by tui
 
             InputEvent::AdjustSwing(delta) => {
-                self.state.swing = (self.state.swing + delta).clamp(0.0, 1.0);
+                // 1 displayed percent per click, snapped so the readout never drifts
+                let pct = (self.state.swing * SWING_DISPLAY_MAX + delta * 20.0).round();
+                self.state.swing = (pct / SWING_DISPLAY_MAX).clamp(0.0, 1.0);
+                vec![]
+            }
+            InputEvent::AdjustTrackSwing(delta) => {
+                // same snapping as the global swing; starts from it the first time
+                let pi = self.state.selected_pattern as usize;
+                let global = self.state.swing;
+                let track = &mut self.state.patterns[pi].tracks[self.state.selected_sound as usize];
+                let pct = (track.swing.unwrap_or(global) * SWING_DISPLAY_MAX + delta * 20.0).round();
+                track.swing = Some((pct / SWING_DISPLAY_MAX).clamp(0.0, 1.0));
+                vec![]
+            }
+            InputEvent::AdjustTuning(delta) => {
+                // 1 Hz per click, within about a semitone either side of A=440
+                let hz = ((self.state.tuning_hz + delta * 20.0) * 10.0).round() / 10.0;
+                self.state.tuning_hz = hz.clamp(415.0, 466.0);
+                vec![]
+            }
+            InputEvent::AdjustVolume(delta) => {
+                // a tenth of a step per click
+                let vol = ((self.state.master_volume + delta * 2.0) * 10.0).round() / 10.0;
+                self.state.master_volume = vol.clamp(1.0, 16.0);
+                vec![]
+            }
+            InputEvent::AdjustRecordThreshold(delta) => {
+                // once it's capturing the threshold has done its job
+                if !self.recording_armed || self.is_capturing {
+                    return vec![];
+                }
+                // multiplicative, so the quiet end gets as many steps as the loud one
+                let factor = if delta > 0.0 { 1.25 } else { 0.8 };
+                let threshold = (self.state.record_threshold * factor).clamp(0.001, 0.5);
+                self.state.record_threshold = threshold;
+                self.notice = Some(format!("THRESH {:.1}%", threshold * 100.0));
+                vec![AudioCommand::SetRecordThreshold(threshold)]
+            }
+            InputEvent::TapTempo => {
+                let now = Instant::now();
+                if self.taps.back().is_some_and(|t| now.duration_since(*t).as_secs_f64() > TAP_TEMPO_RESET_SECS) {
+                    self.taps.clear();
+                }
+                if self.taps.len() == TAP_TEMPO_TAPS {
+                    self.taps.pop_front();
+                }
+                self.taps.push_back(now);
+
+                // median gap, so one sloppy tap doesn't yank the tempo around
+                let mut gaps: Vec<f64> = self.taps.iter().zip(self.taps.iter().skip(1))
+                    .map(|(a, b)| b.duration_since(*a).as_secs_f64())
+                    .collect();
+                if gaps.is_empty() {
+                    self.notice = Some(String::from("TAP"));
+                    return vec![];
+                }
+                gaps.sort_by(f64::total_cmp);
+                let mid = gaps.len() / 2;
+                let gap = if gaps.len().is_multiple_of(2) { (gaps[mid - 1] + gaps[mid]) / 2.0 } else { gaps[mid] };
+                self.state.bpm = (60.0 / gap).clamp(60.0, 240.0) as f32;
+                self.notice = Some(format!("TAP {:.0} BPM", self.state.bpm));
+                vec![]
+            }
+            InputEvent::SyncTempo(bpm) => {
+                self.state.bpm = bpm.clamp(60.0, 240.0);
                 vec![]
             }
             InputEvent::AdjustBpm(delta) => {
@@ -274,9 +1140,9 @@ impl Middle {
             InputEvent::PitchLockStep(delta) => {
                 let pi = self.state.selected_pattern as usize;
                 let sound_idx = self.state.selected_sound as usize;
-                let si = self.current_step as usize;
                 let sound = &self.state.sounds[sound_idx];
-                let step = &mut self.state.patterns[pi].tracks[sound_idx].steps[si];
+                let track = &mut self.state.patterns[pi].tracks[sound_idx];
+                let step = &mut track.steps[track.wrap(self.pattern_steps)];
                 let current = step.pitch_lock.unwrap_or(sound.pitch);
                 // Multiplicative: ~0.24 semitones per click, round-trips cleanly
                 let semitones = delta * 4.8;
@@ -287,9 +1153,9 @@ impl Middle {
             InputEvent::GainLockStep(delta) => {
                 let pi = self.state.selected_pattern as usize;
                 let sound_idx = self.state.selected_sound as usize;
-                let si = self.current_step as usize;
                 let sound = &self.state.sounds[sound_idx];
-                let step = &mut self.state.patterns[pi].tracks[sound_idx].steps[si];
+                let track = &mut self.state.patterns[pi].tracks[sound_idx];
+                let step = &mut track.steps[track.wrap(self.pattern_steps)];
                 let current = step.gain_lock.unwrap_or(sound.gain);
                 step.gain_lock = Some((current + delta).clamp(0.0, 1.0));
                 vec![]
@@ -311,37 +1177,48 @@ impl Middle {
                 let sound = &mut self.state.sounds[self.state.selected_sound as usize];
                 let factor = if delta > 0.0 { 1.1 } else { 0.9 };
                 sound.filter_cutoff = (sound.filter_cutoff * factor).clamp(20.0, 20000.0);
-                vec![]
+                self.sustained_filter()
             }
             InputEvent::AdjustFilterResonance(delta) => {
                 let sound = &mut self.state.sounds[self.state.selected_sound as usize];
                 sound.filter_resonance = (sound.filter_resonance + delta).clamp(0.0, 1.0);
-                vec![]
+                self.sustained_filter()
             }
             InputEvent::AdjustTrimStart(delta) => {
                 let sound = &mut self.state.sounds[self.state.selected_sound as usize];
-                let max = sound.buffer_len.saturating_sub(1);
-                // Much finer: 0.2% of buffer per click (was 5%)
-                let step_size = (max as f32 * delta.abs() * 0.04).max(1.0) as usize;
-                if delta > 0.0 {
-                    sound.trim_start = (sound.trim_start + step_size).min(max);
-                } else {
-                    sound.trim_start = sound.trim_start.saturating_sub(step_size);
-                }
-                let remaining = sound.buffer_len.saturating_sub(sound.trim_start);
-                sound.length = sound.length.min(remaining);
+                self.trim_at_floor = Self::adjust_trim(sound, delta, 0.0);
                 vec![]
             }
             InputEvent::AdjustTrimLength(delta) => {
                 let sound = &mut self.state.sounds[self.state.selected_sound as usize];
-                let max = sound.buffer_len.saturating_sub(sound.trim_start);
-                // A little finer: 1% of buffer per click (was 5%)
-                let step_size = (max as f32 * delta.abs() * 0.2).max(1.0) as usize;
-                if delta > 0.0 {
-                    sound.length = (sound.length + step_size).min(max);
-                } else {
-                    sound.length = sound.length.saturating_sub(step_size).max(1);
-                }
+                self.trim_at_floor = Self::adjust_trim(sound, 0.0, delta);
+                vec![]
+            }
+            InputEvent::AdjustAttack(delta) => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.attack = Self::adjust_env_secs(sound.attack, delta);
+                vec![]
+            }
+            InputEvent::AdjustDecay(delta) => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.decay = Self::adjust_env_secs(sound.decay, delta);
+                vec![]
+            }
+            InputEvent::AdjustSustain(delta) => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.sustain = (sound.sustain + delta).clamp(0.0, 1.0);
+                vec![]
+            }
+            InputEvent::AdjustRelease(delta) => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                sound.release = Self::adjust_env_secs(sound.release, delta);
+                vec![]
+            }
+            InputEvent::AdjustPan(delta) => {
+                let sound = &mut self.state.sounds[self.state.selected_sound as usize];
+                // twice the knob step, so centre to either side is the same turn as gain 0 to 1
+                let pan = (sound.pan + delta * 2.0).clamp(-1.0, 1.0);
+                sound.pan = if pan.abs() < 1e-3 { 0.0 } else { pan }; // lands back on dead centre
                 vec![]
             }
 
@@ -367,66 +1244,152 @@ impl Middle {
                 s.gain_lock = Some((current + delta).clamp(0.0, 1.0));
                 vec![]
             }
+            InputEvent::LockStepNudgeAt { step, delta } => {
+                let pi = self.state.selected_pattern as usize;
+                let sound_idx = self.state.selected_sound as usize;
+                let s = &mut self.state.patterns[pi].tracks[sound_idx].steps[step as usize];
+                // 5% of a step a click, up to half a step early or late
+                s.nudge = ((s.nudge + delta) * 20.0).round().clamp(-10.0, 10.0) / 20.0;
+                self.notice = Some(format!("NUDGE {:+.0}%", s.nudge * 100.0));
+                vec![]
+            }
+            InputEvent::LockStepRatchetAt { step, dir } => {
+                let pi = self.state.selected_pattern as usize;
+                let sound_idx = self.state.selected_sound as usize;
+                let s = &mut self.state.patterns[pi].tracks[sound_idx].steps[step as usize];
+                s.ratchet = (s.ratchet.max(1) as i32 + dir).clamp(1, MAX_RATCHET as i32) as u8;
+                self.notice = Some(format!("RATCHET x{}", s.ratchet));
+                vec![]
+            }
+            InputEvent::LockStepProbabilityAt { step, delta } => {
+                let pi = self.state.selected_pattern as usize;
+                let sound_idx = self.state.selected_sound as usize;
+                let s = &mut self.state.patterns[pi].tracks[sound_idx].steps[step as usize];
+                // 5% a click, and back to "always" once it's turned all the way up
+                let chance = (s.probability.unwrap_or(1.0) + delta).clamp(0.0, 1.0);
+                s.probability = (chance < 1.0).then_some(chance);
+                self.notice = Some(format!("CHANCE {:.0}%", chance * 100.0));
+                vec![]
+            }
+
+            InputEvent::SettingsMove(delta) => {
+                if let Some(menu) = &mut self.device_menu {
+                    menu.move_cursor(delta);
+                }
+                vec![]
+            }
 
             // Handled in main loop (needs AudioHandle), not here
             InputEvent::CycleInputDevice => vec![],
-            InputEvent::BouncePattern => vec![],
+            InputEvent::ToggleBrowser => vec![],
+            InputEvent::BrowserMove(_) => vec![],
+            InputEvent::BrowserConfirm => vec![],
+            InputEvent::BouncePattern | InputEvent::BounceSong => vec![],
+            InputEvent::ToggleMasterCapture => {
+                if self.jam_started.take().is_some() {
+                    self.notice = Some(String::from("JAM STOP"));
+                    vec![AudioCommand::StopMasterCapture]
+                } else {
+                    self.jam_started = Some(Instant::now());
+                    self.notice = Some(String::from("JAM REC"));
+                    // reserved here, off the audio thread; the pages aren't touched until frames land in them
+                    let cap = (MASTER_CAPTURE_MAX_SECS * self.sample_rate) as usize;
+                    vec![AudioCommand::StartMasterCapture { buffer: Vec::with_capacity(cap) }]
+                }
+            }
+            InputEvent::ExportMidi => vec![],
+            InputEvent::ImportMidi => vec![],
+            InputEvent::DumpState => vec![],
+            InputEvent::Save => vec![],
+            InputEvent::SwitchProjectSlot(_) => vec![], // main loop: switch_project
+            InputEvent::ToggleSettings => vec![],
+            InputEvent::SettingsConfirm => vec![],
 
             InputEvent::Quit => vec![],
         }
     }
 
-    /// Generate the AudioCommands for each of the 16 steps of the current pattern.
-    /// Used for offline bounce rendering.
-    pub fn generate_pattern_commands(&self) -> Vec<Vec<AudioCommand>> {
-        let pi = self.state.selected_pattern as usize;
-        let pattern = &self.state.patterns[pi];
+    /// Where a step's hits land relative to its grid line, in steps: swing on the
+    /// off-beats plus the step's own nudge. Held to half a step either way, so two
+    /// neighbouring steps of a track can meet but never swap order.
+    fn step_offset(&self, track: &Track, si: usize) -> f64 {
+        // swing: off-beat sixteenths late by up to half a step
+        let swing = if si % 2 == 1 { track.swing.unwrap_or(self.state.swing) as f64 / 2.0 } else { 0.0 };
+        (swing + track.steps[si].nudge as f64).clamp(-0.5, 0.5)
+    }
+
+    /// The hits track `ti` of pattern `pi` makes at `position`, each with its offset
+    /// from the grid line in steps (step_offset, plus the ratchet). `early` picks the
+    /// hits nudged ahead of their line, which go out a step ahead; the rest fire on their
+    /// own step. A step that fails its probability roll, or has nothing loaded, makes none.
+    fn step_hits(&mut self, pi: usize, ti: usize, position: usize, early: bool) -> Vec<(f64, AudioCommand)> {
+        let track = &self.state.patterns[pi].tracks[ti];
+        let si = track.wrap(position);
+        let step = &track.steps[si];
+        if !step.active {
+            return vec![];
+        }
+        let mut offset = self.step_offset(track, si);
+        if early != (offset < 0.0) {
+            if early || self.current_step != 0 {
+                return vec![];
+            }
+            offset = 0.0; // nudged early off the downbeat: can't reach back into the last bar
+        }
+        if let Some(chance) = step.probability
+            && self.chance.next_f32() >= chance
+        {
+            return vec![];
+        }
 
-        (0..STEPS_PER_PATTERN).map(|step_idx| {
-            let mut cmds = Vec::new();
-            for (sound_idx, track) in pattern.tracks.iter().enumerate() {
-                let step = &track.steps[step_idx];
+        // Real-time effect (y + pad) > per-step saved effect > track default
+        let fx = self.active_rt_effect.or(step.effect).or(track.effect);
+        let sound_idx = self.state.sound_for(pi, ti);
+        let mut triggers = self.trigger_commands(&self.state.sounds[sound_idx], Some(step), fx, 1.0);
+        if triggers.is_empty() {
+            return vec![];
+        }
+        if self.state.sidechain.source == Some(sound_idx as u8) {
+            triggers.extend(self.duck_commands());
+        }
+        step.ratchet_offsets()
+            .flat_map(|r| triggers.iter().map(move |cmd| (offset + r, cmd.clone())))
+            .collect()
+    }
+
+    /// Everything one loop of `pattern` fires, as (frame offset, command) pairs sorted
+    /// by offset — what tick() would send, minus the real-time parts (live fx, pattern
+    /// queueing, retrigger, probability rolls). Swing, nudges, ratchets, locks,
+    /// step/track effects and ducking are included.
+    pub fn schedule_pattern(&self, pattern: usize, bpm: f32, sample_rate: u32) -> Vec<(usize, AudioCommand)> {
+        let frames_per_step = 60.0 / (bpm.max(1.0) as f64 * 4.0) * sample_rate as f64;
+        let mut events = Vec::new();
+
+        for (sound_idx, track) in self.state.patterns[pattern].tracks.iter().enumerate() {
+            for step_idx in 0..STEPS_PER_PATTERN {
+                let si = track.wrap(step_idx);
+                let step = &track.steps[si];
                 if !step.active { continue; }
 
+                let sound_idx = self.state.sound_for(pattern, sound_idx);
                 let sound = &self.state.sounds[sound_idx];
-                let Some(sample_id) = sound.sample_id else { continue; };
-
-                let gain = step.gain_lock.unwrap_or(sound.gain)
-                    * (self.state.master_volume as f32 / 16.0);
-                let mut pitch = step.pitch_lock.unwrap_or(sound.pitch);
-
-                let fx = step.effect;
-                let (reverse, stutter_period_samples, pitch_mult, is_unison, unison_detune) =
-                    Self::derive_trigger_mods_from_fx(self.state.bpm, fx);
-                pitch *= pitch_mult;
-
-                cmds.push(AudioCommand::Trigger(TriggerParams {
-                    sample_id,
-                    trim_start: sound.trim_start,
-                    length: sound.length,
-                    gain,
-                    pitch,
-                    effect_chain: vec![],
-                    reverse,
-                    stutter_period_samples,
-                }));
+                let mut triggers = self.trigger_commands(sound, Some(step), step.effect.or(track.effect), 1.0);
+                if triggers.is_empty() { continue; }
+                if self.state.sidechain.source == Some(sound_idx as u8) {
+                    triggers.extend(self.duck_commands());
+                }
 
-                if is_unison {
-                    let detune_factor = 2.0_f32.powf(unison_detune / 1200.0);
-                    cmds.push(AudioCommand::Trigger(TriggerParams {
-                        sample_id,
-                        trim_start: sound.trim_start,
-                        length: sound.length,
-                        gain,
-                        pitch: pitch * detune_factor,
-                        effect_chain: vec![],
-                        reverse,
-                        stutter_period_samples,
-                    }));
+                // same timing as advance_step, early hits on the first step included
+                let offset = self.step_offset(track, si);
+                let offset = if step_idx == 0 { offset.max(0.0) } else { offset };
+                for r in step.ratchet_offsets() {
+                    let at = (step_idx as f64 + offset + r) * frames_per_step;
+                    events.extend(triggers.iter().map(|cmd| (at as usize, cmd.clone())));
                 }
             }
-            cmds
-        }).collect()
+        }
+        events.sort_by_key(|(at, _)| *at); // stable, so each trigger keeps its duck right behind it
+        events
     }
 
     pub fn tick(&mut self, elapsed: f64) -> Vec<AudioCommand> {
@@ -436,89 +1399,106 @@ impl Middle {
 
         self.step_accumulator += elapsed;
 
+        let mut commands = Vec::new();
+        let mut fired = 0;
+
+        // swung hits from earlier steps whose delay is up
+        for (left, _) in &mut self.swung {
+            *left -= elapsed;
+        }
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.swung)
+            .into_iter()
+            .partition(|(left, _)| *left <= 0.0);
+        self.swung = waiting;
+        commands.extend(due.into_iter().map(|(_, cmd)| cmd));
+
+        while self.step_accumulator >= self.secs_per_step() {
+            if fired == MAX_CATCHUP_STEPS {
+                // the UI stalled: keep our place in the step, forget the missed ones
+                self.step_accumulator %= self.secs_per_step();
+                break;
+            }
+            self.step_accumulator -= self.secs_per_step();
+            self.advance_step(&mut commands);
+            fired += 1;
+        }
+
+        commands
+    }
+
+    /// How long until the sequencer wants its next tick, so the main loop can
+    /// sleep exactly that long instead of waiting on a redraw.
+    pub fn until_next_step(&self) -> Option<f64> {
+        let step = (self.secs_per_step() - self.step_accumulator).max(0.0);
+        let swung = self.swung.iter().map(|(left, _)| left.max(0.0)).fold(step, f64::min);
+        self.playing.then_some(swung)
+    }
+
+    fn secs_per_step(&self) -> f64 {
         // Effect 13 (6/8 quantize): triplet swing timing
         let base = 60.0 / (self.state.bpm as f64 * 4.0);
-        let secs_per_step = if self.active_rt_effect == Some(13) {
+        if self.active_rt_effect == Some(13) {
             // Alternate long/short steps to create a triplet feel (2:1 ratio)
             if self.current_step % 2 == 0 { base * 4.0 / 3.0 } else { base * 2.0 / 3.0 }
         } else {
             base
-        };
-
-        let mut commands = Vec::new();
-
-        while self.step_accumulator >= secs_per_step {
-            self.step_accumulator -= secs_per_step;
-            self.advance_step(&mut commands);
         }
-
-        commands
     }
 
     /// Advance to the next step and trigger any active sounds.
     fn advance_step(&mut self, commands: &mut Vec<AudioCommand>) {
         self.current_step = (self.current_step + 1) % STEPS_PER_PATTERN as u8;
-
-        // pattern chaining doesn't do anything now, but will
-        if self.current_step == 0 && !self.state.pattern_chain.is_empty() {
-            self.chain_position =
-                (self.chain_position + 1) % self.state.pattern_chain.len();
-            self.state.selected_pattern =
-                self.state.pattern_chain[self.chain_position];
-        }
-
-        let pi = self.state.selected_pattern as usize;
-        let si = self.current_step as usize;
-        let pattern = &self.state.patterns[pi];
-
-        for (sound_idx, track) in pattern.tracks.iter().enumerate() {
-            let step = &track.steps[si];
-            if !step.active {
-                continue;
+        self.pattern_steps = self.pattern_steps.wrapping_add(1);
+
+        // A queued pattern takes over at the loop point, otherwise follow the chain
+        if self.current_step == 0 {
+            self.bar += 1;
+            let previous = self.playing_pattern;
+            if let Some(q) = self.queued_pattern.take() {
+                self.playing_pattern = q;
+                self.chain_position = 0;
+            } else if !self.state.pattern_chain.is_empty() {
+                self.chain_position =
+                    (self.chain_position + 1) % self.state.pattern_chain.len();
+                self.playing_pattern = self.state.pattern_chain[self.chain_position];
             }
+            // a new pattern starts every track from its top; the same one keeps its polymeter going
+            if self.playing_pattern != previous {
+                self.pattern_steps = 0;
+            }
+            if std::mem::take(&mut self.grab_armed) {
+                commands.extend(self.start_grab());
+            }
+        }
 
-            let sound = &self.state.sounds[sound_idx];
-            let Some(sample_id) = sound.sample_id else {
-                continue;
-            };
-
-            let gain = step.gain_lock.unwrap_or(sound.gain)
-                * (self.state.master_volume as f32 / 16.0);
-            let mut pitch = step.pitch_lock.unwrap_or(sound.pitch);
+        commands.extend(self.metronome_tick());
 
-            // Real-time effect (y + pad) takes priority over per-step saved effect
-            let fx = self.active_rt_effect.or(step.effect);
-            let effect_chain = self.build_effect_chain(sound, fx);
+        // Stutters (fx 9/10) divide a step evenly, so restarting them here keeps
+        // every overlapping stutter voice on the beat
+        if self.state.stutter_sync && matches!(self.active_rt_effect, Some(9) | Some(10)) {
+            commands.push(AudioCommand::SyncStutter);
+        }
 
-            // Derive voice-level modifiers from the active effect
-            let (reverse, stutter_period_samples, pitch_mult, is_unison, unison_detune) =
-                Self::derive_trigger_mods_from_fx(self.state.bpm, fx);
-            pitch *= pitch_mult;
+        // Buffered fx changes land on the quantize grid
+        let div = self.state.fx_quantize.max(1);
+        if self.current_step.is_multiple_of(div)
+            && let Some(fx) = self.pending_rt_effect.take()
+        {
+            commands.extend(self.engage_rt_effect(fx));
+        }
 
-            commands.push(AudioCommand::Trigger(TriggerParams {
-                sample_id,
-                trim_start: sound.trim_start,
-                length: sound.length,
-                gain,
-                pitch,
-                effect_chain: effect_chain.clone(),
-                reverse,
-                stutter_period_samples,
-            }));
-
-            // Unison: trigger a second voice with slight detune
-            if is_unison {
-                let detune_factor = 2.0_f32.powf(unison_detune / 1200.0);
-                commands.push(AudioCommand::Trigger(TriggerParams {
-                    sample_id,
-                    trim_start: sound.trim_start,
-                    length: sound.length,
-                    gain,
-                    pitch: pitch * detune_factor,
-                    effect_chain,
-                    reverse,
-                    stutter_period_samples,
-                }));
+        let pi = self.playing_pattern as usize;
+        // Swing and nudges move hits off the grid line. tick() already took this step's
+        // time off the accumulator, so what's left is how late we are firing it.
+        let step_secs = 60.0 / (self.state.bpm as f64 * 4.0);
+        for ti in 0..self.state.patterns[pi].tracks.len() {
+            for (offset, cmd) in self.step_hits(pi, ti, self.pattern_steps, false) {
+                let delay = offset * step_secs - self.step_accumulator;
+                if delay > 0.0 {
+                    self.swung.push((delay, cmd));
+                } else {
+                    commands.push(cmd);
+                }
             }
         }
 
@@ -526,13 +1506,27 @@ impl Middle {
         let has_retrigger = self.active_rt_effect == Some(14) || {
             let pattern = &self.state.patterns[pi];
             pattern.tracks.iter().any(|t| {
-                let step = &t.steps[si];
+                let step = &t.steps[t.wrap(self.pattern_steps)];
                 step.active && step.effect == Some(14)
             })
         };
         if has_retrigger {
-            // Next advance_step will increment this to 0
+            // Next advance_step will increment these to 0
             self.current_step = STEPS_PER_PATTERN as u8 - 1;
+            self.pattern_steps = usize::MAX;
+        }
+
+        // Hits nudged early belong to the next step but have to go out during this one.
+        // Not across the bar line though: the next bar may be another pattern (or a
+        // retrigger), so step_hits fires those on the downbeat instead.
+        if (self.current_step as usize) < STEPS_PER_PATTERN - 1 {
+            let next = self.pattern_steps.wrapping_add(1);
+            for ti in 0..self.state.patterns[pi].tracks.len() {
+                for (offset, cmd) in self.step_hits(pi, ti, next, true) {
+                    let delay = (1.0 + offset) * step_secs - self.step_accumulator;
+                    self.swung.push((delay, cmd));
+                }
+            }
         }
     }
 
@@ -543,16 +1537,24 @@ impl Middle {
 
     fn rebuild_display(&mut self) {
         // basic display refreshing
-        let (a_label, b_label) = self.param_page.knob_labels();
+        let (target_a, target_b) = self.param_page.targets().unwrap_or(self.state.macro_targets);
 
         let mut leds = [LedState::Off; STEPS_PER_PATTERN];
+        let active_length = {
+            let track = &self.state.patterns[self.state.selected_pattern as usize]
+                .tracks[self.state.selected_sound as usize];
+            track.length.clamp(1, STEPS_PER_PATTERN)
+        };
 
         if self.held.sound {
-            leds[self.state.selected_sound as usize] = LedState::OnMedium;
+            leds[self.state.selected_sound as usize % NUM_SOUNDS] = LedState::OnMedium;
         } else if self.held.pattern {
             leds[self.state.selected_pattern as usize] = LedState::OnMedium;
+            if let Some(q) = self.queued_pattern {
+                leds[q as usize] = LedState::Blink;
+            }
         } else if self.held.bpm {
-            for i in 0..self.state.master_volume as usize {
+            for i in 0..self.state.master_volume.round() as usize {
                 if i < STEPS_PER_PATTERN {
                     leds[i] = LedState::OnMedium;
                 }
@@ -566,7 +1568,10 @@ impl Middle {
             let si = self.state.selected_sound as usize;
             let track = &self.state.patterns[pi].tracks[si];
             for (i, step) in track.steps.iter().enumerate() {
-                if step.active {
+                if i >= active_length {
+                    // past the loop boundary: mark so the user sees where the track wraps
+                    leds[i] = LedState::OutOfRange;
+                } else if step.active {
                     leds[i] = LedState::OnMedium;
                 }
             }
@@ -577,45 +1582,71 @@ impl Middle {
         } else {
             None
         };
-        if let Some(ps) = playing_step {
+        // the playhead follows the selected track, so a short track shows its own loop
+        if self.playing && self.playing_pattern == self.state.selected_pattern {
+            let track = &self.state.patterns[self.playing_pattern as usize]
+                .tracks[self.state.selected_sound as usize];
+            leds[track.wrap(self.pattern_steps)] = LedState::Blink;
+        } else if let Some(ps) = playing_step {
             leds[ps as usize] = LedState::Blink;
         }
 
         // Knob values (normalized 0.0-1.0 for display)
-        let sound = &self.state.sounds[self.state.selected_sound as usize];
-        let (knob_a, knob_b) = match self.param_page {
-            ParamPage::Tone => (
-                // pitch: 0.5-2.0 mapped to 0.0-1.0 via log2
-                // log2(0.5)=-1 → 0.0, log2(1.0)=0 → 0.5, log2(2.0)=1 → 1.0
-                ((sound.pitch.log2() + 1.0) / 2.0).clamp(0.0, 1.0),
-                sound.gain,
-            ),
-            ParamPage::Filter => (
-                // cutoff: 20-20000 mapped to 0.0-1.0 (log scale approximation)
-                ((sound.filter_cutoff / 20.0).ln() / (1000.0_f32).ln()).clamp(0.0, 1.0),
-                sound.filter_resonance,
-            ),
-            ParamPage::Trim => (
-                if sound.buffer_len > 0 {
-                    sound.trim_start as f32 / sound.buffer_len as f32
-                } else {
-                    0.0
-                },
-                if sound.buffer_len > 0 {
-                    sound.length as f32 / sound.buffer_len as f32
-                } else {
-                    1.0
-                },
-            ),
-        };
+        let knob_a = self.knob_value(target_a);
+        let knob_b = self.knob_value(target_b);
 
         // Display text
-        let display_text = if self.held.bpm {
-            format!("VOL {}", self.state.master_volume)
+        // a notice answers the key just pressed, so it wins over the held-button readouts
+        let display_text = if let Some(notice) = &self.notice {
+            notice.clone()
+        } else if self.held.bpm && self.held.sound {
+            let pi = self.state.selected_pattern as usize;
+            let track = &self.state.patterns[pi].tracks[self.state.selected_sound as usize];
+            match track.swing {
+                Some(s) => format!("VOL {:.1} TRK SWING {}%", self.state.master_volume, (s * SWING_DISPLAY_MAX).round()),
+                None => format!("VOL {:.1} TRK SWING -", self.state.master_volume),
+            }
+        } else if self.held.bpm {
+            format!("VOL {:.1} SWING {}%", self.state.master_volume, self.swing_percent())
+        } else if self.held.sound && self.held.fx {
+            let pi = self.state.selected_pattern as usize;
+            match self.state.patterns[pi].tracks[self.state.selected_sound as usize].effect {
+                Some(fx) => format!("TRK FX {}", fx),
+                None => String::from("TRK FX -"),
+            }
+        } else if self.held.fx && self.param_page == ParamPage::Macro {
+            String::from("MACRO ASSIGN")
+        } else if self.held.fx {
+            let lock = if self.state.stutter_sync { " LOCK" } else { "" };
+            let preview = if self.held.preview { " PRV" } else { "" };
+            match self.state.fx_quantize {
+                0 => format!("FX Q OFF{}{}", lock, preview),
+                n => format!("FX Q {}ST{}{}", n, lock, preview),
+            }
         } else if self.held.sound {
-            format!("SND {}", self.state.selected_sound + 1)
+            let sound = &self.state.sounds[self.state.selected_sound as usize];
+            let rev = if sound.reverse { " REV" } else { "" };
+            let comp = if sound.compress { " CMP" } else { "" };
+            let cue = if sound.cue { " CUE" } else { "" };
+            let gate = match sound.length_mode {
+                LengthMode::Samples => String::new(),
+                mode => format!(" {}", mode.label()),
+            };
+            let sc = &self.state.sidechain;
+            let duck = if sc.source == Some(self.state.selected_sound) {
+                " KEY"
+            } else if sc.targets.contains(&self.state.selected_sound) {
+                " DUCK"
+            } else {
+                ""
+            };
+            format!("SND {} {}{}{}{}{}{}", Self::sound_label(self.state.selected_sound), sound.interp.label(), rev, comp, cue, gate, duck)
         } else if self.held.pattern {
-            format!("PAT {}", self.state.selected_pattern + 1)
+            let kit = self.state.patterns[self.state.selected_pattern as usize].kit
+                .map_or(String::new(), |b| format!(" KIT {}", (b'A' + b) as char));
+            format!("PAT {} A{:.0}{}", self.state.selected_pattern + 1, self.state.tuning_hz, kit)
+        } else if self.trim_at_floor {
+            String::from("TRIM MIN")
         } else {
             format!("{:.0} BPM", self.state.bpm)
         };
@@ -630,40 +1661,91 @@ impl Middle {
 
         self.display = DisplayState {
             leds,
+            active_length: active_length as u8,
             playing_step,
+            // 4 steps to the beat, 4 beats to the bar
+            position: playing_step.filter(|_| self.bar > 0).map(|s| (self.bar, s / 4 + 1)),
             write_mode: self.write_mode,
             playing: self.playing,
+            hold: self.hold,
             recording,
             param_page: self.param_page,
             selected_sound: self.state.selected_sound,
             selected_pattern: self.state.selected_pattern,
+            queued_pattern: self.queued_pattern,
+            playing_pattern: self.playing.then_some(self.playing_pattern),
+            chain_position: (self.playing && self.state.pattern_chain.len() > 1)
+                .then(|| (self.chain_position + 1, self.state.pattern_chain.len())),
             bpm: self.state.bpm,
+            tuning_hz: self.state.tuning_hz,
+            swing_pct: self.swing_percent(),
             display_text,
-            knob_a_label: a_label,
-            knob_b_label: b_label,
+            knob_a_label: target_a.label(),
+            knob_b_label: target_b.label(),
             knob_a_value: knob_a,
             knob_b_value: knob_b,
+            macro_targets: self.state.macro_targets,
+            sound_reversed: self.state.sounds[self.state.selected_sound as usize].reverse,
+            dirty: self.is_dirty(),
             input_device: self.input_device_name.clone(),
+            input_ok: self.input_ok,
+            monitor_held: self.state.input_monitor && self.monitor_guarded,
+            input_peak: self.input_peak,
+            peak: self.output_peak,
+            clipping: self.clipped_at.is_some_and(|t| t.elapsed().as_secs_f32() < CLIP_HOLD_SECS),
+            pad_peaks: {
+                let bank = self.state.selected_bank() * NUM_SOUNDS;
+                std::array::from_fn(|i| self.sound_peaks[bank + i])
+            },
+            capture_secs: self.capture_started.map_or(0.0, |t| t.elapsed().as_secs_f32()),
+            jam_secs: self.jam_started.map(|t| t.elapsed().as_secs_f32()),
+            voice_count: self.engine_load.0,
+            cpu_load: self.engine_load.1,
+            device_menu: self.device_menu.clone(),
+            browser: self.browser.clone(),
         };
     }
 
     fn empty_display() -> DisplayState {
         DisplayState {
             leds: [LedState::Off; STEPS_PER_PATTERN],
+            active_length: STEPS_PER_PATTERN as u8,
             playing_step: None,
+            position: None,
             write_mode: false,
             playing: false,
+            hold: false,
             recording: RecordingDisplay::Idle,
             param_page: ParamPage::Tone,
             selected_sound: 0,
             selected_pattern: 0,
+            queued_pattern: None,
+            playing_pattern: None,
+            chain_position: None,
             bpm: 120.0,
+            tuning_hz: REFERENCE_TUNING_HZ,
+            swing_pct: 0,
             display_text: String::from("120 BPM"),
             knob_a_label: "PITCH",
             knob_b_label: "GAIN",
             knob_a_value: 0.5,
             knob_b_value: 0.5,
+            macro_targets: (KnobTarget::Cutoff, KnobTarget::Gain),
+            sound_reversed: false,
+            dirty: false,
             input_device: String::from("default"),
+            input_ok: true,
+            monitor_held: false,
+            input_peak: 0.0,
+            peak: 0.0,
+            clipping: false,
+            pad_peaks: [0.0; NUM_SOUNDS],
+            capture_secs: 0.0,
+            jam_secs: None,
+            voice_count: 0,
+            cpu_load: 0,
+            device_menu: None,
+            browser: None,
         }
     }
 
@@ -672,11 +1754,23 @@ impl Middle {
         slot: u8,
         path: &Path,
         target_rate: u32,
-    ) -> anyhow::Result<AudioCommand> {
-        let (sample_id, buffer) = sample_loader::load(path, target_rate)?;
+    ) -> anyhow::Result<Vec<AudioCommand>> {
+        let (sample_id, mut buffer) = sample_loader::load(path, target_rate)?;
         let buf_len = buffer.data.len();
         let sound = &mut self.state.sounds[slot as usize];
         let is_fresh = sound.sample_path.is_empty();
+        // a new file follows the current setting; a reload does what was done the first time
+        if is_fresh {
+            sound.normalized = self.normalize_on_load;
+        }
+        if sound.normalized {
+            buffer.normalize(LOAD_NORMALIZE_PEAK);
+        }
+        // whatever the slot played before is now unreachable
+        let mut cmds: Vec<AudioCommand> = sound.sample_id
+            .map(|id| AudioCommand::UnregisterSample { id })
+            .into_iter()
+            .collect();
 
         sound.sample_path = path.to_string_lossy().into_owned();
         sound.sample_id = Some(sample_id);
@@ -695,13 +1789,14 @@ impl Middle {
             sound.length = sound.length.min(remaining).max(1);
         }
 
-        Ok(AudioCommand::RegisterSample { id: sample_id, buffer })
+        cmds.push(AudioCommand::RegisterSample { id: sample_id, buffer });
+        Ok(cmds)
     }
 
-    pub fn clear_slot(&mut self, slot: u8) { // deletes buffers after, say, deleting the wav and reloading pocketty
-        if (slot as usize) < self.state.sounds.len() {
-            self.state.sounds[slot as usize] = SoundSlot::default();
-        }
+    pub fn clear_slot(&mut self, slot: u8) -> Option<AudioCommand> { // deletes buffers after, say, deleting the wav and reloading pocketty
+        let sound = self.state.sounds.get_mut(slot as usize)?;
+        let old = std::mem::take(sound);
+        old.sample_id.map(|id| AudioCommand::UnregisterSample { id })
     }
 
     /// Called when the engine finishes a recording. Finds the slot that owns
@@ -722,8 +1817,8 @@ impl Middle {
         let filename = format!("rec_{:02}.wav", slot_idx);
         let wav_path = rec_dir.join(&filename);
 
-        const SAMPLE_RATE: u32 = 44100;
-        buffer.save_wav(&wav_path, SAMPLE_RATE)?;
+        // recordings (and grabs) come back at the engine's rate
+        buffer.save_wav(&wav_path, self.sample_rate as u32)?;
 
         let sound = &mut self.state.sounds[slot_idx];
         sound.sample_path = wav_path.to_string_lossy().into_owned();
@@ -744,60 +1839,431 @@ impl Middle {
     }
 
     // trigger for melodic style
+    fn scale_trigger_gain(cmds: &mut [AudioCommand], velocity: f32) {
+        for cmd in cmds {
+            if let AudioCommand::Trigger(params) = cmd {
+                params.gain *= velocity;
+            }
+        }
+    }
+
     fn trigger_sound_with_pitch(&self, slot: u8, pitch_override_mult: Option<f32>) -> Vec<AudioCommand> {
         let sound = &self.state.sounds[slot as usize];
+        self.trigger_commands(sound, None, self.active_rt_effect, pitch_override_mult.unwrap_or(1.0))
+    }
+
+    /// Every hit goes through here (live pads, the sequencer, bounce) so they can't drift
+    /// apart. `step` supplies per-step locks, `pitch_mult` is on top of the sound's pitch.
+    /// Empty if the sound has nothing loaded.
+    fn trigger_commands(&self, sound: &SoundSlot, step: Option<&Step>, fx: Option<u8>, pitch_mult: f32) -> Vec<AudioCommand> {
         let Some(sample_id) = sound.sample_id else {
             return vec![];
         };
 
-        let gain = sound.gain * (self.state.master_volume as f32 / 16.0);
-        let fx = self.active_rt_effect;
-        let effect_chain = self.build_effect_chain(sound, fx);
-        let (reverse, stutter_period_samples, pitch_mult, is_unison, unison_detune) =
-            Self::derive_trigger_mods_from_fx(self.state.bpm, fx);
-        let pitch = match pitch_override_mult {
-            Some(m) => sound.pitch * m * pitch_mult,
-            None => sound.pitch * pitch_mult,
-        };
+        let gain = step.and_then(|s| s.gain_lock).unwrap_or(sound.gain) * self.master_gain();
+        let pitch = step.and_then(|s| s.pitch_lock).unwrap_or(sound.pitch);
+        let mut effect_chain = self.build_effect_chain(sound, step, fx);
+        effect_chain.extend(step.into_iter().flat_map(|s| s.effects.iter().cloned()));
+
+        // Derive voice-level modifiers from the active effect
+        let (reverse, stutter_period_samples, fx_pitch_mult, is_unison, unison_detune) =
+            Self::derive_trigger_mods_from_fx(self.state.bpm, self.sample_rate, fx);
+        let reverse = reverse || sound.reverse; // fx 15 or the sound's own setting
+        let pitch = self.tuned(pitch * pitch_mult * fx_pitch_mult);
 
-        let mut cmds = vec![AudioCommand::Trigger(TriggerParams {
+        let params = TriggerParams {
             sample_id,
             trim_start: sound.trim_start,
-            length: sound.length,
+            length: sound.length_mode.frames(sound.length, self.state.bpm, self.sample_rate),
             gain,
             pitch,
-            effect_chain: effect_chain.clone(),
+            effect_chain,
             reverse,
             stutter_period_samples,
-        })];
+            hold: false,
+            interp: sound.interp,
+            envelope: Envelope {
+                attack: sound.attack * self.sample_rate,
+                decay: sound.decay * self.sample_rate,
+                sustain: sound.sustain,
+                release: sound.release * self.sample_rate,
+            },
+            loop_start: sound.loop_start,
+            loop_end: sound.loop_end,
+            pan: sound.pan,
+            choke_group: sound.choke_group,
+            cue: sound.cue,
+        };
 
-        if is_unison {
-            let detune_factor = 2.0_f32.powf(unison_detune / 1200.0);
-            cmds.push(AudioCommand::Trigger(TriggerParams {
-                sample_id,
-                trim_start: sound.trim_start,
-                length: sound.length,
-                gain,
-                pitch: pitch * detune_factor,
-                effect_chain,
-                reverse,
-                stutter_period_samples,
-            }));
+        // Unison: trigger a second voice with slight detune
+        let unison = is_unison.then(|| TriggerParams {
+            pitch: pitch * 2.0_f32.powf(unison_detune / 1200.0),
+            ..params.clone()
+        });
+        // a sustain loop without a pad to let go of (the sequencer, terminals that don't
+        // report releases) lets go when the sound is hit again
+        let mut cmds = Vec::new();
+        if sound.loop_start.is_some() {
+            cmds.push(AudioCommand::ReleaseVoice { sample_id });
         }
-
+        cmds.push(AudioCommand::Trigger(params));
+        cmds.extend(unison.map(AudioCommand::Trigger));
         cmds
     }
 
+    /// Turn an attack/decay/release time by a knob delta, within 0..=ENV_MAX_SECS.
+    /// Envelope times turn on a square-law scale, so the short end (where plucks live)
+    /// gets most of the knob.
+    fn adjust_env_secs(secs: f32, delta: f32) -> f32 {
+        let knob = (secs / ENV_MAX_SECS).sqrt();
+        (knob + delta).clamp(0.0, 1.0).powi(2) * ENV_MAX_SECS
+    }
+
+    /// Apply trim knob turns to a sound, always leaving at least MIN_TRIM_LENGTH samples
+    /// (or the whole buffer, if it's shorter) to play. Returns true if the turn hit that floor.
+    fn adjust_trim(sound: &mut SoundSlot, start_delta: f32, length_delta: f32) -> bool {
+        if sound.buffer_len == 0 {
+            return false; // nothing loaded (or still recording), nothing to trim against
+        }
+        let floor = MIN_TRIM_LENGTH.min(sound.buffer_len);
+        let mut hit = false;
+
+        if start_delta != 0.0 {
+            let max_start = sound.buffer_len - floor;
+            // Much finer: 0.2% of buffer per click (was 5%)
+            let step_size = (sound.buffer_len as f32 * start_delta.abs() * 0.04).max(1.0) as usize;
+            if start_delta > 0.0 {
+                hit = sound.trim_start + step_size > max_start;
+                sound.trim_start = (sound.trim_start + step_size).min(max_start);
+            } else {
+                sound.trim_start = sound.trim_start.saturating_sub(step_size);
+            }
+        }
+        sound.trim_start = sound.trim_start.min(sound.buffer_len - floor);
+        let remaining = sound.buffer_len - sound.trim_start;
+
+        if length_delta != 0.0 {
+            // A little finer: 1% of buffer per click (was 5%)
+            let step_size = (remaining as f32 * length_delta.abs() * 0.2).max(1.0) as usize;
+            if length_delta > 0.0 {
+                sound.length = sound.length.saturating_add(step_size);
+            } else {
+                hit = sound.length.min(remaining) < floor + step_size;
+                sound.length = sound.length.min(remaining).saturating_sub(step_size);
+            }
+        }
+        sound.length = sound.length.clamp(floor, remaining);
+        hit
+    }
+
+    /// Human-readable snapshot of the project plus the runtime-only sequencer
+    /// state that never makes it into project.json. For bug reports, not for loading.
+    pub fn dump_state(&self) -> String {
+        use std::fmt::Write;
+        let s = &self.state;
+        let mut out = String::new();
+        let _ = writeln!(out, "== sequencer ==");
+        let _ = writeln!(out, "playing: {}  write_mode: {}  hold: {}", self.playing, self.write_mode, self.hold);
+        let _ = writeln!(out, "current_step: {}  step_accumulator: {:.4}s", self.current_step, self.step_accumulator);
+        let _ = writeln!(out, "pattern: {}  playing: {}  queued: {:?}  chain: {:?} @ {}",
+            s.selected_pattern + 1, self.playing_pattern + 1, self.queued_pattern.map(|q| q + 1),
+            s.pattern_chain.iter().map(|p| p + 1).collect::<Vec<_>>(), self.chain_position);
+        let _ = writeln!(out, "rt_effect: {:?}  pending: {:?}  fx_quantize: {}",
+            self.active_rt_effect, self.pending_rt_effect, s.fx_quantize);
+        let _ = writeln!(out, "param_page: {:?}  held: {:?}", self.param_page, self.held);
+        let _ = writeln!(out, "recording_armed: {}  capturing: {}  input: {} (ok: {})",
+            self.recording_armed, self.is_capturing, self.input_device_name, self.input_ok);
+        let _ = writeln!(out, "bpm: {:.1}  swing: {}%  volume: {:.1}  tuning: A{:.1}",
+            s.bpm, self.swing_percent(), s.master_volume, s.tuning_hz);
+
+        let _ = writeln!(out, "\n== sounds ==");
+        for (i, sound) in s.sounds.iter().enumerate() {
+            if sound.sample_path.is_empty() && sound.sample_id.is_none() {
+                continue;
+            }
+            let marker = if i == s.selected_sound as usize { '>' } else { ' ' };
+            let _ = writeln!(out,
+                "{}{:<3} id {:?} len {}/{} from {}  pitch {:.3} gain {:.2} cutoff {:.0} reso {:.2}  {}",
+                marker, Self::sound_label(i as u8), sound.sample_id.map(|id| id.0),
+                sound.length, sound.buffer_len, sound.trim_start,
+                sound.pitch, sound.gain, sound.filter_cutoff, sound.filter_resonance,
+                sound.sample_path);
+        }
+
+        for (pi, pattern) in s.patterns.iter().enumerate() {
+            let used: Vec<_> = pattern.tracks.iter().enumerate()
+                .filter(|(_, t)| t.steps.iter().any(|st| st.active) || t.effect.is_some())
+                .collect();
+            if used.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "\n== pattern {} ==", pi + 1);
+            for (ti, track) in used {
+                // x = on, * = on with locks or an effect, . = off, blank = past the loop length
+                let grid: String = track.steps.iter().enumerate().map(|(i, st)| {
+                    if i >= track.length {
+                        ' '
+                    } else if !st.active {
+                        '.'
+                    } else if Self::step_has_locks(st) {
+                        '*'
+                    } else {
+                        'x'
+                    }
+                }).collect();
+                let _ = writeln!(out, " {:<3} [{}] len {} fx {:?}",
+                    Self::sound_label(ti as u8), grid, track.length, track.effect);
+                for (i, st) in track.steps.iter().enumerate() {
+                    if st.active && Self::step_has_locks(st) {
+                        let _ = writeln!(out, "      step {:>2}: {:?}", i + 1, st);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn step_has_locks(step: &Step) -> bool {
+        step.pitch_lock.is_some() || step.gain_lock.is_some() || step.effect.is_some()
+            || step.filter_cutoff_lock.is_some() || step.filter_resonance_lock.is_some()
+            || step.probability.is_some() || step.nudge != 0.0 || step.ratchet > 1
+    }
+
+    /// Where a knob's target currently sits, normalized 0.0-1.0 for display.
+    fn knob_value(&self, target: KnobTarget) -> f32 {
+        let sound = &self.state.sounds[self.state.selected_sound as usize];
+        match target {
+            // pitch: 0.5-2.0 mapped to 0.0-1.0 via log2
+            // log2(0.5)=-1 → 0.0, log2(1.0)=0 → 0.5, log2(2.0)=1 → 1.0
+            KnobTarget::Pitch => ((sound.pitch.log2() + 1.0) / 2.0).clamp(0.0, 1.0),
+            KnobTarget::Gain => sound.gain,
+            // cutoff: 20-20000 mapped to 0.0-1.0 (log scale approximation)
+            KnobTarget::Cutoff => ((sound.filter_cutoff / 20.0).ln() / (1000.0_f32).ln()).clamp(0.0, 1.0),
+            KnobTarget::Reso => sound.filter_resonance,
+            KnobTarget::TrimStart if sound.buffer_len > 0 => {
+                sound.trim_start as f32 / sound.buffer_len as f32
+            }
+            KnobTarget::TrimStart => 0.0,
+            KnobTarget::TrimLength if sound.buffer_len > 0 => {
+                sound.length as f32 / sound.buffer_len as f32
+            }
+            KnobTarget::TrimLength => 1.0,
+            KnobTarget::Attack => (sound.attack / ENV_MAX_SECS).sqrt(),
+            KnobTarget::Decay => (sound.decay / ENV_MAX_SECS).sqrt(),
+            KnobTarget::Sustain => sound.sustain,
+            KnobTarget::Release => (sound.release / ENV_MAX_SECS).sqrt(),
+            KnobTarget::Pan => (sound.pan + 1.0) / 2.0,
+            KnobTarget::Swing => self.state.swing,
+            KnobTarget::Bpm => (self.state.bpm - 60.0) / 180.0,
+            KnobTarget::Tuning => (self.state.tuning_hz - 415.0) / 51.0,
+        }
+    }
+
+    fn fx_quantized(&self) -> bool {
+        self.playing && self.state.fx_quantize > 0
+    }
+
+    /// Switch the real-time effect now. In write mode a new effect is also
+    /// recorded onto the current step of the selected track.
+    fn engage_rt_effect(&mut self, fx: Option<u8>) -> Vec<AudioCommand> {
+        self.active_rt_effect = fx;
+        if let (Some(fx_num), true) = (fx, self.write_mode && !self.held.preview) {
+            // onto the step that's playing, so it comes back where it was heard
+            let pi = self.playing_pattern as usize;
+            let sound_idx = self.state.selected_sound as usize;
+            let track = &mut self.state.patterns[pi].tracks[sound_idx];
+            let si = track.wrap(self.pattern_steps);
+            track.steps[si].effect = Some(fx_num);
+        }
+        // Kill old effect voices (stutter/loop) before the new one takes over
+        vec![AudioCommand::StopAllVoices]
+    }
+
+    // claim the next empty slot after the selected one and have the engine fill it with
+    // one bar of output; it comes back through on_recording_complete like a recording
+    fn start_grab(&mut self) -> Option<AudioCommand> {
+        let from = self.state.selected_sound as usize + 1;
+        let Some(slot) = (0..NUM_SLOTS)
+            .map(|i| (from + i) % NUM_SLOTS)
+            .find(|&s| !self.state.sounds[s].is_loaded())
+        else {
+            self.notice = Some(String::from("GRAB: NO FREE SLOT"));
+            return None;
+        };
+        let sample_id = next_sample_id();
+        let sound = &mut self.state.sounds[slot];
+        sound.sample_id = Some(sample_id);
+        sound.sample_path = "(grab)".into();
+        sound.trim_start = 0;
+        sound.buffer_len = 0;
+        sound.length = usize::MAX; // set properly once the grab lands
+        self.notice = Some(format!("GRAB -> {}", Self::sound_label(slot as u8)));
+        // a plain bar at the tempo; secs_per_step would stretch or squeeze it under fx 13
+        let secs = 60.0 / (self.state.bpm as f64 * 4.0) * STEPS_PER_PATTERN as f64;
+        let frames = (secs * self.sample_rate as f64) as usize;
+        // reserved here, off the audio thread, like the jam recording's buffer
+        Some(AudioCommand::GrabOutput { sample_id, buffer: Vec::with_capacity(frames) })
+    }
+
+    fn snapshot_pattern(&mut self, pattern: usize) {
+        self.push_undo(Undo::Pattern(pattern, Box::new(self.state.patterns[pattern].clone())));
+    }
+
+    fn push_undo(&mut self, entry: Undo) {
+        if self.undo.len() == UNDO_DEPTH {
+            self.undo.remove(0);
+        }
+        self.undo.push(entry);
+    }
+
+    /// Put `entry` back and return what it replaced, for the opposite stack. A restored
+    /// sound reloads its sample from disk; the commands for that land in `cmds`.
+    fn restore(&mut self, entry: Undo, cmds: &mut Vec<AudioCommand>) -> Undo {
+        match entry {
+            Undo::Pattern(pi, pattern) => {
+                Undo::Pattern(pi, Box::new(std::mem::replace(&mut self.state.patterns[pi], *pattern)))
+            }
+            Undo::AllPatterns(patterns) => {
+                Undo::AllPatterns(Box::new(std::mem::replace(&mut self.state.patterns, *patterns)))
+            }
+            Undo::Sound(slot, sound) => {
+                let mut current = std::mem::replace(&mut self.state.sounds[slot as usize], *sound);
+                cmds.extend(current.sample_id.take().map(|id| AudioCommand::UnregisterSample { id }));
+                let path = PathBuf::from(&self.state.sounds[slot as usize].sample_path);
+                if !path.as_os_str().is_empty() {
+                    match self.load_sample_into_slot(slot, &path, self.sample_rate as u32) {
+                        Ok(load) => cmds.extend(load),
+                        Err(_) => self.notice = Some(String::from("SAMPLE MISSING")),
+                    }
+                }
+                Undo::Sound(slot, Box::new(current))
+            }
+        }
+    }
+
+    /// Whether an input can change anything that gets saved. Errs on the side of
+    /// yes: a spurious unsaved dot is harmless, a missing one isn't.
+    fn edits_project(event: &InputEvent) -> bool {
+        !matches!(
+            event,
+            InputEvent::SoundDown
+                | InputEvent::SoundUp
+                | InputEvent::PatternDown
+                | InputEvent::WriteUp
+                | InputEvent::FxDown
+                | InputEvent::BpmDown
+                | InputEvent::BpmUp
+                | InputEvent::PreviewDown
+                | InputEvent::PreviewUp
+                | InputEvent::PlayPress
+                | InputEvent::TriggerPad(_)
+                | InputEvent::ReleasePad(_)
+                | InputEvent::ToggleNormalizeLoads
+                | InputEvent::PadVelocity(_)
+                | InputEvent::SyncTempo(_)
+                | InputEvent::ToggleHold
+                | InputEvent::ToggleLoopRoll
+                | InputEvent::ClearRealtimeEffect
+                | InputEvent::SettingsMove(_)
+                | InputEvent::SettingsConfirm
+                | InputEvent::ToggleSettings
+                | InputEvent::CycleInputDevice
+                | InputEvent::ToggleBrowser
+                | InputEvent::ToggleMetronome
+                | InputEvent::CopyPattern
+                | InputEvent::BrowserMove(_)
+                | InputEvent::BouncePattern
+                | InputEvent::BounceSong
+                | InputEvent::ToggleMasterCapture
+                | InputEvent::ExportMidi
+                | InputEvent::ImportMidi
+                | InputEvent::DumpState
+                | InputEvent::ClearAllPatterns
+                | InputEvent::ConfirmClearAll(false)
+                | InputEvent::Save
+                | InputEvent::SwitchProjectSlot(_)
+                | InputEvent::Quit
+        )
+    }
+
+    /// Bank letter + pad number, e.g. "B5" for the fifth pad of the second bank.
+    fn sound_label(slot: u8) -> String {
+        let bank = (b'A' + slot / NUM_SOUNDS as u8) as char;
+        format!("{}{}", bank, slot % NUM_SOUNDS as u8 + 1)
+    }
+
+    fn swing_percent(&self) -> u8 {
+        (self.state.swing * SWING_DISPLAY_MAX).round() as u8
+    }
+
+    /// Master volume as linear gain: unity at 16, a fixed dB drop per step below it.
+    fn master_gain(&self) -> f32 {
+        let db = (self.state.master_volume.clamp(1.0, 16.0) - 16.0) * VOLUME_DB_PER_STEP;
+        10.0_f32.powf(db / 20.0)
+    }
+
+    /// Duck every loaded sidechain target; sent whenever the source sound is sequenced.
+    fn duck_commands(&self) -> Vec<AudioCommand> {
+        let sc = &self.state.sidechain;
+        sc.targets.iter()
+            .filter_map(|&t| self.state.sounds.get(t as usize)?.sample_id)
+            .map(|target| AudioCommand::Duck {
+                target,
+                amount: sc.amount.clamp(0.0, 1.0),
+                release_ms: sc.release_ms,
+            })
+            .collect()
+    }
+
+    /// Scale a playback rate by the master tuning, keeping it inside the voice range.
+    fn tuned(&self, pitch: f32) -> f32 {
+        let (lo, hi) = VOICE_PITCH_RANGE;
+        (pitch * self.state.tuning_hz / REFERENCE_TUNING_HZ).clamp(lo, hi)
+    }
+
     fn trigger_sound(&self, slot: u8) -> Vec<AudioCommand> {
         self.trigger_sound_with_pitch(slot, None)
     }
 
-    fn build_effect_chain(&self, _sound: &SoundSlot, _fx: Option<u8>) -> Vec<EffectSpec> {
-        // PO-33 effects are all handled via voice params (stutter, pitch, reverse)
-        // or sequencer logic (retrigger, 6/8 quantize). None use the sample-domain
-        // effect chain. Keeping this for future custom effects.
+    // A held drone of the selected sound hears filter moves live instead of on its next trigger
+    fn sustained_filter(&self) -> Vec<AudioCommand> {
+        let sound = &self.state.sounds[self.state.selected_sound as usize];
+        match self.held_voice {
+            Some(sample_id) if sound.sample_id == Some(sample_id) => vec![AudioCommand::SetVoiceFilter {
+                sample_id,
+                cutoff_hz: sound.filter_cutoff,
+                resonance: sound.filter_resonance,
+            }],
+            _ => vec![],
+        }
+    }
+
+    fn build_effect_chain(&self, sound: &SoundSlot, step: Option<&Step>, fx: Option<u8>) -> Vec<EffectSpec> {
+        // Per-sound inserts; a step's filter locks win over the sound's own setting
+        let mut chain = Vec::new();
+        let cutoff_hz = step.and_then(|s| s.filter_cutoff_lock).unwrap_or(sound.filter_cutoff);
+        let resonance = step.and_then(|s| s.filter_resonance_lock).unwrap_or(sound.filter_resonance);
+        if cutoff_hz < 20000.0 {
+            chain.push(EffectSpec::LowPass { cutoff_hz, resonance });
+        }
+        if sound.compress {
+            chain.push(EffectSpec::Compressor {
+                threshold_db: -18.0,
+                ratio: 4.0,
+                attack_ms: 5.0,
+                release_ms: 80.0,
+            });
+        }
+
+        // fx 12 is our one sample-domain effect: thins the sound out from the bottom
+        if fx == Some(12) {
+            chain.push(EffectSpec::HighPass { cutoff_hz: HIGH_PASS_FX_HZ, resonance: 0.1 });
+        }
+
+        // The rest of the PO-33 effects are handled via voice params (stutter, pitch,
+        // reverse) or sequencer logic (retrigger, 6/8 quantize).
         //
-        // PO-33 effect map (all handled outside the chain):
+        // PO-33 effect map (all but 12 handled outside the chain):
         //   1: loop 16       → stutter (1 beat)
         //   2: loop 12       → stutter (triplet)
         //   3: loop short    → stutter (1/2 step)
@@ -809,49 +2275,49 @@ impl Middle {
         //   9: stutter 4     → stutter (1 step)
         //   10: stutter 3    → stutter (triplet step)
         //   11: scratch       → knob A sends SetPlaybackPosition
-        //   12: scratch fast  → knob A sends SetPlaybackPosition (fine)
+        //   12: high-pass     → HighPass insert (took the unimplemented scratch fast slot)
         //   13: 6/8 quantize  → tick() adjusts step timing
         //   14: retrigger     → advance_step resets current_step
         //   15: reverse       → reverse flag on voice
-        vec![]
+        chain
     }
 
     /// Derive voice-level modifiers (reverse, stutter, pitch) from an effect number.
     /// These are NOT in the effect chain — they change how the Voice reads the buffer.
     /// Returns (reverse, stutter_period_samples, pitch_mult, is_unison, unison_detune).
-    fn derive_trigger_mods_from_fx(bpm: f32, fx: Option<u8>) -> (bool, Option<u32>, f32, bool, f32) {
+    fn derive_trigger_mods_from_fx(bpm: f32, sample_rate: f32, fx: Option<u8>) -> (bool, Option<u32>, f32, bool, f32) {
         let reverse = fx == Some(15);
 
         let stutter_period_samples = match fx {
             Some(1) => {
                 // loop 16: 1 whole beat
                 let secs = 60.0 / bpm;
-                Some((secs * SAMPLE_RATE) as u32)
+                Some((secs * sample_rate) as u32)
             }
             Some(2) => {
                 // loop 12: triplet beat (1/3 of a bar = 1 beat in 3/4)
                 let secs = 60.0 / (bpm * 3.0 / 2.0);
-                Some((secs * SAMPLE_RATE) as u32)
+                Some((secs * sample_rate) as u32)
             }
             Some(3) => {
                 // loop short: 1/2 step
                 let secs = 60.0 / (bpm * 8.0);
-                Some((secs * SAMPLE_RATE) as u32)
+                Some((secs * sample_rate) as u32)
             }
             Some(4) => {
                 // loop shorter: 1/4 step
                 let secs = 60.0 / (bpm * 16.0);
-                Some((secs * SAMPLE_RATE) as u32)
+                Some((secs * sample_rate) as u32)
             }
             Some(9) => {
                 // stutter 4: 1 step (1/16 note)
                 let secs = 60.0 / (bpm * 4.0);
-                Some((secs * SAMPLE_RATE) as u32)
+                Some((secs * sample_rate) as u32)
             }
             Some(10) => {
                 // stutter 3: triplet step (1/12 note)
                 let secs = 60.0 / (bpm * 12.0);
-                Some((secs * SAMPLE_RATE) as u32)
+                Some((secs * sample_rate) as u32)
             }
             _ => None,
         };
@@ -881,14 +2347,361 @@ impl Middle {
     }
 
     // live recording quantization attempt
+    // Position (in pattern steps, see Track::wrap) of the step nearest to now
     fn quantize_to_nearest_step(&self) -> usize {
         let secs_per_step = 60.0 / (self.state.bpm as f64 * 4.0);
         let fraction = self.step_accumulator / secs_per_step;
 
         if fraction >= 0.5 {
-            ((self.current_step as usize + 1) % STEPS_PER_PATTERN)
+            self.pattern_steps.wrapping_add(1)
         } else {
-            self.current_step as usize
+            self.pattern_steps
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a Middle with something "loaded" in the first pad (the engine isn't involved,
+    // so an id is all a sound needs to trigger)
+    fn loaded_middle() -> Middle {
+        let mut m = Middle::new();
+        m.state.sounds[0].sample_id = Some(next_sample_id());
+        m.state.sounds[0].length = 48000;
+        m.state.sounds[0].buffer_len = 48000;
+        m
+    }
+
+    fn triggers(cmds: &[AudioCommand]) -> Vec<&TriggerParams> {
+        cmds.iter()
+            .filter_map(|c| match c {
+                AudioCommand::Trigger(p) => Some(p),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn filter_locks_reach_the_effect_chain() {
+        let m = loaded_middle();
+        let step = Step { active: true, filter_cutoff_lock: Some(300.0), filter_resonance_lock: Some(0.4), ..Default::default() };
+        let cmds = m.trigger_commands(&m.state.sounds[0], Some(&step), None, 1.0);
+        let chain = &triggers(&cmds)[0].effect_chain;
+        assert!(matches!(chain.first(), Some(EffectSpec::LowPass { cutoff_hz, resonance }) if *cutoff_hz == 300.0 && *resonance == 0.4));
+    }
+
+    #[test]
+    fn browser_load_keeps_the_slot_on_failure_and_marks_dirty_on_success() {
+        let dir = std::env::temp_dir().join(format!("pocketty-browser-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.wav");
+        SampleBuffer::from_frames(vec![StereoFrame { left: 0.5, right: 0.5 }; 100]).save_wav(&good, 48000).unwrap();
+        let bad = dir.join("bad.wav");
+        std::fs::write(&bad, b"not a wav").unwrap();
+
+        let mut m = Middle::new();
+        m.set_sample_rate(48000);
+        m.state.sounds[0].sample_path = String::from("kick.wav");
+        m.open_browser(SampleBrowser::new(vec![bad, good.clone()], &dir));
+        assert!(m.load_browser_selection(48000).is_err());
+        assert_eq!(m.state.sounds[0].sample_path, "kick.wav");
+        assert_eq!(m.notice.as_deref(), Some("LOAD ERR"));
+        assert!(!m.is_dirty());
+
+        m.browser.as_mut().unwrap().move_cursor(1);
+        assert!(m.load_browser_selection(48000).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(m.state.sounds[0].sample_path, good.to_string_lossy());
+        assert!(m.is_dirty());
+    }
+
+    #[test]
+    fn midi_export_reports_its_outcome() {
+        let mut middle = Middle::with_state(ProjectState::default());
+        middle.on_midi_exported(Ok(()));
+        assert_eq!(middle.notice.as_deref(), Some("MIDI OUT"));
+        middle.on_midi_exported(Err(anyhow::anyhow!("disk full")));
+        assert_eq!(middle.notice.as_deref(), Some("MIDI ERR"));
+    }
+
+    #[test]
+    fn swing_delays_off_beats_by_its_share_of_half_a_step() {
+        let mut m = loaded_middle();
+        m.state.bpm = 120.0;
+        m.state.swing = 0.5;
+        for step in &mut m.state.patterns[0].tracks[0].steps[..4] {
+            step.active = true;
+        }
+        m.handle_input(InputEvent::PlayPress);
+
+        // fixed ticks through the first four steps, noting when each hit goes out
+        let step_secs = 60.0 / (120.0 * 4.0);
+        let dt = 0.0005;
+        let mut onsets = Vec::new();
+        for i in 1..=(5.0 * step_secs / dt) as usize {
+            if !triggers(&m.tick(dt)).is_empty() {
+                onsets.push(i as f64 * dt / step_secs);
+            }
+        }
+        let onsets: Vec<f64> = onsets.iter().map(|t| t - onsets[0]).collect();
+
+        // swing 0.5 puts the off-beats a quarter step late; the on-beats stay on the grid
+        assert_eq!(onsets.len(), 4);
+        for (got, want) in onsets.iter().zip([0.0, 1.25, 2.0, 3.25]) {
+            assert!((got - want).abs() < 0.01, "onsets {:?}", onsets);
+        }
+    }
+
+    #[test]
+    fn edits_mark_the_project_dirty_until_saved() {
+        let mut m = Middle::new();
+        // moving around, auditioning and transport don't touch the project
+        for event in [InputEvent::SoundDown, InputEvent::SoundUp, InputEvent::TriggerPad(3), InputEvent::PlayPress] {
+            m.handle_input(event);
+        }
+        assert!(!m.is_dirty());
+
+        m.handle_input(InputEvent::ToggleStep(0));
+        assert!(m.is_dirty());
+        m.mark_saved();
+        assert!(!m.is_dirty());
+    }
+
+    #[test]
+    fn a_background_save_only_covers_the_edits_in_its_snapshot() {
+        let mut m = Middle::new();
+        m.handle_input(InputEvent::ToggleStep(0));
+        let snapshot = m.edit_count();
+        m.handle_input(InputEvent::ToggleStep(4)); // lands while the save is being written
+
+        m.mark_saved_at(snapshot);
+        assert!(m.is_dirty());
+        m.mark_saved_at(m.edit_count());
+        assert!(!m.is_dirty());
+        m.mark_saved_at(snapshot); // an older save landing late doesn't undo that
+        assert!(!m.is_dirty());
+    }
+
+    #[test]
+    fn undo_brings_a_cleared_track_back_and_redo_clears_it_again() {
+        let mut m = Middle::new();
+        m.handle_input(InputEvent::ToggleStep(0));
+        m.handle_input(InputEvent::ToggleStep(4));
+        let steps = |m: &Middle| m.state.patterns[0].tracks[0].steps.iter().filter(|s| s.active).count();
+
+        m.handle_input(InputEvent::ClearTrack);
+        assert_eq!(steps(&m), 0);
+        m.handle_input(InputEvent::Undo);
+        assert_eq!(steps(&m), 2);
+        m.handle_input(InputEvent::Redo);
+        assert_eq!(steps(&m), 0);
+
+        // a fresh edit after an undo drops what could have been redone
+        m.handle_input(InputEvent::Undo);
+        m.handle_input(InputEvent::ToggleStep(8));
+        m.handle_input(InputEvent::Redo);
+        assert_eq!(steps(&m), 3);
+        assert_eq!(m.notice.as_deref(), Some("NOTHING TO REDO"));
+    }
+
+    #[test]
+    fn undo_history_is_capped() {
+        let mut m = Middle::new();
+        for _ in 0..UNDO_DEPTH + 8 {
+            m.handle_input(InputEvent::ToggleStep(0));
+        }
+        assert_eq!(m.undo.len(), UNDO_DEPTH);
+        for _ in 0..UNDO_DEPTH {
+            m.handle_input(InputEvent::Undo);
+        }
+        assert_eq!(m.notice.as_deref(), Some("UNDONE"));
+        m.handle_input(InputEvent::Undo);
+        assert_eq!(m.notice.as_deref(), Some("NOTHING TO UNDO"));
+    }
+
+    #[test]
+    fn ratchets_fire_n_locked_hits_per_step() {
+        for n in 1..=MAX_RATCHET {
+            let mut m = loaded_middle();
+            m.state.bpm = 120.0;
+            let step = &mut m.state.patterns[0].tracks[0].steps[0];
+            *step = Step { active: true, ratchet: n, pitch_lock: Some(2.0), gain_lock: Some(0.5), ..Default::default() };
+            m.handle_input(InputEvent::PlayPress);
+
+            // step 0 goes out after one step's time; stop just short of step 1
+            let mut hits = Vec::new();
+            for _ in 0..490 {
+                hits.extend(triggers(&m.tick(0.0005)).into_iter().cloned());
+            }
+            assert_eq!(hits.len(), n as usize, "ratchet {}", n);
+            for hit in &hits {
+                assert_eq!(hit.pitch, 2.0);
+                assert_eq!(hit.gain, 0.5 * m.master_gain());
+            }
+        }
+    }
+
+    #[test]
+    fn a_stalled_tick_fires_at_most_the_catchup_cap() {
+        let mut m = loaded_middle();
+        m.state.bpm = 120.0;
+        for step in &mut m.state.patterns[0].tracks[0].steps {
+            step.active = true;
+        }
+        m.handle_input(InputEvent::PlayPress);
+
+        // ten seconds is 80 steps at 120 bpm: one tick fires the cap's worth, not a burst
+        let hits = triggers(&m.tick(10.0)).len();
+        assert_eq!(hits, MAX_CATCHUP_STEPS as usize);
+        assert!(m.step_accumulator < m.secs_per_step());
+    }
+
+    #[test]
+    fn stutter_sync_resyncs_on_every_step_only_when_on() {
+        for sync in [false, true] {
+            let mut m = loaded_middle();
+            m.state.bpm = 120.0;
+            m.state.stutter_sync = sync;
+            m.handle_input(InputEvent::PlayPress);
+            m.active_rt_effect = Some(9);
+
+            let mut resyncs = 0;
+            for _ in 0..4 {
+                resyncs += m.tick(m.secs_per_step()).iter()
+                    .filter(|c| matches!(c, AudioCommand::SyncStutter))
+                    .count();
+            }
+            assert_eq!(resyncs, if sync { 4 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn schedule_pattern_places_hits_with_swing_nudge_ratchets_and_locks() {
+        let mut m = loaded_middle();
+        m.state.swing = 0.5;
+        let steps = &mut m.state.patterns[0].tracks[0].steps;
+        steps[0].active = true;
+        steps[1].active = true; // off-beat: swung a quarter step late
+        steps[4] = Step { active: true, pitch_lock: Some(2.0), ..Default::default() };
+        steps[8] = Step { active: true, nudge: -0.25, ..Default::default() };
+        steps[12] = Step { active: true, ratchet: 2, ..Default::default() };
+
+        // 120 bpm at 48k: 6000 frames a step
+        let events = m.schedule_pattern(0, 120.0, 48000);
+        let offsets: Vec<usize> = events.iter().map(|(at, _)| *at).collect();
+        assert_eq!(offsets, [0, 7500, 24000, 46500, 72000, 75000]);
+        let pitches: Vec<f32> = events.iter()
+            .filter_map(|(_, c)| match c { AudioCommand::Trigger(p) => Some(p.pitch), _ => None })
+            .collect();
+        assert_eq!(pitches, [1.0, 1.0, 2.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn pasted_patterns_are_independent_copies() {
+        let mut m = Middle::new();
+        m.state.patterns[0].tracks[0].steps[3] = Step { active: true, pitch_lock: Some(1.5), ..Default::default() };
+        m.handle_input(InputEvent::CopyPattern);
+        m.state.selected_pattern = 1;
+        m.handle_input(InputEvent::PastePattern);
+        assert_eq!(m.state.patterns[1].tracks[0].steps[3].pitch_lock, Some(1.5));
+
+        // editing the copy leaves the source, and the clipboard, as they were
+        m.state.patterns[1].tracks[0].steps[3].pitch_lock = Some(0.5);
+        m.handle_input(InputEvent::ToggleStep(7));
+        assert_eq!(m.state.patterns[0].tracks[0].steps[3].pitch_lock, Some(1.5));
+        assert!(!m.state.patterns[0].tracks[0].steps[7].active);
+        m.state.selected_pattern = 2;
+        m.handle_input(InputEvent::PastePattern);
+        assert_eq!(m.state.patterns[2].tracks[0].steps[3].pitch_lock, Some(1.5));
+        assert!(!m.state.patterns[2].tracks[0].steps[7].active);
+    }
+
+    #[test]
+    fn probability_steps_fire_about_that_often_and_repeatably() {
+        // for each step of 200 bars, whether it made a hit (only the first step can)
+        let bars_fired = |chance: f32| {
+            let mut m = loaded_middle();
+            m.state.patterns[0].tracks[0].steps[0] = Step { active: true, probability: Some(chance), ..Default::default() };
+            m.handle_input(InputEvent::PlayPress);
+            (0..200 * STEPS_PER_PATTERN)
+                .map(|_| !triggers(&m.tick(m.secs_per_step())).is_empty())
+                .collect::<Vec<_>>()
+        };
+
+        let fired = bars_fired(0.6);
+        let hits = fired.iter().filter(|&&f| f).count();
+        assert!((100..140).contains(&hits), "{} of 200", hits);
+        assert_eq!(fired, bars_fired(0.6)); // same seed, same rolls
+        assert!(!bars_fired(0.0).contains(&true));
+    }
+
+    #[test]
+    fn stutter_period_follows_the_device_rate() {
+        let mut m = loaded_middle();
+        m.set_sample_rate(48000);
+        m.state.bpm = 120.0;
+        // a 1/16 at 120 bpm is 125ms: 6000 frames at 48k, not the 5512 a 44.1k guess gives
+        let cmds = m.trigger_commands(&m.state.sounds[0], None, Some(9), 1.0);
+        assert_eq!(triggers(&cmds)[0].stutter_period_samples, Some(6000));
+    }
+
+    #[test]
+    fn reloading_a_sample_that_shrank_to_nothing_keeps_a_valid_region() {
+        let dir = std::env::temp_dir().join(format!("pocketty-emptied-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("emptied.wav");
+        let spec = hound::WavSpec { channels: 1, sample_rate: 48000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        hound::WavWriter::create(&path, spec).unwrap().finalize().unwrap();
+
+        let mut m = Middle::new();
+        let sound = &mut m.state.sounds[0];
+        sound.sample_path = path.to_string_lossy().into_owned();
+        (sound.buffer_len, sound.trim_start, sound.length) = (48000, 1000, 20000);
+        m.reload_samples();
+
+        let sound = &m.state.sounds[0];
+        assert_eq!(sound.buffer_len, 0);
+        assert_eq!((sound.trim_start, sound.length), (0, 1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_grab_is_one_plain_bar_even_under_the_triplet_fx() {
+        let mut m = Middle::new();
+        m.state.bpm = 120.0;
+        m.active_rt_effect = Some(13);
+        m.current_step = 1; // a short triplet step
+        let Some(AudioCommand::GrabOutput { buffer, .. }) = m.start_grab() else { panic!("no grab") };
+        // 16 sixteenths at 120 bpm is two seconds
+        assert_eq!(buffer.capacity(), (2.0 * m.sample_rate) as usize);
+    }
+
+    #[test]
+    fn a_written_fx_lands_on_the_playing_step_of_the_playing_pattern() {
+        let mut m = Middle::new();
+        m.write_mode = true;
+        m.state.patterns[0].tracks[0].length = 4;
+        m.state.selected_pattern = 1; // editing another pattern while pattern 1 plays
+        (m.current_step, m.pattern_steps) = (6, 6);
+
+        m.handle_input(InputEvent::SetRealtimeEffect(3));
+        // step 6 of a 4-step track is its step 2
+        assert_eq!(m.state.patterns[0].tracks[0].steps[2].effect, Some(3));
+        assert!(m.state.patterns[1].tracks[0].steps.iter().all(|s| s.effect.is_none()));
+    }
+
+    #[test]
+    fn the_lcd_shows_when_monitoring_is_held_off() {
+        let mut m = Middle::new();
+        m.set_monitor_guarded(true);
+        assert!(!m.display_state().monitor_held); // nothing held while monitoring is off
+
+        m.handle_input(InputEvent::ToggleInputMonitor);
+        assert_eq!(m.display_state().display_text, "MONITOR HELD: SAME DEVICE");
+        assert!(m.display_state().monitor_held);
+        // overridden (or a device switch): it plays
+        m.set_monitor_guarded(false);
+        assert!(!m.display_state().monitor_held);
+    }
+}
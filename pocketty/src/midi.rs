@@ -0,0 +1,112 @@
+// MIDI controller input (the `midi` feature). Notes are pad presses handled the way
+// osc.rs describes; velocity rides along and scales the hit. Start/continue/stop drive
+// the transport and incoming clock sets the tempo.
+//
+//   notes      36 (C1) is the bottom-left pad, rows go up; other octaves fold onto the grid.
+//              note-offs let go of the pad (sustain loops)
+//   0xFA/0xFB  start / continue: play from the top if stopped
+//   0xFC       stop
+//   0xF8       clock, 24 per beat; the tempo follows once per beat
+
+use crossbeam_channel::{Receiver, unbounded};
+use midir::{Ignore, MidiInput as Backend, MidiInputConnection};
+use crate::shared::NUM_PADS;
+
+const CLIENT_NAME: &str = "pocketty";
+const FIRST_PAD_NOTE: i32 = 36;
+const CLOCKS_PER_BEAT: u32 = 24;
+const TEMPO_JITTER_BPM: f32 = 0.5; // smaller clock wobble than this doesn't move the tempo
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiInput {
+    Pad { pad: u8, velocity: f32 }, // 0-based pad, velocity 0.0-1.0
+    PadUp { pad: u8 },
+    Start,
+    Stop,
+    Tempo(f32),
+}
+
+/// An open input port. Messages keep arriving for as long as this is alive.
+pub struct MidiListener {
+    pub rx: Receiver<MidiInput>,
+    _connection: MidiInputConnection<ClockState>,
+}
+
+/// Names of the MIDI input ports, in the order `spawn_listener` picks from.
+pub fn list_midi_inputs() -> Vec<String> {
+    let Ok(backend) = Backend::new(CLIENT_NAME) else { return Vec::new(); };
+    backend.ports().iter().filter_map(|p| backend.port_name(p).ok()).collect()
+}
+
+/// Listen on the first input port whose name contains `name`, or the first port
+/// there is when `name` is None.
+pub fn spawn_listener(name: Option<&str>) -> anyhow::Result<MidiListener> {
+    let mut backend = Backend::new(CLIENT_NAME)?;
+    backend.ignore(Ignore::SysexAndActiveSense); // keep clock
+    let index = list_midi_inputs()
+        .iter()
+        .position(|p| name.is_none_or(|n| p.contains(n)))
+        .ok_or_else(|| anyhow::anyhow!("no MIDI input{}", name.map_or(String::new(), |n| format!(" matching '{}'", n))))?;
+    let ports = backend.ports();
+    let port = ports.get(index).ok_or_else(|| anyhow::anyhow!("MIDI port went away"))?;
+
+    let (tx, rx) = unbounded();
+    let connection = backend
+        .connect(
+            port,
+            "pocketty-in",
+            move |stamp, message, clock| {
+                if let Some(input) = decode(message, stamp, clock) {
+                    let _ = tx.send(input);
+                }
+            },
+            ClockState::default(),
+        )
+        .map_err(|e| anyhow::anyhow!("couldn't open MIDI input: {}", e.kind()))?;
+    Ok(MidiListener { rx, _connection: connection })
+}
+
+#[derive(Default)]
+struct ClockState {
+    ticks: u32,             // clocks since the last beat
+    beat_start: Option<u64>, // timestamp (µs) of the last beat
+    bpm: f32,               // last tempo sent
+}
+
+fn key_to_pad(key: u8) -> u8 {
+    let offset = (key as i32 - FIRST_PAD_NOTE).rem_euclid(NUM_PADS as i32) as usize;
+    let (row_from_bottom, col) = (offset / 4, offset % 4);
+    ((3 - row_from_bottom) * 4 + col) as u8
+}
+
+fn decode(message: &[u8], stamp: u64, clock: &mut ClockState) -> Option<MidiInput> {
+    match *message {
+        [status, key, vel] if status & 0xF0 == 0x90 && vel > 0 => {
+            Some(MidiInput::Pad { pad: key_to_pad(key), velocity: vel as f32 / 127.0 })
+        }
+        // note on at velocity 0 is a note off too
+        [status, key, _] if status & 0xF0 == 0x80 || status & 0xF0 == 0x90 => {
+            Some(MidiInput::PadUp { pad: key_to_pad(key) })
+        }
+        [0xFA] | [0xFB] => {
+            *clock = ClockState { bpm: clock.bpm, ..Default::default() };
+            Some(MidiInput::Start)
+        }
+        [0xFC] => Some(MidiInput::Stop),
+        [0xF8] => {
+            clock.ticks += 1;
+            if clock.beat_start.is_some() && clock.ticks < CLOCKS_PER_BEAT {
+                return None;
+            }
+            let beat = clock.beat_start.replace(stamp).map(|start| stamp.saturating_sub(start));
+            clock.ticks = 0;
+            let bpm = (60_000_000.0 / beat.filter(|&us| us > 0)? as f64) as f32;
+            if (bpm - clock.bpm).abs() < TEMPO_JITTER_BPM {
+                return None;
+            }
+            clock.bpm = bpm;
+            Some(MidiInput::Tempo(bpm))
+        }
+        _ => None,
+    }
+}
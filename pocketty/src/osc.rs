@@ -0,0 +1,175 @@
+// Optional OSC listener (TouchOSC, Processing, ...). Messages are turned into the same
+// pad/knob presses the keyboard makes and resolved against the TUI's modifier state, so
+// a networked pad behaves exactly like pressing its key. Decoding is hand-written; the
+// subset we need (address + first int/float/bool arg, bundles) is tiny.
+//
+//   /pad/N     N = 1..16, triggers like the pad key; a 0 argument (button release) is ignored
+//   /knob/a x  turn knob A by x (the keys turn by 0.05)
+//   /knob/b x  turn knob B by x
+//   /play      PlayPress; a 0 argument is ignored
+
+use std::net::UdpSocket;
+use crossbeam_channel::{Receiver, unbounded};
+use crate::shared::NUM_PADS;
+
+const MAX_KNOB_DELTA: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OscInput {
+    Pad(u8), // 0-based
+    KnobA(f32),
+    KnobB(f32),
+    Play,
+}
+
+/// Listen for OSC on `port` (all interfaces) in a background thread.
+pub fn spawn_listener(port: u16) -> anyhow::Result<Receiver<OscInput>> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    let (tx, rx) = unbounded();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1536];
+        let mut inputs = Vec::new();
+        while let Ok(len) = socket.recv(&mut buf) {
+            decode_packet(&buf[..len], &mut inputs);
+            for input in inputs.drain(..) {
+                if tx.send(input).is_err() {
+                    return; // main loop is gone
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+fn decode_packet(packet: &[u8], out: &mut Vec<OscInput>) {
+    if let Some(mut rest) = packet.strip_prefix(b"#bundle\0") {
+        rest = rest.get(8..).unwrap_or_default(); // time tag; we play everything now
+        while let Some(size) = rest.get(..4) {
+            let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+            // no 4 + size: a bogus size mustn't overflow where usize is 32 bits
+            let Some(element) = rest[4..].get(..size) else { return; };
+            decode_packet(element, out);
+            rest = &rest[4 + size..];
+        }
+        return;
+    }
+    let Some((address, rest)) = read_str(packet) else { return; };
+    let value = read_str(rest).and_then(|(tags, args)| first_arg(tags, args));
+
+    let input = match address {
+        "/knob/a" => value.map(|v| OscInput::KnobA(v.clamp(-MAX_KNOB_DELTA, MAX_KNOB_DELTA))),
+        "/knob/b" => value.map(|v| OscInput::KnobB(v.clamp(-MAX_KNOB_DELTA, MAX_KNOB_DELTA))),
+        "/play" if value != Some(0.0) => Some(OscInput::Play),
+        _ => address
+            .strip_prefix("/pad/")
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|n| (1..=NUM_PADS).contains(n) && value != Some(0.0))
+            .map(|n| OscInput::Pad(n as u8 - 1)),
+    };
+    out.extend(input);
+}
+
+// OSC strings are nul-terminated and padded to a multiple of 4 bytes
+fn read_str(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.iter().position(|&b| b == 0)?;
+    let s = std::str::from_utf8(&data[..end]).ok()?;
+    let next = (end + 4) & !3;
+    Some((s, data.get(next..).unwrap_or_default()))
+}
+
+fn first_arg(tags: &str, args: &[u8]) -> Option<f32> {
+    let word = || args.get(..4).map(|b| [b[0], b[1], b[2], b[3]]);
+    match tags.strip_prefix(',')?.chars().next()? {
+        'i' => Some(i32::from_be_bytes(word()?) as f32),
+        'f' => Some(f32::from_be_bytes(word()?)),
+        'T' => Some(1.0),
+        'F' => Some(0.0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(s: &str) -> Vec<u8> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize((s.len() + 4) & !3, 0);
+        bytes
+    }
+
+    // address, type tags and big-endian arg words, as a sender lays them out
+    fn message(address: &str, tags: &str, args: &[[u8; 4]]) -> Vec<u8> {
+        let mut packet = padded(address);
+        packet.extend(padded(tags));
+        args.iter().for_each(|a| packet.extend(a));
+        packet
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = padded("#bundle");
+        packet.extend([0, 0, 0, 0, 0, 0, 0, 1]); // time tag "now"
+        for element in elements {
+            packet.extend((element.len() as u32).to_be_bytes());
+            packet.extend(element);
+        }
+        packet
+    }
+
+    fn decode(packet: &[u8]) -> Vec<OscInput> {
+        let mut out = Vec::new();
+        decode_packet(packet, &mut out);
+        out
+    }
+
+    #[test]
+    fn pads_are_one_based_and_out_of_range_ones_are_ignored() {
+        assert_eq!(decode(&message("/pad/1", ",", &[])), [OscInput::Pad(0)]);
+        assert_eq!(decode(&message("/pad/16", ",i", &[1i32.to_be_bytes()])), [OscInput::Pad(15)]);
+        for address in ["/pad/0", "/pad/17", "/pad/-1", "/pad/x", "/pad/"] {
+            assert!(decode(&message(address, ",", &[])).is_empty(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn a_zero_argument_is_a_release_and_does_nothing() {
+        assert!(decode(&message("/pad/3", ",i", &[0i32.to_be_bytes()])).is_empty());
+        assert!(decode(&message("/pad/3", ",f", &[0.0f32.to_be_bytes()])).is_empty());
+        assert!(decode(&message("/play", ",F", &[])).is_empty());
+        assert_eq!(decode(&message("/play", ",T", &[])), [OscInput::Play]);
+    }
+
+    #[test]
+    fn knob_turns_are_clamped() {
+        assert_eq!(decode(&message("/knob/a", ",f", &[0.25f32.to_be_bytes()])), [OscInput::KnobA(0.25)]);
+        assert_eq!(decode(&message("/knob/a", ",f", &[7.0f32.to_be_bytes()])), [OscInput::KnobA(MAX_KNOB_DELTA)]);
+        assert_eq!(decode(&message("/knob/b", ",i", &[(-3i32).to_be_bytes()])), [OscInput::KnobB(-MAX_KNOB_DELTA)]);
+        // a knob needs a value to turn by
+        assert!(decode(&message("/knob/b", ",", &[])).is_empty());
+    }
+
+    #[test]
+    fn nested_bundles_are_played_in_order() {
+        let inner = bundle(&[message("/pad/2", ",", &[]), message("/play", ",", &[])]);
+        let outer = bundle(&[message("/pad/1", ",", &[]), inner, message("/pad/4", ",", &[])]);
+        assert_eq!(decode(&outer), [OscInput::Pad(0), OscInput::Pad(1), OscInput::Play, OscInput::Pad(3)]);
+    }
+
+    #[test]
+    fn truncated_and_oversized_elements_stop_the_bundle_without_panicking() {
+        let pad = message("/pad/5", ",", &[]);
+        let whole = bundle(&[pad.clone(), pad.clone()]);
+        // cut inside the second element's size, then inside its body
+        assert_eq!(decode(&whole[..16 + 4 + pad.len() + 2]), [OscInput::Pad(4)]);
+        assert_eq!(decode(&whole[..whole.len() - 1]), [OscInput::Pad(4)]);
+
+        let mut oversized = bundle(std::slice::from_ref(&pad));
+        oversized.extend(u32::MAX.to_be_bytes());
+        oversized.extend(&pad);
+        assert_eq!(decode(&oversized), [OscInput::Pad(4)]);
+
+        // a bundle header with no time tag, and an arg tag with no arg bytes
+        assert!(decode(b"#bundle\0\0\0").is_empty());
+        assert!(decode(&message("/knob/a", ",f", &[])).is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+// Offline render of a pattern, or the whole chain, to disk: the audio, fx and all
+// (see midi for just the notes).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::audio::{self, SampleBuffer, SampleId};
+use crate::middle::Middle;
+use crate::shared::STEPS_PER_PATTERN;
+
+pub const SONG_TAIL_SECS: f64 = 2.0; // room after the last bar for reverb/delay tails to ring out
+
+/// Render one bar of `pattern` at the project tempo and write it to
+/// `.pocketty/bounces/pattern_XX.wav`. Steps on empty slots are skipped; a pattern
+/// with nothing to play is an error rather than a file of silence.
+pub fn export_pattern_wav(
+    middle: &Middle,
+    samples: &HashMap<SampleId, SampleBuffer>,
+    sample_rate: u32,
+    project_dir: &Path,
+    pattern: usize,
+) -> anyhow::Result<PathBuf> {
+    let events = middle.schedule_pattern(pattern, middle.state.bpm, sample_rate);
+    if events.is_empty() {
+        anyhow::bail!("pattern {} has no steps on loaded sounds", pattern + 1);
+    }
+    let buffer = audio::bounce_offline(
+        samples, &events, bar_frames(middle, sample_rate), sample_rate, middle.state.dc_filter,
+    );
+    write_bounce(&buffer, sample_rate, project_dir, &format!("pattern_{:02}.wav", pattern + 1))
+}
+
+/// Render every pattern in the chain back to back (just the selected one if there's
+/// no chain) into `.pocketty/bounces/song.wav`. It's one continuous render, so
+/// voices and effect tails carry across bar lines like they do live, and
+/// `tail_secs` of extra time after the last bar lets them finish.
+pub fn export_song_wav(
+    middle: &Middle,
+    samples: &HashMap<SampleId, SampleBuffer>,
+    sample_rate: u32,
+    project_dir: &Path,
+    tail_secs: f64,
+) -> anyhow::Result<PathBuf> {
+    let state = &middle.state;
+    let chain = if state.pattern_chain.is_empty() {
+        vec![state.selected_pattern]
+    } else {
+        state.pattern_chain.clone()
+    };
+
+    let bar = bar_frames(middle, sample_rate);
+    let mut events = Vec::new();
+    for (i, &pattern) in chain.iter().enumerate() {
+        let start = i * bar;
+        events.extend(
+            middle.schedule_pattern(pattern as usize, state.bpm, sample_rate)
+                .into_iter()
+                .map(|(at, cmd)| (start + at, cmd)),
+        );
+    }
+    if events.is_empty() {
+        anyhow::bail!("no pattern in the chain has steps on loaded sounds");
+    }
+    events.sort_by_key(|(at, _)| *at); // stable, so ducks stay right behind their triggers
+
+    let total_frames = chain.len() * bar + (tail_secs.max(0.0) * sample_rate as f64) as usize;
+    let buffer = audio::bounce_offline(samples, &events, total_frames, sample_rate, state.dc_filter);
+    write_bounce(&buffer, sample_rate, project_dir, "song.wav")
+}
+
+/// Write a master (jam) recording to the first free `.pocketty/bounces/jam_XX.wav`.
+pub fn save_master_capture(buffer: &SampleBuffer, sample_rate: u32, project_dir: &Path) -> anyhow::Result<PathBuf> {
+    let dir = project_dir.join(".pocketty").join("bounces");
+    let name = (1..)
+        .map(|n| format!("jam_{:02}.wav", n))
+        .find(|name| !dir.join(name).exists())
+        .unwrap_or_default();
+    write_bounce(buffer, sample_rate, project_dir, &name)
+}
+
+// one bar (a pattern's worth of steps) at the project tempo
+fn bar_frames(middle: &Middle, sample_rate: u32) -> usize {
+    let secs_per_step = 60.0 / (middle.state.bpm.max(1.0) as f64 * 4.0);
+    (secs_per_step * sample_rate as f64 * STEPS_PER_PATTERN as f64) as usize
+}
+
+fn write_bounce(buffer: &SampleBuffer, sample_rate: u32, project_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let dir = project_dir.join(".pocketty").join("bounces");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(name);
+    buffer.save_wav(&path, sample_rate)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{next_sample_id, StereoFrame};
+    use crate::pipeline::project::ProjectState;
+
+    const RATE: u32 = 48000;
+
+    // a project whose first pad holds a second of DC, hit on the first step
+    fn dc_project() -> (Middle, HashMap<SampleId, SampleBuffer>) {
+        let id = next_sample_id();
+        let mut middle = Middle::with_state(ProjectState::default());
+        middle.set_sample_rate(RATE);
+        let sound = &mut middle.state.sounds[0];
+        sound.sample_id = Some(id);
+        sound.length = RATE as usize;
+        sound.buffer_len = RATE as usize;
+        middle.state.patterns[0].tracks[0].steps[0].active = true;
+        let dc = SampleBuffer::from_frames(vec![StereoFrame { left: 0.5, right: 0.5 }; RATE as usize]);
+        (middle, HashMap::from([(id, dc)]))
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pocketty-{}-{}", name, std::process::id()))
+    }
+
+    // The DC blocker turns a held level into r^n, with r set by the rate it was tuned
+    // for. Over 10ms at 48k that's exp(-2π·20·0.01) ≈ 0.284; tuned for 44.1k it'd be 0.254.
+    fn assert_dc_decay_at_48k(frames: &[StereoFrame]) {
+        let decay = frames[960].left / frames[480].left;
+        let expected = (1.0 - 2.0 * std::f32::consts::PI * 20.0 / RATE as f32).powi(480);
+        assert!((decay - expected).abs() < 0.01, "decay {} vs {}", decay, expected);
+    }
+
+    #[test]
+    fn pattern_bounce_renders_at_the_device_rate() {
+        let (middle, samples) = dc_project();
+        let dir = scratch_dir("bounce-pattern");
+        let path = export_pattern_wav(&middle, &samples, RATE, &dir, 0).unwrap();
+        let spec = hound::WavReader::open(&path).unwrap().spec();
+        let frames = SampleBuffer::load_wav(&path, RATE, 2).unwrap().data;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(spec.sample_rate, RATE);
+        assert_eq!(frames.len(), bar_frames(&middle, RATE));
+        assert_dc_decay_at_48k(&frames);
+    }
+
+    #[test]
+    fn song_bounce_renders_at_the_device_rate() {
+        let (mut middle, samples) = dc_project();
+        middle.state.pattern_chain = vec![0, 0];
+        let dir = scratch_dir("bounce-song");
+        let path = export_song_wav(&middle, &samples, RATE, &dir, 0.5).unwrap();
+        let spec = hound::WavReader::open(&path).unwrap().spec();
+        let frames = SampleBuffer::load_wav(&path, RATE, 2).unwrap().data;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(spec.sample_rate, RATE);
+        assert_eq!(frames.len(), 2 * bar_frames(&middle, RATE) + RATE as usize / 2);
+        assert_dc_decay_at_48k(&frames);
+    }
+
+    #[test]
+    fn bounce_skips_the_dc_blocker_when_it_is_off() {
+        let (mut middle, samples) = dc_project();
+        middle.state.dc_filter = false;
+        let dir = scratch_dir("bounce-no-dc");
+        let path = export_pattern_wav(&middle, &samples, RATE, &dir, 0).unwrap();
+        let frames = SampleBuffer::load_wav(&path, RATE, 2).unwrap().data;
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // the held level comes through flat instead of decaying
+        assert!((frames[960].left - frames[480].left).abs() < 1e-3);
+        assert!(frames[960].left > 0.01);
+    }
+}
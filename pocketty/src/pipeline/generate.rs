@@ -0,0 +1,82 @@
+// "Generate a beat": fills a pattern with drum hits that lean toward where each kind of
+// sound usually goes, instead of uniform noise. Pads are assumed to follow the same
+// layout the MIDI export maps to GM drums (kick, snare, closed hat, open hat, clap, ...).
+
+use crate::pipeline::project::{Pattern, ProjectState};
+use crate::shared::{NUM_SOUNDS, STEPS_PER_PATTERN};
+
+const GHOST_GAIN: f32 = 0.45; // off-backbeat snare hits come in quieter
+
+/// Replace `pattern` with a generated beat for the loaded sounds in the selected bank.
+/// `density` (0.0-1.0) scales how busy everything past the anchor hits gets; the same
+/// seed always gives the same beat. Returns how many steps were set.
+pub fn generate_pattern(state: &mut ProjectState, pattern: usize, density: f32, seed: u64) -> usize {
+    let density = density.clamp(0.0, 1.0);
+    let mut rng = Rng::new(seed);
+    let bank_start = state.selected_bank() * NUM_SOUNDS;
+
+    let mut fresh = Pattern { kit: state.patterns[pattern].kit, ..Default::default() };
+    let mut hits = 0;
+    for pad in 0..NUM_SOUNDS {
+        let slot = bank_start + pad;
+        if !state.sounds[slot].is_loaded() {
+            continue;
+        }
+        for (i, step) in fresh.tracks[slot].steps.iter_mut().enumerate().take(STEPS_PER_PATTERN) {
+            let (chance, gain) = hit_chance(pad, i, density);
+            if rng.next_f32() < chance {
+                step.active = true;
+                step.gain_lock = gain;
+                hits += 1;
+            }
+        }
+    }
+    state.patterns[pattern] = fresh;
+    hits
+}
+
+// (probability, optional gain lock) of a hit for `pad` on step `i`
+fn hit_chance(pad: usize, i: usize, density: f32) -> (f32, Option<f32>) {
+    let downbeat = i.is_multiple_of(4);
+    let eighth = i.is_multiple_of(2);
+    let backbeat = i == 4 || i == 12;
+    match pad {
+        // kick: one and three, then pushes on the other beats and eighths
+        0 if i == 0 => (1.0, None),
+        0 if i == 8 => (0.9, None),
+        0 if downbeat => (0.5 * density, None),
+        0 if eighth => (0.3 * density, None),
+        0 => (0.1 * density, None),
+        // snare: the backbeat, with the odd ghost note
+        1 if backbeat => (1.0, None),
+        1 => (0.1 * density, Some(GHOST_GAIN)),
+        // closed hat: eighths most of the time, sixteenths as it gets busier
+        2 if eighth => (0.6 + 0.4 * density, None),
+        2 => (0.6 * density, None),
+        // open hat: the "and"s
+        3 if i % 4 == 2 => (0.35 * density, None),
+        // clap: doubles the backbeat sometimes
+        4 if backbeat => (0.5 * density, None),
+        3 | 4 => (0.0, None),
+        // everything else is sparse percussion, leaning on eighths
+        _ if eighth => (0.12 * density, None),
+        _ => (0.05 * density, None),
+    }
+}
+
+// xorshift64*, plenty for picking steps and keeps us off an rng dependency
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed | 1) // all-zero state would get stuck
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -0,0 +1,160 @@
+// Standard MIDI file export for a single pattern: the note data, not the rendered audio (see bounce for that).
+// Small enough to write by hand rather than pull in a crate.
+
+use std::path::Path;
+use crate::pipeline::project::ProjectState;
+use crate::shared::{NUM_SOUNDS, STEPS_PER_PATTERN};
+
+const TICKS_PER_QUARTER: u16 = 96;
+const TICKS_PER_STEP: u32 = TICKS_PER_QUARTER as u32 / 4; // steps are sixteenths
+const DRUM_CHANNEL: u8 = 9; // GM channel 10
+const MELODIC_ROOT: f32 = 60.0; // pitch 1.0 = middle C
+
+// GM drum note per pad, same for every bank: kick, snare, hats, clap, toms, cymbals, percussion
+const GM_DRUM_NOTES: [u8; NUM_SOUNDS] = [
+    36, 38, 42, 46,
+    39, 45, 48, 50,
+    49, 51, 37, 56,
+    54, 70, 75, 76,
+];
+
+/// Write one bar of `pattern` to `path` as a format 0 MIDI file. Tracks with any
+/// pitch locks are treated as melodic and get notes from their pitch, each on its own
+/// channel; everything else lands on the drum channel via the GM map.
+pub fn export_pattern(state: &ProjectState, pattern: usize, path: &Path) -> anyhow::Result<()> {
+    let mut events: Vec<(u32, [u8; 3])> = Vec::new(); // (tick, status/data1/data2)
+    let mut melodic_channels = (0..16u8).filter(|&c| c != DRUM_CHANNEL).cycle();
+
+    for (slot, track) in state.patterns[pattern].tracks.iter().enumerate() {
+        if !track.steps.iter().any(|s| s.active) {
+            continue;
+        }
+        let sound = &state.sounds[state.sound_for(pattern, slot)];
+        let melodic = track.steps.iter().any(|s| s.active && s.pitch_lock.is_some());
+        let channel = if melodic { melodic_channels.next().unwrap_or(0) } else { DRUM_CHANNEL };
+
+        for (i, step) in track.steps.iter().enumerate().take(STEPS_PER_PATTERN) {
+            if !step.active {
+                continue;
+            }
+            let note = if melodic {
+                let pitch = step.pitch_lock.unwrap_or(sound.pitch);
+                (MELODIC_ROOT + 12.0 * pitch.log2()).round().clamp(0.0, 127.0) as u8
+            } else {
+                GM_DRUM_NOTES[slot % NUM_SOUNDS]
+            };
+            let velocity = (step.gain_lock.unwrap_or(sound.gain) * 127.0).round().clamp(1.0, 127.0) as u8;
+            let tick = i as u32 * TICKS_PER_STEP;
+            events.push((tick, [0x90 | channel, note, velocity]));
+            events.push((tick + TICKS_PER_STEP, [0x80 | channel, note, 0]));
+        }
+    }
+    // note offs before note ons on the same tick so back-to-back steps retrigger
+    events.sort_by_key(|(tick, msg)| (*tick, msg[0] & 0xF0 == 0x90));
+
+    let mut track = Vec::new();
+    let tempo = (60_000_000.0 / state.bpm.max(1.0)) as u32; // microseconds per quarter
+    track.extend([0x00, 0xFF, 0x51, 0x03]);
+    track.extend(&tempo.to_be_bytes()[1..]);
+    track.extend([0x00, 0xFF, 0x58, 0x04, 4, 2, 24, 8]); // 4/4
+
+    let mut last = 0;
+    for (tick, msg) in events {
+        write_vlq(&mut track, tick - last);
+        track.extend(msg);
+        last = tick;
+    }
+    let bar_end = STEPS_PER_PATTERN as u32 * TICKS_PER_STEP;
+    write_vlq(&mut track, bar_end.saturating_sub(last));
+    track.extend([0xFF, 0x2F, 0x00]); // end of track
+
+    let mut out = Vec::with_capacity(track.len() + 22);
+    out.extend(b"MThd");
+    out.extend(6u32.to_be_bytes());
+    out.extend(0u16.to_be_bytes()); // format 0
+    out.extend(1u16.to_be_bytes()); // one track
+    out.extend(TICKS_PER_QUARTER.to_be_bytes());
+    out.extend(b"MTrk");
+    out.extend((track.len() as u32).to_be_bytes());
+    out.extend(track);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+// MIDI variable-length quantity: 7 bits per byte, high bit set on all but the last
+fn write_vlq(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+pub struct ImportReport {
+    pub notes: usize,   // notes written into the pattern
+    pub wrapped: usize, // notes past the first bar, folded back onto it
+}
+
+/// Load the first track with notes from `path` into `pattern`. If every note is on the
+/// drum channel the GM map picks pads in the selected bank; otherwise the notes become
+/// pitch locks on the selected sound. The affected tracks are cleared first.
+pub fn import_pattern(state: &mut ProjectState, pattern: usize, path: &Path) -> anyhow::Result<ImportReport> {
+    let data = std::fs::read(path)?;
+    let smf = midly::Smf::parse(&data)?;
+    let ticks_per_quarter = match smf.header.timing {
+        midly::Timing::Metrical(t) => t.as_int() as u32,
+        midly::Timing::Timecode(..) => anyhow::bail!("timecode-based MIDI files aren't supported"),
+    };
+
+    // (absolute tick, channel, key) for every note on in the first track that has any
+    let mut notes = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0;
+        for event in track {
+            tick += event.delta.as_int();
+            if let midly::TrackEventKind::Midi { channel, message: midly::MidiMessage::NoteOn { key, vel } } = event.kind
+                && vel > 0
+            {
+                notes.push((tick, channel.as_int(), key.as_int()));
+            }
+        }
+        if !notes.is_empty() {
+            break;
+        }
+    }
+
+    let drums = notes.iter().all(|&(_, channel, _)| channel == DRUM_CHANNEL);
+    let bank_start = state.selected_bank() * NUM_SOUNDS;
+    let selected = state.selected_sound as usize;
+    let tracks = &mut state.patterns[pattern].tracks;
+    let cleared = if drums { bank_start..bank_start + NUM_SOUNDS } else { selected..selected + 1 };
+    for track in &mut tracks[cleared] {
+        track.steps = Default::default();
+    }
+
+    let mut report = ImportReport { notes: 0, wrapped: 0 };
+    for (tick, _, key) in notes {
+        // nearest sixteenth
+        let step = ((tick * 4 + ticks_per_quarter / 2) / ticks_per_quarter.max(1)) as usize;
+        if step >= STEPS_PER_PATTERN {
+            report.wrapped += 1;
+        }
+        let step = step % STEPS_PER_PATTERN;
+        if drums {
+            let Some(pad) = GM_DRUM_NOTES.iter().position(|&n| n == key) else { continue; };
+            tracks[bank_start + pad].steps[step].active = true;
+        } else {
+            let s = &mut tracks[selected].steps[step];
+            s.active = true;
+            s.pitch_lock = Some(2.0_f32.powf((key as f32 - MELODIC_ROOT) / 12.0).clamp(0.25, 4.0));
+        }
+        report.notes += 1;
+    }
+    Ok(report)
+}
@@ -1,3 +1,6 @@
+pub mod bounce;
+pub mod generate;
+pub mod midi;
 pub mod persistence;
 pub mod project;
 pub mod song;
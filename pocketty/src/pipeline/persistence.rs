@@ -1,28 +1,251 @@
 // to be called on main startup and quit; saves state of app so we can reload it later
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use crate::pipeline::project::ProjectState;
+use crate::shared::NUM_SLOTS;
 
 const POCKETTY_DIR: &str = ".pocketty";
-const PROJECT_FILE: &str = "project.json";
+const PROJECT_FILE: &str = "project.json"; // from before save slots; slot 0 falls back to it
+const SLOTS_FILE: &str = "slots.toml";
+const ACTIVE_SLOT_FILE: &str = "active_slot";
+pub const PROJECT_SLOTS: u8 = 8;
 
-// <project_dir>/.pocketty/project.json
-fn project_file_path(project_dir: &Path) -> PathBuf {
-    project_dir.join(POCKETTY_DIR).join(PROJECT_FILE)
+// <project_dir>/.pocketty/project_N.json
+fn project_slot_path(project_dir: &Path, slot: u8) -> PathBuf {
+    project_dir.join(POCKETTY_DIR).join(format!("project_{}.json", slot))
 }
 
-pub fn load_project(project_dir: &Path) -> Option<ProjectState> {
-    let path = project_file_path(project_dir);
+/// The project saved in `slot` (0..PROJECT_SLOTS), if there is one.
+pub fn load_project_slot(project_dir: &Path, slot: u8) -> Option<ProjectState> {
+    let mut path = project_slot_path(project_dir, slot);
+    if slot == 0 && !path.exists() {
+        path = project_dir.join(POCKETTY_DIR).join(PROJECT_FILE);
+    }
+    let data = std::fs::read_to_string(&path).ok()?;
+    let mut state: ProjectState = serde_json::from_str(&data).ok()?;
+    state.fill_banks();
+    Some(state)
+}
+
+/// Slots that have a saved project, in order.
+pub fn list_project_slots(project_dir: &Path) -> Vec<u8> {
+    (0..PROJECT_SLOTS)
+        .filter(|&slot| {
+            project_slot_path(project_dir, slot).exists()
+                || (slot == 0 && project_dir.join(POCKETTY_DIR).join(PROJECT_FILE).exists())
+        })
+        .collect()
+}
+
+// <project_dir>/.pocketty/active_slot holds the slot to reopen on the next launch
+pub fn load_active_slot(project_dir: &Path) -> Option<u8> {
+    let data = std::fs::read_to_string(project_dir.join(POCKETTY_DIR).join(ACTIVE_SLOT_FILE)).ok()?;
+    data.trim().parse().ok().filter(|&slot| slot < PROJECT_SLOTS)
+}
+
+pub fn save_active_slot(project_dir: &Path, slot: u8) -> anyhow::Result<()> {
+    let dir = project_dir.join(POCKETTY_DIR);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(ACTIVE_SLOT_FILE), format!("{}\n", slot))?;
+    Ok(())
+}
+
+// <project_dir>/.pocketty/slots.toml pins slots to samples, one `slot = "relative/path.wav"`
+// per line (slot is 0-63, bank by bank). Missing or unparseable file = nothing pinned.
+pub fn load_slot_manifest(project_dir: &Path) -> Option<BTreeMap<usize, PathBuf>> {
+    let path = project_dir.join(POCKETTY_DIR).join(SLOTS_FILE);
     let data = std::fs::read_to_string(&path).ok()?;
-    serde_json::from_str(&data).ok()
+    let table: BTreeMap<String, String> = toml::from_str(&data).ok()?;
+    let slots = table.into_iter()
+        .filter_map(|(slot, rel)| Some((slot.trim().parse::<usize>().ok()?, project_dir.join(rel))))
+        .filter(|(slot, _)| *slot < NUM_SLOTS)
+        .collect();
+    Some(slots)
+}
+
+// Write the pins back, paths relative to the project where they can be (so the folder
+// can move); anything outside it stays absolute.
+pub fn save_slot_manifest(project_dir: &Path, slots: &BTreeMap<usize, PathBuf>) -> anyhow::Result<()> {
+    let dir = project_dir.join(POCKETTY_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let table: BTreeMap<String, String> = slots.iter()
+        .map(|(slot, path)| {
+            let rel = path.strip_prefix(project_dir).unwrap_or(path);
+            (slot.to_string(), rel.to_string_lossy().into_owned())
+        })
+        .collect();
+    std::fs::write(dir.join(SLOTS_FILE), toml::to_string(&table)?)?;
+    Ok(())
 }
 
-// Save the project state to disk, making the files if they don't exist already
-pub fn save_project(project_dir: &Path, state: &ProjectState) -> anyhow::Result<()> {
-    let path = project_file_path(project_dir);
+// Save the project state to its slot on disk, making the files if they don't exist already.
+// Written next to the real file and renamed over it, so a crash mid-save (autosave can
+// land at any moment) leaves the previous save rather than half a file.
+pub fn save_project_slot(project_dir: &Path, state: &ProjectState, slot: u8) -> anyhow::Result<()> {
+    let path = project_slot_path(project_dir, slot);
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent)?; // create .pocketty/ if needed
     }
-    let json = serde_json::to_string_pretty(state)?;
-    std::fs::write(&path, json)?;
+    let json = serde_json::to_string(state)?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, &path)?;
     Ok(())
 }
+
+/// Writes the project in the background so a save never stalls the main loop.
+/// Each save carries the edit count its snapshot was taken at, handed back on success.
+#[derive(Default)]
+pub struct Autosave {
+    running: Option<(u64, std::thread::JoinHandle<anyhow::Result<()>>)>,
+}
+
+impl Autosave {
+    /// Save a snapshot of the project on a worker thread, unless a save is still running.
+    pub fn start(&mut self, project_dir: &Path, state: ProjectState, slot: u8, edits: u64) {
+        if self.running.is_some() {
+            return;
+        }
+        let project_dir = project_dir.to_path_buf();
+        let handle = std::thread::spawn(move || save_project_slot(&project_dir, &state, slot));
+        self.running = Some((edits, handle));
+    }
+
+    /// The edit count of a save that has just finished, if it succeeded.
+    /// A failed save is dropped; the project stays dirty and the next round retries.
+    pub fn poll(&mut self) -> Option<u64> {
+        if !self.running.as_ref().is_some_and(|(_, handle)| handle.is_finished()) {
+            return None;
+        }
+        self.wait()
+    }
+
+    /// Block until a running save lands, so a save made right after it isn't overwritten.
+    pub fn wait(&mut self) -> Option<u64> {
+        let (edits, handle) = self.running.take()?;
+        matches!(handle.join(), Ok(Ok(()))).then_some(edits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::project::Step;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pocketty-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn slot_manifest_round_trips() {
+        let dir = scratch_dir("manifest");
+        let outside = std::env::temp_dir().join("elsewhere").join("hat.wav");
+        let pins = BTreeMap::from([(3, dir.join("kit").join("snare.wav")), (12, outside)]);
+        save_slot_manifest(&dir, &pins).unwrap();
+        let written = std::fs::read_to_string(dir.join(POCKETTY_DIR).join(SLOTS_FILE)).unwrap();
+        let loaded = load_slot_manifest(&dir);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(written.contains("\"kit/snare.wav\""), "{}", written); // relative inside the project
+        assert_eq!(loaded, Some(pins));
+    }
+
+    #[test]
+    fn step_effects_survive_save_and_load() {
+        use crate::audio::EffectSpec;
+        let dir = scratch_dir("step-effects");
+        let mut state = ProjectState::default();
+        let step = &mut state.patterns[0].tracks[0].steps[0];
+        step.effect = Some(3);
+        step.effects = vec![
+            EffectSpec::LowPass { cutoff_hz: 800.0, resonance: 0.3 },
+            EffectSpec::Reverb { room_size: 0.7, damping: 0.4, mix: 0.25 },
+        ];
+        save_project_slot(&dir, &state, 1).unwrap();
+        let loaded = load_project_slot(&dir, 1).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let step = &loaded.patterns[0].tracks[0].steps[0];
+        assert_eq!(step.effect, Some(3));
+        assert_eq!(format!("{:?}", step.effects), format!("{:?}", state.patterns[0].tracks[0].steps[0].effects));
+    }
+
+    #[test]
+    fn project_json_from_before_step_effects_still_loads() {
+        let dir = scratch_dir("old-project");
+        let mut state = ProjectState::default();
+        state.patterns[0].tracks[0].steps[0].effect = Some(3);
+        let mut json = serde_json::to_value(&state).unwrap();
+        let step = &mut json["patterns"][0]["tracks"][0]["steps"][0];
+        step.as_object_mut().unwrap().remove("effects");
+        assert!(step.get("effect").is_some());
+        std::fs::create_dir_all(dir.join(POCKETTY_DIR)).unwrap();
+        std::fs::write(dir.join(POCKETTY_DIR).join(PROJECT_FILE), json.to_string()).unwrap();
+        let loaded = load_project_slot(&dir, 0);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let step = &loaded.expect("old project should load").patterns[0].tracks[0].steps[0];
+        assert_eq!(step.effect, Some(3));
+        assert!(step.effects.is_empty());
+    }
+
+    #[test]
+    fn sounds_saved_before_interpolation_load_as_cubic() {
+        use crate::audio::Interpolation;
+        let dir = scratch_dir("old-interp");
+        let mut json = serde_json::to_value(ProjectState::default()).unwrap();
+        for sound in json["sounds"].as_array_mut().unwrap() {
+            sound.as_object_mut().unwrap().remove("interp");
+        }
+        std::fs::create_dir_all(dir.join(POCKETTY_DIR)).unwrap();
+        std::fs::write(dir.join(POCKETTY_DIR).join(PROJECT_FILE), json.to_string()).unwrap();
+        let loaded = load_project_slot(&dir, 0);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(loaded.unwrap().sounds.iter().all(|s| s.interp == Interpolation::Cubic));
+    }
+
+    #[test]
+    fn empty_tracks_and_steps_stay_out_of_the_save() {
+        let dir = scratch_dir("sparse");
+        let mut state = ProjectState::default();
+        save_project_slot(&dir, &state, 0).unwrap();
+        let empty = std::fs::metadata(project_slot_path(&dir, 0)).unwrap().len();
+        assert!(empty < 64 * 1024, "an empty project saved as {} bytes", empty);
+
+        // a step in the last bank still comes back, with every track around it
+        let last = NUM_SLOTS - 1;
+        state.patterns[3].tracks[last].steps[5] = Step { active: true, nudge: 0.25, ..Default::default() };
+        save_project_slot(&dir, &state, 0).unwrap();
+        let loaded = load_project_slot(&dir, 0).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(loaded.patterns.iter().all(|p| p.tracks.len() == NUM_SLOTS));
+        let step = &loaded.patterns[3].tracks[last].steps[5];
+        assert!(step.active && step.nudge == 0.25 && step.ratchet == 0);
+        assert!(loaded.patterns[3].tracks[0].is_empty());
+    }
+
+    #[test]
+    fn autosave_writes_the_snapshot_and_hands_back_its_edit_count() {
+        let dir = scratch_dir("autosave");
+        let mut state = ProjectState { bpm: 97.0, ..ProjectState::default() };
+        let mut autosave = Autosave::default();
+        assert_eq!(autosave.poll(), None); // nothing running
+
+        autosave.start(&dir, state.clone(), 2, 7);
+        state.bpm = 140.0; // an edit after the snapshot doesn't reach the file
+        assert_eq!(autosave.wait(), Some(7));
+        assert_eq!(autosave.wait(), None);
+        assert_eq!(load_project_slot(&dir, 2).unwrap().bpm, 97.0);
+
+        // a write that can't land doesn't count as saved
+        let blocked = dir.join("blocked");
+        std::fs::write(&blocked, "").unwrap();
+        autosave.start(&blocked, state, 0, 8);
+        assert_eq!(autosave.wait(), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
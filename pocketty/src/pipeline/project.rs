@@ -1,8 +1,8 @@
 // defines a ton of structs for middle.rs to finangle
 
 use serde::{Deserialize, Serialize}; // serde does json
-use crate::audio::SampleId;
-use crate::shared::{NUM_PATTERNS, NUM_SOUNDS, STEPS_PER_PATTERN};
+use crate::audio::{EffectSpec, Interpolation, SampleId};
+use crate::shared::{KnobTarget, ParamPage, NUM_PATTERNS, NUM_SLOTS, NUM_SOUNDS, STEPS_PER_PATTERN};
 
 // -- DEFINITIONS --
 // I hate all of this terminology. 
@@ -33,8 +33,43 @@ pub struct SoundSlot {
     // I'm thinking of doing the full PO-33 stuff here isntead of the OP-1 auto adsr stuff manit was talking about.
     pub filter_cutoff: f32,
     pub filter_resonance: f32,
+    #[serde(default)]
+    pub interp: Interpolation, // repitch quality: raw (nearest), linear, cubic
+    #[serde(default)]
+    pub reverse: bool, // always play backwards (on top of the momentary fx 15)
+    #[serde(default)]
+    pub compress: bool, // run through the insert compressor
+    #[serde(default)]
+    pub cue: bool, // pre-listen on the cue output instead of the main mix
+    #[serde(default)]
+    pub length_mode: LengthMode, // how long each hit plays: the trimmed region, or a tempo-synced gate
+
+    // amplitude envelope: times in seconds, sustain is a level. The defaults play the sample as-is.
+    #[serde(default)]
+    pub attack: f32,
+    #[serde(default)]
+    pub decay: f32,
+    #[serde(default = "default_sustain")]
+    pub sustain: f32,
+    #[serde(default)]
+    pub release: f32,
+    // sustain loop, frames from trim_start. Set, a hit loops [start, end) until its pad is let go.
+    #[serde(default)]
+    pub loop_start: Option<usize>,
+    #[serde(default)]
+    pub loop_end: Option<usize>,
+    #[serde(default)]
+    pub pan: f32, // -1.0 (left) .. 1.0 (right)
+    #[serde(default)]
+    pub normalized: bool, // the sample was brought to a set peak on load; reloads do the same
+    #[serde(default)]
+    pub choke_group: Option<u8>, // 1..=CHOKE_GROUPS; a hit cuts the other sounds in the same group
 }
 
+pub const CHOKE_GROUPS: u8 = 4;
+
+fn default_sustain() -> f32 { 1.0 }
+
 impl Default for SoundSlot {
     fn default() -> Self {
         Self {
@@ -47,6 +82,61 @@ impl Default for SoundSlot {
             pitch: 1.0,
             filter_cutoff: 20000.0,
             filter_resonance: 0.0,
+            interp: Interpolation::Cubic,
+            reverse: false,
+            compress: false,
+            cue: false,
+            length_mode: LengthMode::Samples,
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.0,
+            loop_start: None,
+            loop_end: None,
+            pan: 0.0,
+            normalized: false,
+            choke_group: None,
+        }
+    }
+}
+
+pub const MAX_RATCHET: u8 = 4;
+
+// Gate lengths the sound button cycles through, in beats (a beat being a quarter note)
+const GATE_BEATS: [f32; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum LengthMode {
+    #[default]
+    Samples,    // play the trimmed region (`length`)
+    Beats(f32), // cut off after this many beats at the current bpm, never past the region
+}
+
+impl LengthMode {
+    /// Samples -> 1/16 -> 1/8 -> 1/4 -> 1/2 -> 1 bar -> Samples
+    pub fn next(self) -> Self {
+        match self {
+            LengthMode::Samples => LengthMode::Beats(GATE_BEATS[0]),
+            LengthMode::Beats(b) => match GATE_BEATS.iter().position(|&g| g > b) {
+                Some(i) => LengthMode::Beats(GATE_BEATS[i]),
+                None => LengthMode::Samples,
+            },
+        }
+    }
+
+    /// Region length to hand the voice for a sound trimmed to `length` samples.
+    pub fn frames(self, length: usize, bpm: f32, sample_rate: f32) -> usize {
+        match self {
+            LengthMode::Samples => length,
+            LengthMode::Beats(b) => ((60.0 / bpm.max(1.0) * b * sample_rate) as usize).clamp(1, length.max(1)),
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            LengthMode::Samples => String::new(),
+            LengthMode::Beats(b) if b >= 4.0 => format!("G{}BAR", b / 4.0),
+            LengthMode::Beats(b) => format!("G1/{}", (4.0 / b).round()),
         }
     }
 }
@@ -58,13 +148,21 @@ impl SoundSlot {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Pattern {
-    pub tracks: [Track; NUM_SOUNDS],
+    // one per sound slot (NUM_SLOTS), indexed like ProjectState::sounds. Saved only up to the
+    // last one with anything on it; fill_banks pads the rest back out on load.
+    #[serde(serialize_with = "serialize_used_tracks")]
+    pub tracks: Vec<Track>,
+    // Bank every track plays through, whichever bank its steps were written in, so the same
+    // beat can be reused with another kit. None = each track plays its own slot.
+    #[serde(default)]
+    pub kit: Option<u8>,
 }
 
 impl Default for Pattern {
     fn default() -> Self {
         Self {
-            tracks: std::array::from_fn(|_| Track::default()), // inherits defaults
+            tracks: vec![Track::default(); NUM_SLOTS], // inherits defaults
+            kit: None,
         }
     }
 }
@@ -73,38 +171,119 @@ impl Default for Pattern {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Track {
     pub steps: [Step; STEPS_PER_PATTERN],
+    // How many steps the track loops over before wrapping (1..=16). Steps past this are out of range.
+    #[serde(default = "default_track_length", skip_serializing_if = "is_full_length")]
+    pub length: usize,
+    // Effect used on steps that don't set their own. Precedence when sequencing:
+    // real-time effect > per-step effect > track default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<u8>,
+    // Swing for just this track (same 0.0-1.0 scale as the project's); None follows the project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swing: Option<f32>,
+}
+
+fn default_track_length() -> usize { STEPS_PER_PATTERN }
+fn is_full_length(length: &usize) -> bool { *length == STEPS_PER_PATTERN }
+fn is_default<T: Default + PartialEq>(value: &T) -> bool { *value == T::default() }
+
+// 16 patterns x 64 tracks x 16 steps is most of a project, and nearly all of it empty
+fn serialize_used_tracks<S: serde::Serializer>(tracks: &[Track], serializer: S) -> Result<S::Ok, S::Error> {
+    let used = tracks.iter().rposition(|t| !t.is_empty()).map_or(0, |i| i + 1);
+    serializer.collect_seq(&tracks[..used])
+}
+
+impl Step {
+    /// When each of a ratcheted step's hits goes off, in steps after the first. The
+    /// hits split the step evenly; locks apply to all of them.
+    pub fn ratchet_offsets(&self) -> impl Iterator<Item = f64> {
+        let hits = self.ratchet.clamp(1, MAX_RATCHET);
+        (0..hits).map(move |k| k as f64 / hits as f64)
+    }
+
+    /// Nothing set on it at all, so it saves as `{}`.
+    pub fn is_empty(&self) -> bool {
+        !self.active
+            && self.pitch_lock.is_none()
+            && self.gain_lock.is_none()
+            && self.filter_cutoff_lock.is_none()
+            && self.filter_resonance_lock.is_none()
+            && self.probability.is_none()
+            && self.nudge == 0.0
+            && self.ratchet == 0
+            && self.effect.is_none()
+            && self.effects.is_empty()
+    }
+}
+
+impl Track {
+    /// Which of this track's steps plays `position` steps into the pattern. Tracks
+    /// shorter than the pattern wrap on their own, which is what makes polymeters.
+    pub fn wrap(&self, position: usize) -> usize {
+        position % self.length.clamp(1, STEPS_PER_PATTERN)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.iter().all(Step::is_empty)
+            && self.length == STEPS_PER_PATTERN
+            && self.effect.is_none()
+            && self.swing.is_none()
+    }
 }
 
 impl Default for Track {
     fn default() -> Self {
         Self {
-            steps: [Step::default(); STEPS_PER_PATTERN],
+            steps: Default::default(),
+            length: STEPS_PER_PATTERN,
+            effect: None,
+            swing: None,
         }
     }
 }
 
 
-#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+// Only what's set gets saved; anything missing loads as its default.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Step {
+    #[serde(skip_serializing_if = "is_default")]
     pub active: bool,                        // has it been toggled in the UI?
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub pitch_lock: Option<f32>,             // updates default pitch in trigger call (multiplied)
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gain_lock: Option<f32>,              // updates gain similarly
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub filter_cutoff_lock: Option<f32>,     // updates filter cutoff similarly
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub filter_resonance_lock: Option<f32>,  // updates filter resonance similarly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probability: Option<f32>,            // chance (0.0-1.0) the step fires each pass; None always fires
+    #[serde(skip_serializing_if = "is_default")]
+    pub nudge: f32,                          // off-grid timing, -0.5..0.5 of a step (negative = early)
+    #[serde(skip_serializing_if = "is_default")]
+    pub ratchet: u8,                         // hits per step for rolls, up to MAX_RATCHET; 0 and 1 both fire once
 
     // Upon review of the manual, we're only ever going to have one effect on a step at a time.
     // Also now that I think about it, the PO-33 doesn't even have sound-level effects, only global effects.
     // I guess we'll add our own effects later that can edit on the sound level, but for now I suppose all we
     // need to worry about is global effects. And because we're procrastinating that part anyways, I guess effects
     // won't matter for a while anyways.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub effect: Option<u8>,
+
+    // Fully spelled-out inserts, appended after the sound's own. Unlike `effect` these keep
+    // their parameters across save/load; older projects just don't have the field.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<EffectSpec>,
 }
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProjectState {
-    pub sounds: [SoundSlot; NUM_SOUNDS],
-    pub selected_sound: u8, // what sound/channel (previously called "pattern") are we on?
+    // Banks are laid out back to back: bank b, pad n is sounds[b * NUM_SOUNDS + n].
+    pub sounds: Vec<SoundSlot>,
+    pub selected_sound: u8, // what sound/channel (previously called "pattern") are we on? global across banks
     pub patterns: [Pattern; NUM_PATTERNS],
     pub selected_pattern: u8, // what pattern (previously called "song") are we on?
     pub bpm: f32,
@@ -112,22 +291,123 @@ pub struct ProjectState {
 
     // Fancy stuff
     pub swing: f32, // Not entirely sure how this is handled, probably an offset in the sequencer loop
-    pub master_volume: u8, // It'd be fun to implement the PO BPM+1-16 volume control
+    pub master_volume: f32, // 1.0-16.0, whole steps from BPM + pad, fine steps from BPM + sound + knob B
     pub pattern_chain: Vec<u8>, // Also like a very, very end-game feature, definitely not needed for the demo.
+    #[serde(default = "default_tuning_hz")]
+    pub tuning_hz: f32, // master tuning reference (A), scales every trigger's pitch
+    #[serde(default)]
+    pub fx_quantize: u8, // real-time fx engage/release on multiples of this many steps (0 = instantly)
+    #[serde(default = "default_dc_filter")]
+    pub dc_filter: bool, // master ~20 Hz high-pass (DC / rumble removal)
+    #[serde(default = "default_record_threshold")]
+    pub record_threshold: f32, // input peak (0.001-0.5) that starts an armed recording
+    #[serde(default)]
+    pub normalize_recordings: bool, // finished recordings peak at -1 dBFS
+    #[serde(default)]
+    pub trim_recordings: bool, // finished recordings lose their silent tail
+    #[serde(default)]
+    pub input_monitor: bool, // hear the input while armed
+    #[serde(default = "default_monitor_gain")]
+    pub monitor_gain: f32, // 0.0-1.0
+    #[serde(default)]
+    pub stutter_sync: bool, // phase-lock stutter fx to the step grid
+    #[serde(default = "default_macro_targets")]
+    pub macro_targets: (KnobTarget, KnobTarget), // knob A / knob B on the macro param page
+    #[serde(default)]
+    pub sidechain: Sidechain,
+
+    // Where the UI was when we saved, so reopening picks up there (play always starts stopped)
+    #[serde(default)]
+    pub param_page: ParamPage,
+    #[serde(default)]
+    pub write_mode: bool,
 }
 
+fn default_tuning_hz() -> f32 { 440.0 }
+fn default_dc_filter() -> bool { true }
+fn default_record_threshold() -> f32 { 0.02 }
+fn default_monitor_gain() -> f32 { 0.8 }
+fn default_macro_targets() -> (KnobTarget, KnobTarget) { (KnobTarget::Cutoff, KnobTarget::Gain) }
+
 impl Default for ProjectState {
     fn default() -> Self {
         Self {
-            sounds: std::array::from_fn(|_| SoundSlot::default()),
+            sounds: vec![SoundSlot::default(); NUM_SLOTS],
             selected_sound: 0,
             patterns: std::array::from_fn(|_| Pattern::default()),
             selected_pattern: 0,
             bpm: 120.0,
             current_step: 0,
             swing: 0.0,
-            master_volume: 8,
+            master_volume: 8.0,
             pattern_chain: Vec::new(),
+            tuning_hz: default_tuning_hz(),
+            fx_quantize: 0,
+            dc_filter: default_dc_filter(),
+            record_threshold: default_record_threshold(),
+            normalize_recordings: false,
+            trim_recordings: false,
+            input_monitor: false,
+            monitor_gain: default_monitor_gain(),
+            stutter_sync: false,
+            macro_targets: default_macro_targets(),
+            sidechain: Sidechain::default(),
+            param_page: ParamPage::Tone,
+            write_mode: false,
+        }
+    }
+}
+
+impl ProjectState {
+    /// Projects saved before banks existed only have one bank's worth of sounds
+    /// and tracks; pad everything out so indexing by any slot is safe.
+    pub fn fill_banks(&mut self) {
+        self.sounds.resize_with(NUM_SLOTS, SoundSlot::default);
+        for pattern in &mut self.patterns {
+            pattern.tracks.resize_with(NUM_SLOTS, Track::default);
+        }
+        if self.selected_sound as usize >= NUM_SLOTS {
+            self.selected_sound = 0;
+        }
+    }
+
+    pub fn selected_bank(&self) -> usize {
+        self.selected_sound as usize / NUM_SOUNDS
+    }
+
+    /// Slot that `track` of `pattern` sounds through, after the pattern's kit.
+    pub fn sound_for(&self, pattern: usize, track: usize) -> usize {
+        match self.patterns[pattern].kit {
+            Some(bank) => bank as usize * NUM_SOUNDS + track % NUM_SOUNDS,
+            None => track,
+        }
+    }
+
+    /// Point the pads at the selected pattern's kit, keeping the same pad.
+    pub fn follow_kit(&mut self) {
+        if let Some(bank) = self.patterns[self.selected_pattern as usize].kit {
+            let pad = self.selected_sound as usize % NUM_SOUNDS;
+            self.selected_sound = (bank as usize * NUM_SOUNDS + pad) as u8;
+        }
+    }
+}
+
+// Sequenced hits on the source sound duck the target sounds (kick pumping the bass)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sidechain {
+    pub source: Option<u8>, // slot that keys the ducking, None = off
+    pub targets: Vec<u8>,   // slots that get ducked
+    pub amount: f32,        // 0.0-1.0, how far the targets drop on each hit
+    pub release_ms: f32,    // how long they take to come back up
+}
+
+impl Default for Sidechain {
+    fn default() -> Self {
+        Self {
+            source: None,
+            targets: Vec::new(),
+            amount: 0.8,
+            release_ms: 150.0,
         }
     }
 }
@@ -140,6 +420,7 @@ pub struct HeldButtons {
     pub record: bool,
     pub fx: bool,
     pub bpm: bool,
+    pub preview: bool, // real-time fx are audition-only, even in write mode
     pub write_held: bool, // while certain other command-buttons are just toggled, write being held signifies live record
     pub grid: [bool; NUM_SOUNDS], // chords/passing in pitch as an array in trigger eventually?
 }
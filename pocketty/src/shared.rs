@@ -14,7 +14,38 @@
 //   b             //  RecordDown / RecordUp
 //   y             //  FxDown / FxUp
 //   n             //  BpmDown / BpmUp
+//   ;             //  PreviewDown / PreviewUp (fx + pad auditions without writing to steps)
 //   0             //  ClearTrack (clears current sound's track (i.e steps) so you can record over it)
+//   k             //  ToggleHold (last pad played drones until another pad or hold off)
+//   '             //  ToggleLoopRoll (the playing voice repeats one step's worth of its start)
+//   g + 9         //  CycleChokeGroup (off, 1..4: a hit cuts the other sounds in its group, e.g. open/closed hat)
+//   g + '         //  ToggleSustainLoop (selected sound loops the back half of its region while the pad is held)
+//   l             //  ToggleStutterSync (stutter fx restart on every step)
+//   u             //  ToggleDcFilter (master high-pass that removes DC offset)
+//   N             //  ToggleNormalize (new mic recordings are brought up to -1 dBFS)
+//   L             //  ToggleNormalizeLoads (files loaded from now on are brought to -1 dBFS; off by default)
+//   T             //  ToggleTrimSilence (new mic recordings lose their silent tail, ~50ms kept)
+//   O             //  ToggleInputMonitor (hear the input while armed; off when input and output are one device)
+//   M             //  ToggleMetronome (click on every beat while playing, accented on the one)
+//   g + m         //  ToggleSoundReverse (selected sound always plays backwards)
+//   g + /         //  ToggleSoundCompressor (insert compressor on the selected sound)
+//   g + `         //  ToggleSoundCue (selected sound plays on the cue output, not the mix)
+//   g + \         //  CycleLengthMode (play the trim region, or gate to 1/16 .. 1 bar at the bpm)
+//   g + , / g + . //  SetDuckSource / ToggleDuckTarget (sidechain routing)
+//   p             //  BouncePattern (renders one bar to .pocketty/bounces/pattern_XX.wav)
+//   h + p         //  BounceSong (the whole chain, tails included, to .pocketty/bounces/song.wav)
+//   R             //  ToggleMasterCapture (record the output, knob moves and all, to .pocketty/bounces/jam_XX.wav)
+//   P / I         //  ExportMidi / ImportMidi
+//   X / n + X     //  GeneratePattern (random beat for the loaded sounds in the bank; n = busier)
+//   h + K         //  TogglePatternKit (pattern plays through the selected bank's sounds)
+//   h + C / h + V //  CopyPattern / PastePattern (current pattern, locks and all, onto another)
+//   h + t + pad   //  SwitchProjectSlot (pads 1-8: save this project, open the one in that slot)
+//   Z / Y         //  Undo / Redo (step toggles, generate, paste, clears, sound deletes; 32 deep)
+//   )             //  ClearAllPatterns (asks "CLEAR ALL? (y)" first, y confirms)
+//   G             //  GrabBar (next full bar of the output, fx included, becomes a new sound)
+//   B             //  ToggleBrowser (pick a WAV/FLAC/MP3 by ear: ↑/↓ audition, enter loads it into the sound)
+//   j             //  DumpState (debug snapshot to .pocketty/state_dump.txt)
+//   Ctrl+S        //  Save (the current project slot right now, instead of waiting for quit)
 //
 // Knobs:
 //   [ / ]         //  KnobTurnA(-0.05 or 0.05, or whatever other offset we decide on)
@@ -34,15 +65,20 @@
 //        when holding the pattern button, it'll show a high intensity on the current 
 //        pattern's button)
 //      - Draw `bpm` and a context-dependent `display_text` in the screen segment
-//      - Draw `param_page` text (Tone, Filter, Trim), and the current `knob_a_label/value` 
+//      - Draw `param_page` text (Tone, Env, Tail, Mix, Filter, Trim, Macro), and the current `knob_a_label/value` 
 //        and `knob_b_label/value` in the screen segment
 //      - Probably other things too eventually...
 //   - But yeah, this middle layer is where all of the complexity lies; the TUI just reads
 //     what text, icons, LEDs, and Knob values to display, and does that.
 
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
 pub const NUM_PADS: usize = 16;
 pub const NUM_PATTERNS: usize = 16;
-pub const NUM_SOUNDS: usize = 16;
+pub const NUM_SOUNDS: usize = 16; // sounds per bank, one per pad
+pub const NUM_BANKS: usize = 4;
+pub const NUM_SLOTS: usize = NUM_SOUNDS * NUM_BANKS; // every sound slot across all banks
 pub const STEPS_PER_PATTERN: usize = 16;
 
 // ye olde types
@@ -85,33 +121,122 @@ pub enum InputEvent {
     BpmDown,
     BpmUp,
 
+    // fx preview modifier (;): real-time fx are heard but never written in write mode
+    PreviewDown,
+    PreviewUp,
+
     // clear current sound's track in current pattern (0 key)
     ClearTrack,
 
+    // toggle drone hold: the last pad played keeps sounding (k key)
+    ToggleHold,
+    ToggleLoopRoll, // (') lock the drone / selected sound's voice into looping its first step
+    ToggleStutterSync, // (l) lock stutter fx phase to the step grid
+    ToggleDcFilter, // (u) master DC / subsonic high-pass
+    ToggleMetronome, // (shift+m) beat click, never recorded or bounced
+    ToggleNormalize, // (shift+n) normalize mic recordings as they finish
+    ToggleNormalizeLoads, // (shift+l) normalize files as they load into a slot
+    ToggleTrimSilence, // (shift+t) trim the silent tail off mic recordings as they finish
+    ToggleInputMonitor, // (shift+o) pass the input through to the output while armed
+    AdjustMonitorGain(f32), // record + knob B
+
     // cycle input device (mic ↔ loopback etc.)
     CycleInputDevice,
 
+    // capture the next bar of the master output into a free slot (shift+g, while playing)
+    GrabBar,
+
+    // sample browser overlay (shift+b): arrows audition, enter loads into the selected slot
+    ToggleBrowser,
+    BrowserMove(i32),
+    BrowserConfirm,
+
     // bounce current pattern to WAV
     BouncePattern,
+    // bounce the pattern chain (or just the selected pattern) to one WAV
+    BounceSong,
+    // (shift+r) start/stop recording the master output to a WAV
+    ToggleMasterCapture,
+
+    // export current pattern's notes to export/pattern_NN.mid (shift+p)
+    ExportMidi,
+
+    // load the first .mid in the project dir into the current pattern (shift+i)
+    ImportMidi,
+
+    // tie the current pattern to the selected bank's sounds, or untie it (h + shift+k)
+    TogglePatternKit,
+
+    // copy the current pattern (h + shift+c), paste it over the current pattern (h + shift+v)
+    CopyPattern,
+    PastePattern,
+
+    // replace the current pattern with a generated beat (shift+x, busier with bpm held)
+    GeneratePattern { density: f32 },
+    // put back what the last step toggle, generate, paste, clear or sound delete
+    // replaced (shift+z), and take it back out again (shift+y)
+    Undo,
+    Redo,
+
+    // wipe every pattern (shift+0); only asks, ConfirmClearAll(true) does it
+    ClearAllPatterns,
+    // answer to the clear-all prompt: y = go, any other key backs out
+    ConfirmClearAll(bool),
+
+    // write a readable snapshot of project + sequencer state for bug reports (j)
+    DumpState,
+
+    // write the project to disk now (ctrl+s); it's also saved on quit
+    Save,
+
+    // device settings overlay (o): open/close, move the cursor, switch to the highlighted device
+    ToggleSettings,
+    SettingsMove(i32),
+    SettingsConfirm,
 
     // quit button (esc)
     Quit,
 
     // semantic grid events!! now resolving by tui and not sending keyevents to backend lol
-    SelectSound(u8), // held sound + grid press
+    SelectSound(u8), // held sound + grid press (pad within the current bank)
     SelectPattern(u8), // held pattern + grid press (stopped)
-    ChainPattern(u8), // held pattern + grid press (playing)
+    QueuePattern(u8), // held pattern + first grid press (playing): switch at the next loop point
+    ChainPattern(u8), // held pattern + further grid presses (playing)
+    SwitchProjectSlot(u8), // held pattern + write + grid press (pads 1-8)
     SetVolume(u8), // held bpm + grid press
+    AdjustVolume(f32), // held bpm + held sound + knob b
+    AdjustTrackSwing(f32), // held bpm + held sound + knob a: selected sound's track only
     ToggleStep(u8), // write_mode + grid press (stopped)
     LiveRecordStep(u8), // held write + grid press (playing)
     SetRealtimeEffect(u8), // held fx + grid press (playing)
     ClearRealtimeEffect, // held fx + grid 16 (playing)
+    CycleFxQuantize(i32), // held fx + knob a: step through fx quantize divisions
+    SetTrackEffect(Option<u8>), // held fx + held sound + grid press (grid 16 clears)
+    SetTrackLength(u8), // held pattern + held sound + grid press: selected track loops every n steps
     DeleteSound, // held record + held sound
     TriggerPad(u8), // default: play pad melodically
 
     // semantic knob events, again resolving by tui
+    ShiftBank(i32), // held sound + knob a: step through sound banks
+    CycleInterpolation, // held sound + knob b: selected sound's repitch interpolation
+    ToggleSoundReverse, // held sound + m: selected sound plays backwards
+    ToggleSustainLoop, // (g + ') loop the back half of the region until the pad is let go
+    CycleChokeGroup, // (g + 9) selected sound's choke group: off, 1..4
+    ReleasePad(u8), // pad key let go (only seen when the terminal reports releases)
+    ToggleSoundCompressor, // held sound + '/'
+    ToggleSoundCue, // held sound + '`': pre-listen on the cue output (POCKETTY_CUE_DEVICE)
+    CycleLengthMode, // held sound + '\': trimmed region or a tempo-synced gate
+    SetDuckSource, // held sound + ',': selected sound keys the sidechain (again to turn it off)
+    ToggleDuckTarget, // held sound + '.': selected sound gets ducked by the source
     AdjustSwing(f32), // held bpm + knob a
     AdjustBpm(f32), // held bpm + knob b
+    AdjustRecordThreshold(f32), // held record + knob a, before the capture starts
+    TapTempo, // held bpm + play: bpm follows the taps
+    #[cfg_attr(not(feature = "midi"), allow(dead_code))]
+    PadVelocity(f32), // MIDI note velocity (0.0-1.0), sent right before the pad press it belongs to
+    #[cfg_attr(not(feature = "midi"), allow(dead_code))]
+    SyncTempo(f32), // MIDI clock: bpm follows the controller
+    AdjustTuning(f32), // held pattern + knob a (master A reference)
     PitchLockStep(f32), // held write + playing + knob a (locks current playing step)
     GainLockStep(f32), // held write + playing + knob b (locks current playing step)
     AdjustPitch(f32), // default knob a (tone page)
@@ -120,10 +245,19 @@ pub enum InputEvent {
     AdjustFilterResonance(f32), // default knob b (filter page)
     AdjustTrimStart(f32), // default knob a (trim page)
     AdjustTrimLength(f32), // default knob b (trim page)
+    AdjustAttack(f32), // default knob a (env page)
+    AdjustDecay(f32), // default knob b (env page)
+    AdjustSustain(f32), // default knob a (tail page)
+    AdjustRelease(f32), // default knob b (tail page)
+    AdjustPan(f32), // default knob a (mix page)
+    CycleMacroTarget { knob: u8, dir: i32 }, // held fx + knob a/b on the macro page: reassign that knob
 
     // per-step parameter locks: hold a step pad in write mode (stopped) + turn knob
     LockStepPitchAt { step: u8, delta: f32 }, // semitone-based pitch lock
     LockStepGainAt { step: u8, delta: f32 },  // gain lock
+    LockStepProbabilityAt { step: u8, delta: f32 }, // chance the step fires (fx held + knob b)
+    LockStepNudgeAt { step: u8, delta: f32 }, // push the step off the grid (fx held + knob a)
+    LockStepRatchetAt { step: u8, dir: i32 }, // hits per step, 1-4 (bpm held + knob a)
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -136,20 +270,113 @@ pub enum RecordingDisplay {
 #[derive(Clone, Debug)]
 pub struct DisplayState {
     pub leds: [LedState; STEPS_PER_PATTERN],
+    pub active_length: u8, // selected track's loop length; leds past this are OutOfRange
     pub playing_step: Option<u8>, // if in sequence mode, which step is playing
+    pub position: Option<(u32, u8)>, // (bar, beat), both 1-based, counted from when play was pressed
     pub write_mode: bool,
     pub playing: bool, // whether we're in sequence mode and playing
+    pub hold: bool, // drone hold is on
     pub recording: RecordingDisplay,
     pub param_page: ParamPage, // knob text
-    pub selected_sound: u8, // current sound slot
+    pub selected_sound: u8, // current sound slot (global, across banks)
     pub selected_pattern: u8, // current pattern slot
+    pub queued_pattern: Option<u8>, // pattern waiting for the next loop point (blinks)
+    pub playing_pattern: Option<u8>, // what the sequencer is playing, None when stopped
+    pub chain_position: Option<(usize, usize)>, // (1-based position, length) while a chain of 2+ plays
     pub bpm: f32,
+    pub tuning_hz: f32, // master A reference, shown when it isn't 440
+    pub swing_pct: u8, // 0 = straight, 50 = off-beats half a step late
     pub display_text: String, // 4-6 chars of text to be displayed, not entirely sure what these will definitively be yet.
     pub knob_a_label: &'static str, // "PITCH", "CUTOFF", "START"
     pub knob_b_label: &'static str, // "GAIN", "RESO", "LENGTH"
     pub knob_a_value: f32,
     pub knob_b_value: f32,
+    pub macro_targets: (KnobTarget, KnobTarget), // what the knobs drive on the macro page
+    pub sound_reversed: bool, // selected sound is set to always play backwards
+    pub dirty: bool, // project has changes that aren't on disk yet
     pub input_device: String, // current input device name (for display)
+    pub input_ok: bool, // false when the input stream failed to open or errored
+    pub monitor_held: bool, // monitoring is on but held off: input and output are the same device
+    pub input_peak: f32, // 0.0-1.0 input level, metered on the LCD while recording
+    pub peak: f32, // 0.0-1.0 master output level
+    pub clipping: bool, // the output went over 1.0 in the last second (clip light)
+    pub pad_peaks: [f32; NUM_SOUNDS], // 0.0-1.0 output level of each sound in the bank, metered under its pad
+    pub capture_secs: f32, // how long the current capture has been running
+    pub jam_secs: Option<f32>, // how long the master output has been recording, if it is
+    pub voice_count: usize, // voices the engine is currently rendering
+    pub cpu_load: u32, // audio callback time as % of its real-time budget
+    pub device_menu: Option<DeviceMenu>, // settings overlay, drawn over the device when open
+    pub browser: Option<SampleBrowser>, // sample browser overlay, same idea
+}
+
+// Settings overlay listing audio devices. Inputs are listed first, then outputs;
+// `cursor` runs across both lists.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceMenu {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub current_input: usize,
+    pub current_output: usize,
+    pub cursor: usize,
+    pub error: Option<String>, // last failed switch, shown until the next one
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceChoice {
+    Input(usize),
+    Output(usize),
+}
+
+impl DeviceMenu {
+    pub fn len(&self) -> usize {
+        self.inputs.len() + self.outputs.len()
+    }
+
+    pub fn move_cursor(&mut self, delta: i32) {
+        let n = self.len() as i32;
+        if n > 0 {
+            self.cursor = (self.cursor as i32 + delta).rem_euclid(n) as usize;
+        }
+    }
+
+    pub fn selected(&self) -> Option<DeviceChoice> {
+        if self.cursor < self.inputs.len() {
+            Some(DeviceChoice::Input(self.cursor))
+        } else if self.cursor < self.len() {
+            Some(DeviceChoice::Output(self.cursor - self.inputs.len()))
+        } else {
+            None
+        }
+    }
+}
+
+// The sample browser overlay: every WAV, FLAC and MP3 under the project dir. Moving the cursor
+// auditions the file under it; enter loads it into the selected slot.
+#[derive(Clone, Debug, Default)]
+pub struct SampleBrowser {
+    pub paths: Vec<PathBuf>,
+    pub names: Vec<String>, // paths relative to the project dir, for display
+    pub cursor: usize,
+}
+
+impl SampleBrowser {
+    pub fn new(paths: Vec<PathBuf>, root: &Path) -> Self {
+        let names = paths.iter()
+            .map(|p| p.strip_prefix(root).unwrap_or(p).to_string_lossy().into_owned())
+            .collect();
+        Self { paths, names, cursor: 0 }
+    }
+
+    pub fn move_cursor(&mut self, delta: i32) {
+        let n = self.paths.len() as i32;
+        if n > 0 {
+            self.cursor = (self.cursor as i32 + delta).rem_euclid(n) as usize;
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Path> {
+        self.paths.get(self.cursor).map(PathBuf::as_path)
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -160,31 +387,121 @@ pub enum LedState {
 
     // Will require a little bit of fanciness from the TUI to implement, because 
     // the blinking likely won't happen on every frame.
-    Blink 
+    Blink,
+
+    // Step lies past the track's loop length, so it never plays. Drawn blank.
+    OutOfRange,
 }
 
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ParamPage {
+    #[default]
     Tone,
+    Env,  // attack / decay
+    Tail, // sustain / release
+    Mix,  // pan / gain
     Filter,
     Trim,
+    Macro, // knobs drive whatever the project assigned them
 }
 
 impl ParamPage {
     pub fn next(self) -> Self {
         match self {
-            ParamPage::Tone => ParamPage::Filter,
+            ParamPage::Tone => ParamPage::Env,
+            ParamPage::Env => ParamPage::Tail,
+            ParamPage::Tail => ParamPage::Mix,
+            ParamPage::Mix => ParamPage::Filter,
             ParamPage::Filter => ParamPage::Trim,
-            ParamPage::Trim => ParamPage::Tone,
+            ParamPage::Trim => ParamPage::Macro,
+            ParamPage::Macro => ParamPage::Tone,
         }
     }
 
-    pub fn knob_labels(self) -> (&'static str, &'static str) {
+    /// Fixed knob assignments. None for the macro page, whose targets live in the project.
+    pub fn targets(self) -> Option<(KnobTarget, KnobTarget)> {
         match self {
-            ParamPage::Tone => ("PITCH", "GAIN"),
-            ParamPage::Filter => ("CUTOFF", "RESO"),
-            ParamPage::Trim => ("START", "LENGTH"),
+            ParamPage::Tone => Some((KnobTarget::Pitch, KnobTarget::Gain)),
+            ParamPage::Env => Some((KnobTarget::Attack, KnobTarget::Decay)),
+            ParamPage::Tail => Some((KnobTarget::Sustain, KnobTarget::Release)),
+            ParamPage::Mix => Some((KnobTarget::Pan, KnobTarget::Gain)),
+            ParamPage::Filter => Some((KnobTarget::Cutoff, KnobTarget::Reso)),
+            ParamPage::Trim => Some((KnobTarget::TrimStart, KnobTarget::TrimLength)),
+            ParamPage::Macro => None,
         }
     }
 }
+
+// Anything a knob can be pointed at
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KnobTarget {
+    Pitch,
+    Gain,
+    Cutoff,
+    Reso,
+    TrimStart,
+    TrimLength,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Pan,
+    Swing,
+    Bpm,
+    Tuning,
+}
+
+impl KnobTarget {
+    pub const ALL: [KnobTarget; 14] = [
+        KnobTarget::Pitch, KnobTarget::Gain, KnobTarget::Cutoff, KnobTarget::Reso,
+        KnobTarget::TrimStart, KnobTarget::TrimLength,
+        KnobTarget::Attack, KnobTarget::Decay, KnobTarget::Sustain, KnobTarget::Release, KnobTarget::Pan,
+        KnobTarget::Swing, KnobTarget::Bpm, KnobTarget::Tuning,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            KnobTarget::Pitch => "PITCH",
+            KnobTarget::Gain => "GAIN",
+            KnobTarget::Cutoff => "CUTOFF",
+            KnobTarget::Reso => "RESO",
+            KnobTarget::TrimStart => "START",
+            KnobTarget::TrimLength => "LENGTH",
+            KnobTarget::Attack => "ATTACK",
+            KnobTarget::Decay => "DECAY",
+            KnobTarget::Sustain => "SUSTAIN",
+            KnobTarget::Release => "RELEASE",
+            KnobTarget::Pan => "PAN",
+            KnobTarget::Swing => "SWING",
+            KnobTarget::Bpm => "BPM",
+            KnobTarget::Tuning => "TUNE",
+        }
+    }
+
+    /// The semantic event a turn of a knob pointed here sends.
+    pub fn event(self, delta: f32) -> InputEvent {
+        match self {
+            KnobTarget::Pitch => InputEvent::AdjustPitch(delta),
+            KnobTarget::Gain => InputEvent::AdjustGain(delta),
+            KnobTarget::Cutoff => InputEvent::AdjustFilterCutoff(delta),
+            KnobTarget::Reso => InputEvent::AdjustFilterResonance(delta),
+            KnobTarget::TrimStart => InputEvent::AdjustTrimStart(delta),
+            KnobTarget::TrimLength => InputEvent::AdjustTrimLength(delta),
+            KnobTarget::Attack => InputEvent::AdjustAttack(delta),
+            KnobTarget::Decay => InputEvent::AdjustDecay(delta),
+            KnobTarget::Sustain => InputEvent::AdjustSustain(delta),
+            KnobTarget::Release => InputEvent::AdjustRelease(delta),
+            KnobTarget::Pan => InputEvent::AdjustPan(delta),
+            KnobTarget::Swing => InputEvent::AdjustSwing(delta),
+            KnobTarget::Bpm => InputEvent::AdjustBpm(delta),
+            KnobTarget::Tuning => InputEvent::AdjustTuning(delta),
+        }
+    }
+
+    pub fn cycle(self, dir: i32) -> Self {
+        let n = Self::ALL.len() as i32;
+        let i = Self::ALL.iter().position(|&t| t == self).unwrap_or(0) as i32;
+        Self::ALL[(i + dir).rem_euclid(n) as usize]
+    }
+}
@@ -1,7 +1,14 @@
 use std::time::Duration;
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use crate::shared::{InputEvent, ParamPage};
 use super::mode::TuiState;
+use crate::osc::OscInput;
+use crate::pipeline::persistence::PROJECT_SLOTS;
+#[cfg(feature = "midi")]
+use crate::midi::MidiInput;
+
+const GEN_DENSITY: f32 = 0.5;
+const GEN_DENSITY_BUSY: f32 = 0.85;
 
 // All modifier buttons are TOGGLES: press once = on, press again = off.
 // Buttons do NOT repeat when held. Knobs DO repeat when held.
@@ -13,6 +20,13 @@ pub fn poll_input(timeout: Duration, ts: &mut TuiState) -> anyhow::Result<Vec<In
     }
 
     if let Event::Key(key) = event::read()? {
+        // ctrl+s would otherwise land on the 's' pad
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(match (key.kind, key.code) {
+                (KeyEventKind::Press, KeyCode::Char('s')) => vec![InputEvent::Save],
+                _ => vec![],
+            });
+        }
         return Ok(match key.kind {
             KeyEventKind::Press => handle_press(key.code, ts),
             KeyEventKind::Repeat => handle_repeat(key.code, ts),
@@ -25,8 +39,19 @@ pub fn poll_input(timeout: Duration, ts: &mut TuiState) -> anyhow::Result<Vec<In
 // ── First press ──────────────────────────────────────────────────
 
 fn handle_press(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
+    if ts.settings_open {
+        return handle_settings_press(code);
+    }
+    if ts.browser_open {
+        return handle_browser_press(code);
+    }
+    if ts.confirm_clear_all {
+        ts.confirm_clear_all = false;
+        return vec![InputEvent::ConfirmClearAll(code == KeyCode::Char('y'))];
+    }
     match code {
         KeyCode::Esc => vec![InputEvent::Quit],
+        KeyCode::Char(' ') if ts.bpm_held => vec![InputEvent::TapTempo],
         KeyCode::Char(' ') => vec![InputEvent::PlayPress],
 
         // 4×4 grid pads
@@ -45,8 +70,14 @@ fn handle_press(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
         }
         KeyCode::Char('h') => {
             ts.pattern_held = !ts.pattern_held;
+            ts.chain_started = false;
+            ts.slot_pick = false;
             if ts.pattern_held { vec![InputEvent::PatternDown] } else { vec![InputEvent::PatternUp] }
         }
+        KeyCode::Char('t') if ts.pattern_held => {
+            ts.slot_pick = true;
+            vec![]
+        }
         KeyCode::Char('t') => {
             // Always send WriteDown — backend toggles write_mode on every WriteDown
             vec![InputEvent::WriteDown]
@@ -63,10 +94,52 @@ fn handle_press(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
             ts.bpm_held = !ts.bpm_held;
             if ts.bpm_held { vec![InputEvent::BpmDown] } else { vec![InputEvent::BpmUp] }
         }
+        KeyCode::Char(';') => {
+            ts.preview_held = !ts.preview_held;
+            if ts.preview_held { vec![InputEvent::PreviewDown] } else { vec![InputEvent::PreviewUp] }
+        }
 
         KeyCode::Char('0') => vec![InputEvent::ClearTrack],
+        KeyCode::Char(')') => {
+            ts.confirm_clear_all = true;
+            vec![InputEvent::ClearAllPatterns]
+        }
+        KeyCode::Char('k') => vec![InputEvent::ToggleHold],
+        KeyCode::Char('\'') if ts.sound_held => vec![InputEvent::ToggleSustainLoop],
+        KeyCode::Char('9') if ts.sound_held => vec![InputEvent::CycleChokeGroup],
+        KeyCode::Char('\'') => vec![InputEvent::ToggleLoopRoll],
         KeyCode::Char('i') => vec![InputEvent::CycleInputDevice],
+        KeyCode::Char('p') if ts.pattern_held => vec![InputEvent::BounceSong],
         KeyCode::Char('p') => vec![InputEvent::BouncePattern],
+        KeyCode::Char('P') => vec![InputEvent::ExportMidi],
+        KeyCode::Char('R') => vec![InputEvent::ToggleMasterCapture],
+        KeyCode::Char('I') => vec![InputEvent::ImportMidi],
+        KeyCode::Char('X') => {
+            let density = if ts.bpm_held { GEN_DENSITY_BUSY } else { GEN_DENSITY };
+            vec![InputEvent::GeneratePattern { density }]
+        }
+        KeyCode::Char('Z') => vec![InputEvent::Undo],
+        KeyCode::Char('Y') => vec![InputEvent::Redo],
+        KeyCode::Char('K') if ts.pattern_held => vec![InputEvent::TogglePatternKit],
+        KeyCode::Char('C') if ts.pattern_held => vec![InputEvent::CopyPattern],
+        KeyCode::Char('V') if ts.pattern_held => vec![InputEvent::PastePattern],
+        KeyCode::Char('o') => vec![InputEvent::ToggleSettings],
+        KeyCode::Char('B') => vec![InputEvent::ToggleBrowser],
+        KeyCode::Char('G') => vec![InputEvent::GrabBar],
+        KeyCode::Char('j') => vec![InputEvent::DumpState],
+        KeyCode::Char('l') => vec![InputEvent::ToggleStutterSync],
+        KeyCode::Char('u') => vec![InputEvent::ToggleDcFilter],
+        KeyCode::Char('M') => vec![InputEvent::ToggleMetronome],
+        KeyCode::Char('N') => vec![InputEvent::ToggleNormalize],
+        KeyCode::Char('L') => vec![InputEvent::ToggleNormalizeLoads],
+        KeyCode::Char('T') => vec![InputEvent::ToggleTrimSilence],
+        KeyCode::Char('O') => vec![InputEvent::ToggleInputMonitor],
+        KeyCode::Char('m') if ts.sound_held => vec![InputEvent::ToggleSoundReverse],
+        KeyCode::Char('/') if ts.sound_held => vec![InputEvent::ToggleSoundCompressor],
+        KeyCode::Char('`') if ts.sound_held => vec![InputEvent::ToggleSoundCue],
+        KeyCode::Char('\\') if ts.sound_held => vec![InputEvent::CycleLengthMode],
+        KeyCode::Char(',') if ts.sound_held => vec![InputEvent::SetDuckSource],
+        KeyCode::Char('.') if ts.sound_held => vec![InputEvent::ToggleDuckTarget],
 
         // knobs (also handled in handle_repeat for auto-repeat)
         KeyCode::Char('[') => resolve_knob_a(-0.05, ts),
@@ -78,9 +151,83 @@ fn handle_press(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
     }
 }
 
+// ── OSC — networked pads/knobs go through the same resolution as the keys ──
+
+pub fn resolve_osc(input: OscInput, ts: &mut TuiState) -> Vec<InputEvent> {
+    if ts.settings_open || ts.browser_open || ts.confirm_clear_all {
+        return vec![];
+    }
+    match input {
+        OscInput::Pad(n) => resolve_grid(n, ts),
+        OscInput::KnobA(delta) => resolve_knob_a(delta, ts),
+        OscInput::KnobB(delta) => resolve_knob_b(delta, ts),
+        OscInput::Play => vec![InputEvent::PlayPress],
+    }
+}
+
+/// Resolve a MIDI message the same way: notes press pads (velocity first, so the
+/// middle layer can scale that one hit), start/stop press play when it would change
+/// anything, and clock tempo goes straight through.
+#[cfg(feature = "midi")]
+pub fn resolve_midi(input: MidiInput, ts: &mut TuiState) -> Vec<InputEvent> {
+    if ts.settings_open || ts.browser_open || ts.confirm_clear_all {
+        return vec![];
+    }
+    match input {
+        MidiInput::Pad { pad, velocity } => {
+            let mut events = vec![InputEvent::PadVelocity(velocity)];
+            events.extend(resolve_grid(pad, ts));
+            events
+        }
+        MidiInput::PadUp { pad } => resolve_grid_release(pad, ts),
+        MidiInput::Start if !ts.playing => vec![InputEvent::PlayPress],
+        MidiInput::Stop if ts.playing => vec![InputEvent::PlayPress],
+        MidiInput::Start | MidiInput::Stop => vec![],
+        MidiInput::Tempo(bpm) => vec![InputEvent::SyncTempo(bpm)],
+    }
+}
+
+// ── Settings overlay — arrows move, enter switches, esc/o closes ──
+
+fn handle_settings_press(code: KeyCode) -> Vec<InputEvent> {
+    match code {
+        KeyCode::Up => vec![InputEvent::SettingsMove(-1)],
+        KeyCode::Down => vec![InputEvent::SettingsMove(1)],
+        KeyCode::Enter => vec![InputEvent::SettingsConfirm],
+        KeyCode::Esc | KeyCode::Char('o') => vec![InputEvent::ToggleSettings],
+        _ => vec![],
+    }
+}
+
+// ── Sample browser — arrows audition, enter loads, esc/B closes ──
+
+fn handle_browser_press(code: KeyCode) -> Vec<InputEvent> {
+    match code {
+        KeyCode::Up => vec![InputEvent::BrowserMove(-1)],
+        KeyCode::Down => vec![InputEvent::BrowserMove(1)],
+        KeyCode::Enter => vec![InputEvent::BrowserConfirm],
+        KeyCode::Esc | KeyCode::Char('B') => vec![InputEvent::ToggleBrowser],
+        _ => vec![],
+    }
+}
+
 // ── Auto-repeat (held key) — only knobs repeat ──────────────────
 
 fn handle_repeat(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
+    if ts.settings_open {
+        return match code {
+            KeyCode::Up => vec![InputEvent::SettingsMove(-1)],
+            KeyCode::Down => vec![InputEvent::SettingsMove(1)],
+            _ => vec![],
+        };
+    }
+    if ts.browser_open {
+        return match code {
+            KeyCode::Up => vec![InputEvent::BrowserMove(-1)],
+            KeyCode::Down => vec![InputEvent::BrowserMove(1)],
+            _ => vec![],
+        };
+    }
     match code {
         KeyCode::Char('[') => resolve_knob_a(-0.05, ts),
         KeyCode::Char(']') => resolve_knob_a(0.05, ts),
@@ -96,6 +243,9 @@ fn handle_release(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
     if let KeyCode::Char(c) = code {
         if is_pad_char(c) {
             ts.held_step = None;
+            if let Some(n) = char_to_pad(c) {
+                return resolve_grid_release(n, ts);
+            }
         }
     }
     vec![]
@@ -104,11 +254,27 @@ fn handle_release(code: KeyCode, ts: &mut TuiState) -> Vec<InputEvent> {
 // ── Grid resolution ──────────────────────────────────────────────
 
 fn resolve_grid(n: u8, ts: &mut TuiState) -> Vec<InputEvent> {
+    if ts.fx_held && ts.sound_held {
+        let fx = if n == 15 { None } else { Some(n + 1) };
+        return vec![InputEvent::SetTrackEffect(fx)];
+    }
+    if ts.pattern_held && ts.slot_pick {
+        ts.slot_pick = false;
+        return if n < PROJECT_SLOTS { vec![InputEvent::SwitchProjectSlot(n)] } else { vec![] };
+    }
+    if ts.pattern_held && ts.sound_held {
+        return vec![InputEvent::SetTrackLength(n + 1)];
+    }
     if ts.sound_held {
         return vec![InputEvent::SelectSound(n)];
     }
     if ts.pattern_held {
         if ts.playing {
+            // first pad queues a fresh chain at the next loop point, the rest extend it
+            if !ts.chain_started {
+                ts.chain_started = true;
+                return vec![InputEvent::QueuePattern(n)];
+            }
             return vec![InputEvent::ChainPattern(n)];
         } else {
             return vec![InputEvent::SelectPattern(n)];
@@ -140,12 +306,50 @@ fn resolve_grid(n: u8, ts: &mut TuiState) -> Vec<InputEvent> {
     vec![InputEvent::TriggerPad(n)]
 }
 
+// letting go only matters to a pad that played its sound
+fn resolve_grid_release(n: u8, ts: &TuiState) -> Vec<InputEvent> {
+    if ts.sound_held || ts.pattern_held || ts.bpm_held || ts.fx_held || ts.record_held {
+        return vec![];
+    }
+    vec![InputEvent::ReleasePad(n)]
+}
+
 // ── Knob resolution ──────────────────────────────────────────────
 
 fn resolve_knob_a(delta: f32, ts: &TuiState) -> Vec<InputEvent> {
+    if ts.record_held && !ts.sound_held {
+        return vec![InputEvent::AdjustRecordThreshold(delta)];
+    }
+    if ts.bpm_held && ts.sound_held {
+        return vec![InputEvent::AdjustTrackSwing(delta)];
+    }
+    if ts.sound_held {
+        return vec![InputEvent::ShiftBank(if delta > 0.0 { 1 } else { -1 })];
+    }
+    // Per-step nudge: holding a step pad in write mode (stopped) with fx held
+    if let Some(step) = ts.held_step
+        && ts.write_mode && !ts.playing && ts.fx_held
+    {
+        return vec![InputEvent::LockStepNudgeAt { step, delta }];
+    }
+    // Per-step ratchet: same step hold, with bpm held
+    if let Some(step) = ts.held_step
+        && ts.write_mode && !ts.playing && ts.bpm_held
+    {
+        return vec![InputEvent::LockStepRatchetAt { step, dir: if delta > 0.0 { 1 } else { -1 } }];
+    }
+    if ts.fx_held && ts.param_page == ParamPage::Macro {
+        return vec![InputEvent::CycleMacroTarget { knob: 0, dir: if delta > 0.0 { 1 } else { -1 } }];
+    }
+    if ts.fx_held {
+        return vec![InputEvent::CycleFxQuantize(if delta > 0.0 { 1 } else { -1 })];
+    }
     if ts.bpm_held {
         return vec![InputEvent::AdjustSwing(delta)];
     }
+    if ts.pattern_held {
+        return vec![InputEvent::AdjustTuning(delta)];
+    }
     // Per-step pitch lock: holding a step pad in write mode (stopped) + knob A
     if let Some(step) = ts.held_step {
         if ts.write_mode && !ts.playing {
@@ -155,14 +359,28 @@ fn resolve_knob_a(delta: f32, ts: &TuiState) -> Vec<InputEvent> {
     if ts.write_mode && ts.playing {
         return vec![InputEvent::PitchLockStep(delta)];
     }
-    match ts.param_page {
-        ParamPage::Tone => vec![InputEvent::AdjustPitch(delta)],
-        ParamPage::Filter => vec![InputEvent::AdjustFilterCutoff(delta)],
-        ParamPage::Trim => vec![InputEvent::AdjustTrimStart(delta)],
-    }
+    vec![ts.knob_targets().0.event(delta)]
 }
 
 fn resolve_knob_b(delta: f32, ts: &TuiState) -> Vec<InputEvent> {
+    if ts.record_held && !ts.sound_held {
+        return vec![InputEvent::AdjustMonitorGain(delta)];
+    }
+    if ts.bpm_held && ts.sound_held {
+        return vec![InputEvent::AdjustVolume(delta)];
+    }
+    if ts.sound_held {
+        return vec![InputEvent::CycleInterpolation];
+    }
+    // Per-step probability: same step hold, with fx held
+    if let Some(step) = ts.held_step
+        && ts.write_mode && !ts.playing && ts.fx_held
+    {
+        return vec![InputEvent::LockStepProbabilityAt { step, delta }];
+    }
+    if ts.fx_held && ts.param_page == ParamPage::Macro {
+        return vec![InputEvent::CycleMacroTarget { knob: 1, dir: if delta > 0.0 { 1 } else { -1 } }];
+    }
     if ts.bpm_held {
         return vec![InputEvent::AdjustBpm(delta)];
     }
@@ -175,11 +393,7 @@ fn resolve_knob_b(delta: f32, ts: &TuiState) -> Vec<InputEvent> {
     if ts.write_mode && ts.playing {
         return vec![InputEvent::GainLockStep(delta)];
     }
-    match ts.param_page {
-        ParamPage::Tone => vec![InputEvent::AdjustGain(delta)],
-        ParamPage::Filter => vec![InputEvent::AdjustFilterResonance(delta)],
-        ParamPage::Trim => vec![InputEvent::AdjustTrimLength(delta)],
-    }
+    vec![ts.knob_targets().1.event(delta)]
 }
 
 // ── Helpers ──────────────────────────────────────────────────────
@@ -1,4 +1,4 @@
-use crate::shared::ParamPage;
+use crate::shared::{KnobTarget, ParamPage};
 
 // state local to tui, mirrors keybinds
 // and resolves them into semantic inputevents
@@ -8,15 +8,25 @@ pub struct TuiState {
     // modifier toggles: press once = on, press again = off
     pub sound_held: bool,
     pub pattern_held: bool,
+    pub chain_started: bool, // a pad was already pressed during this pattern hold (playing)
+    pub slot_pick: bool, // write pressed during this pattern hold: the next pad picks a project slot
     pub record_held: bool,
     pub fx_held: bool,
     pub bpm_held: bool,
+    pub preview_held: bool,
     // synced from DisplayState each frame
     pub write_mode: bool,
     pub playing: bool,
     pub param_page: ParamPage,
+    pub macro_targets: (KnobTarget, KnobTarget),
     // grid pad held in write mode (stopped) for per-step knob editing
     pub held_step: Option<u8>,
+    // device settings overlay is open: arrows/enter drive the overlay instead of the device
+    pub settings_open: bool,
+    // sample browser is open: arrows/enter drive it instead
+    pub browser_open: bool,
+    // clear-all prompt is up: the next key answers it instead of doing its usual thing
+    pub confirm_clear_all: bool,
 }
 
 impl Default for TuiState {
@@ -24,13 +34,27 @@ impl Default for TuiState {
         Self {
             sound_held: false,
             pattern_held: false,
+            chain_started: false,
+            slot_pick: false,
             record_held: false,
             fx_held: false,
             bpm_held: false,
+            preview_held: false,
             write_mode: false,
             playing: false,
             param_page: ParamPage::Tone,
+            macro_targets: (KnobTarget::Cutoff, KnobTarget::Gain),
             held_step: None,
+            settings_open: false,
+            browser_open: false,
+            confirm_clear_all: false,
         }
     }
 }
+
+impl TuiState {
+    /// What knob A and B drive on the current param page.
+    pub fn knob_targets(&self) -> (KnobTarget, KnobTarget) {
+        self.param_page.targets().unwrap_or(self.macro_targets)
+    }
+}
@@ -1,8 +1,8 @@
-use crate::shared::{DisplayState, LedState, RecordingDisplay};
+use crate::shared::{DeviceMenu, DisplayState, LedState, RecordingDisplay, SampleBrowser};
 use ratatui::layout::{Alignment, Layout, Direction, Constraint, Rect};
 use ratatui::style::{Color, Style, Modifier};
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Block, Borders, BorderType, Paragraph};
+use ratatui::widgets::{Block, Borders, BorderType, Clear, Paragraph};
 use ratatui::Frame;
 
 const DIM: Color = Color::Rgb(200, 175, 188);   // muted baby pink
@@ -14,6 +14,10 @@ const LCD_BRIGHT: Color = Color::Rgb(255, 225, 235);
 const LED_MED: Color = Color::Rgb(220, 55, 50);
 const LED_HI: Color = Color::Rgb(240, 50, 50);
 const LED_RED: Color = Color::Rgb(255, 50, 50); // bright red when button is active
+const FAINT: Color = Color::Rgb(90, 80, 85);     // out-of-range pads (past the loop length)
+
+// pad level meter, quietest to full scale
+const METER_GLYPHS: [&str; 8] = ["▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"];
 
 const PAD_LABELS: [&str; 16] = [
     "1", "2", "3", "4",
@@ -22,6 +26,8 @@ const PAD_LABELS: [&str; 16] = [
     "Z", "X", "C", "V",
 ];
 
+const OUT_METER_CELLS: usize = 4; // output meter on the LCD's bottom row, plus its clip light
+
 // 43 width: right column fits + 1 char left offset for pad grid
 const DEVICE_W: u16 = 43;
 const DEVICE_H: u16 = 39;  // compressed: no spacer between LCD and mode row
@@ -108,6 +114,13 @@ pub fn render(frame: &mut Frame, area: Rect, state: &DisplayState, blink_on: boo
     draw_controls_row(frame, rows[2], state);
     draw_pad_area(frame, rows[3], state, blink_on);
 
+    if let Some(menu) = &state.device_menu {
+        draw_device_menu(frame, device_rect, menu);
+    }
+    if let Some(browser) = &state.browser {
+        draw_browser(frame, device_rect, browser);
+    }
+
     draw_footer(frame, footer_area);
 }
 
@@ -135,13 +148,57 @@ fn draw_screen(frame: &mut Frame, area: Rect, state: &DisplayState, blink_on: bo
     let top_border = format!(" ╔{}╗", "═".repeat(iw));
     let bot_border = format!(" ╚{}╝", "═".repeat(iw));
 
-    let play = if state.playing { "▶" } else { "■" };
+    let play = match state.position {
+        Some((bar, beat)) => format!("▶{}.{}", bar, beat),
+        None if state.playing => String::from("▶"),
+        None => String::from("■"),
+    };
     let write = if state.write_mode { "●W" } else { "○W" };
+    let hold = match (state.hold, state.sound_reversed) {
+        (true, true) => " ∞◀",
+        (true, false) => " ∞",
+        (false, true) => " ◀",
+        (false, false) => "",
+    };
     let page = format!("{:?}", state.param_page);
+    // only call out the loop length when the track is shorter than a full bar
+    let len = if (state.active_length as usize) < state.leds.len() {
+        format!(" /{}", state.active_length)
+    } else {
+        String::new()
+    };
+
+    // queued pattern blinks next to the text until the loop point picks it up
+    let queued = match state.queued_pattern {
+        Some(q) if blink_on => format!(" >{}", q + 1),
+        Some(q) => " ".repeat(format!(" >{}", q + 1).len()),
+        None => String::new(),
+    };
+
+    // what's playing, when that isn't obviously the pattern on the grid
+    let chain = match (state.playing_pattern, state.chain_position) {
+        (Some(p), Some((pos, len))) => format!(" P{}:{}/{}", p + 1, pos, len),
+        (Some(p), None) if p != state.selected_pattern => format!(" P{}", p + 1),
+        _ => String::new(),
+    };
+
+    let tuning = if (state.tuning_hz - 440.0).abs() > 0.05 {
+        format!(" A{:.0}", state.tuning_hz)
+    } else {
+        String::new()
+    };
+
+    let swing = if state.swing_pct > 0 {
+        format!(" S{}%", state.swing_pct)
+    } else {
+        String::new()
+    };
+
+    let dirty = if state.dirty { " •" } else { "" };
 
     let l1 = format!(
-        " {} {} {}  {:.0}bpm",
-        state.display_text, play, write, state.bpm
+        " {}{}{} {} {}{}{}  {:.0}bpm{}{}{}",
+        state.display_text, queued, chain, play, write, hold, len, state.bpm, swing, tuning, dirty
     );
     let l2 = format!(
         " {:<5} {}:{:.2} {}:{:.2}",
@@ -149,15 +206,57 @@ fn draw_screen(frame: &mut Frame, area: Rect, state: &DisplayState, blink_on: bo
         state.knob_a_label, state.knob_a_value,
         state.knob_b_label, state.knob_b_value,
     );
-    let dev_name: String = state.input_device.chars().take(iw.saturating_sub(6)).collect();
-    let l3 = format!(" IN: {}", dev_name);
-
+    // engine load sits at the right edge; the device name gets what's left
+    let jam = state.jam_secs.map_or(String::new(), |s| format!("JAM {}:{:02} ", s as u32 / 60, s as u32 % 60));
+    let load = format!("{}v{} {}%", jam, state.voice_count, state.cpu_load.min(999));
+    // output meter after that, sqrt like the input one; the light stays red a second after a clip
+    let out_lit = ((state.peak.sqrt() * OUT_METER_CELLS as f32).round() as usize).min(OUT_METER_CELLS);
+    let out_meter = format!(" {}{}", "▮".repeat(out_lit), "▯".repeat(OUT_METER_CELLS - out_lit));
+    let (clip, clip_style) = if state.clipping { ("●", Style::default().fg(LED_RED)) } else { ("○", sl) };
+    let meter_w = OUT_METER_CELLS + 2;
+    let status = match (state.input_ok, state.monitor_held) {
+        (false, _) => " (failed)",
+        (true, true) => " (MON HELD)",
+        (true, false) => "",
+    };
+    let dev_name: String = state.input_device
+        .chars()
+        .take(iw.saturating_sub(7 + load.len() + meter_w + status.len()))
+        .collect();
+    let l3_left = format!(" IN: {}{}", dev_name, status);
+    let gap = iw.saturating_sub(l3_left.chars().count() + load.len() + meter_w + 1).max(1);
     let pad_str = |s: &str| -> String {
         let n = s.chars().count();
         let p = if iw > n { iw - n } else { 0 };
         format!(" ║{}{}║", s, " ".repeat(p))
     };
 
+    let l3 = match state.recording {
+        // while recording, the bottom row becomes an input meter (and capture time)
+        RecordingDisplay::Armed | RecordingDisplay::Capturing => {
+            let label = if state.recording == RecordingDisplay::Armed { "ARM" } else { "REC" };
+            let time = if state.recording == RecordingDisplay::Capturing {
+                format!(" {:.1}s", state.capture_secs)
+            } else {
+                String::new()
+            };
+            let width = iw.saturating_sub(6 + time.len());
+            // sqrt so quiet mics still visibly move the meter
+            let lit = ((state.input_peak.sqrt() * width as f32).round() as usize).min(width);
+            let meter = format!(" {} {}{}{}", label, "▮".repeat(lit), "▯".repeat(width - lit), time);
+            Line::from(Span::styled(pad_str(&meter), sl))
+        }
+        RecordingDisplay::Idle => {
+            let text = format!("{}{}{}{}", l3_left, " ".repeat(gap), load, out_meter);
+            let pad = iw.saturating_sub(text.chars().count() + 1);
+            Line::from(vec![
+                Span::styled(format!(" ║{}", text), sl),
+                Span::styled(clip, clip_style),
+                Span::styled(format!("{}║", " ".repeat(pad)), sl),
+            ])
+        }
+    };
+
     let mut lines: Vec<Line> = Vec::new();
     lines.push(Line::from(Span::styled(top_border, sb)));
 
@@ -182,7 +281,7 @@ fn draw_screen(frame: &mut Frame, area: Rect, state: &DisplayState, blink_on: bo
     // Text rows
     lines.push(Line::from(Span::styled(pad_str(&l1), sh)));
     lines.push(Line::from(Span::styled(pad_str(&l2), sl)));
-    lines.push(Line::from(Span::styled(pad_str(&l3), sl)));
+    lines.push(l3);
 
     lines.push(Line::from(Span::styled(bot_border, sb)));
 
@@ -326,6 +425,19 @@ fn draw_pad(frame: &mut Frame, area: Rect, idx: usize, state: &DisplayState, bli
 
     let led = state.leds[idx];
     let label = PAD_LABELS[idx];
+
+    // Past the loop boundary: leave the key blank so the wrap point is obvious
+    if led == LedState::OutOfRange {
+        let lines = vec![
+            Line::from(""),
+            Line::from(Span::styled("  ·  ", Style::default().fg(FAINT))),
+            Line::from(""),
+            Line::from(Span::styled(label, Style::default().fg(FAINT))),
+        ];
+        frame.render_widget(Paragraph::new(lines).alignment(Alignment::Center), area);
+        return;
+    }
+
     let (led_sym, led_c) = led_symbol(led, blink_on);
     let pad_c = pad_color(led, blink_on);
     let lbl_c = if led == LedState::Off { TEXT } else { ACCENT };
@@ -339,18 +451,36 @@ fn draw_pad(frame: &mut Frame, area: Rect, idx: usize, state: &DisplayState, bli
             Span::styled(" :", Style::default().fg(pad_c)),
         ]),
         Line::from(Span::styled("':::'", Style::default().fg(pad_c))),
-        Line::from(Span::styled(label, Style::default().fg(lbl_c).add_modifier(Modifier::BOLD))),
+        label_line(label, lbl_c, state.pad_peaks[idx]),
     ];
 
     frame.render_widget(Paragraph::new(lines).alignment(Alignment::Center), area);
 }
 
+// keyname, with the sound's output level beside it while it's making noise
+fn label_line(label: &str, color: Color, peak: f32) -> Line<'static> {
+    let label = Span::styled(label.to_string(), Style::default().fg(color).add_modifier(Modifier::BOLD));
+    if peak < 0.01 {
+        return Line::from(label);
+    }
+    // sqrt like the input meter, so quiet sounds still register
+    let level = ((peak.sqrt() * METER_GLYPHS.len() as f32) as usize).min(METER_GLYPHS.len() - 1);
+    let meter_c = if peak >= 1.0 { LED_RED } else { MID };
+    Line::from(vec![
+        Span::raw("  "),
+        label,
+        Span::raw(" "),
+        Span::styled(METER_GLYPHS[level], Style::default().fg(meter_c)),
+    ])
+}
+
 fn led_symbol(led: LedState, blink_on: bool) -> (&'static str, Color) {
     match led {
         LedState::Off => ("○", DIM),
         LedState::OnMedium => ("●", LED_RED),
         LedState::OnHigh => ("◉", LED_RED),
         LedState::Blink => if blink_on { ("●", LED_RED) } else { ("○", DIM) },
+        LedState::OutOfRange => (" ", FAINT),
     }
 }
 
@@ -360,6 +490,7 @@ fn pad_color(led: LedState, blink_on: bool) -> Color {
         LedState::OnMedium => LED_MED,
         LedState::OnHigh => LED_HI,
         LedState::Blink => if blink_on { LED_HI } else { DIM },
+        LedState::OutOfRange => FAINT,
     }
 }
 
@@ -407,10 +538,110 @@ fn draw_side_btn(frame: &mut Frame, area: Rect, sym: &str, label: &str, key: &st
     frame.render_widget(Paragraph::new(lines).alignment(Alignment::Center), area);
 }
 
+fn draw_device_menu(frame: &mut Frame, area: Rect, menu: &DeviceMenu) {
+    // inset a little so the device border stays visible around the overlay
+    let rect = Rect {
+        x: area.x + 2,
+        y: area.y + 3,
+        width: area.width.saturating_sub(4),
+        height: area.height.saturating_sub(6),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(MID))
+        .title(Span::styled(" devices ", Style::default().fg(TEXT)));
+    let inner = block.inner(rect);
+    frame.render_widget(Clear, rect);
+    frame.render_widget(block, rect);
+
+    let name_w = (inner.width as usize).saturating_sub(4);
+    let entry = |idx: usize, name: &str, current: bool| -> Line {
+        let cursor = if idx == menu.cursor { ">" } else { " " };
+        let mark = if current { "●" } else { " " };
+        let name: String = name.chars().take(name_w).collect();
+        let style = if idx == menu.cursor {
+            Style::default().fg(ACCENT).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(LCD_FG)
+        };
+        Line::from(vec![
+            Span::styled(format!("{}{} ", cursor, mark), Style::default().fg(LED_RED)),
+            Span::styled(name, style),
+        ])
+    };
+
+    let mut lines: Vec<Line> = Vec::new();
+    lines.push(Line::from(Span::styled("INPUT", Style::default().fg(DIM))));
+    for (i, name) in menu.inputs.iter().enumerate() {
+        lines.push(entry(i, name, i == menu.current_input));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled("OUTPUT", Style::default().fg(DIM))));
+    for (i, name) in menu.outputs.iter().enumerate() {
+        lines.push(entry(menu.inputs.len() + i, name, i == menu.current_output));
+    }
+    lines.push(Line::from(""));
+    if let Some(err) = &menu.error {
+        lines.push(Line::from(Span::styled(err.clone(), Style::default().fg(LED_RED))));
+    }
+    lines.push(Line::from(Span::styled("↑/↓ move  enter select  o close", Style::default().fg(DIM))));
+
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+fn draw_browser(frame: &mut Frame, area: Rect, browser: &SampleBrowser) {
+    let rect = Rect {
+        x: area.x + 2,
+        y: area.y + 3,
+        width: area.width.saturating_sub(4),
+        height: area.height.saturating_sub(6),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(MID))
+        .title(Span::styled(" samples ", Style::default().fg(TEXT)));
+    let inner = block.inner(rect);
+    frame.render_widget(Clear, rect);
+    frame.render_widget(block, rect);
+
+    let mut lines: Vec<Line> = Vec::new();
+    if browser.names.is_empty() {
+        lines.push(Line::from(Span::styled("no .wav files here", Style::default().fg(DIM))));
+    }
+    // keep the cursor in view: scroll so it sits a third of the way down
+    let rows = (inner.height as usize).saturating_sub(2);
+    let first = browser.cursor.saturating_sub(rows / 3).min(browser.names.len().saturating_sub(rows));
+    let name_w = (inner.width as usize).saturating_sub(2);
+    for (i, name) in browser.names.iter().enumerate().skip(first).take(rows) {
+        // long paths lose their start; the file name is the useful end
+        let skip = name.chars().count().saturating_sub(name_w);
+        let name: String = name.chars().skip(skip).collect();
+        let (cursor, style) = if i == browser.cursor {
+            (">", Style::default().fg(ACCENT).add_modifier(Modifier::BOLD))
+        } else {
+            (" ", Style::default().fg(LCD_FG))
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{} ", cursor), Style::default().fg(LED_RED)),
+            Span::styled(name, style),
+        ]));
+    }
+    while lines.len() < rows + 1 {
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(Span::styled("↑/↓ listen  enter load  B close", Style::default().fg(DIM))));
+
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
 fn draw_footer(frame: &mut Frame, area: Rect) {
     let line = Line::from(vec![
         Span::styled("(i)", Style::default().fg(DIM)),
         Span::styled("switch input | ", Style::default().fg(MID)),
+        Span::styled("(o)", Style::default().fg(DIM)),
+        Span::styled("devices | ", Style::default().fg(MID)),
         Span::styled("(yy)", Style::default().fg(DIM)),
         Span::styled("switch controls | ", Style::default().fg(MID)),
         Span::styled("(p)", Style::default().fg(DIM)),